@@ -0,0 +1,469 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use memmap2::{Mmap, MmapOptions};
+use redb::{Builder, Database, DatabaseError, StorageBackend};
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 8] = b"SOLARIBN";
+/// Bumped whenever the bundle container format changes.
+pub const BUNDLE_VERSION: u32 = 1;
+// The magic, the version, four bytes of padding, then the offset and length of the table of
+// contents, all little-endian.
+const HEADER_LEN: u64 = 32;
+/// Sections start on page boundaries so each one can be memory-mapped on its own.
+pub const SECTION_ALIGNMENT: u64 = 4096;
+// The granularity of the copy-on-write overlay databases in a bundle are opened with.
+const BLOCK_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Section {
+    name: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TableOfContents {
+    sections: Vec<Section>,
+}
+
+fn invalid(path: &Path, message: impl Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {message}"))
+}
+
+// Sections are unpacked into a single directory, so their names have to be plain file names.
+fn check_name(name: &str) -> io::Result<()> {
+    if Path::new(name)
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        != Some(name)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name:?} isn't a valid section name"),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `path` is a bundle file, as opposed to a directory of files or something else.
+pub fn is_bundle(path: &Path) -> bool {
+    let mut magic = [0u8; 8];
+    path.is_file()
+        && File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+        && &magic == MAGIC
+}
+
+/// A single file holding a timetable and transfer graph, or any other set of files, as sections
+/// listed in a table of contents.
+pub struct Bundle {
+    path: PathBuf,
+    file: File,
+    sections: BTreeMap<String, Section>,
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> io::Result<Bundle> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut header = [0u8; HEADER_LEN as usize];
+        if file.read_exact(&mut header).is_err() || &header[..8] != MAGIC {
+            return Err(invalid(path, "not a Solari bundle"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != BUNDLE_VERSION {
+            return Err(invalid(
+                path,
+                format!(
+                    "bundle version {version}, but this version of Solari reads version {BUNDLE_VERSION}"
+                ),
+            ));
+        }
+        let toc_offset = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        let toc_len = u64::from_le_bytes(header[24..32].try_into().unwrap());
+        if toc_offset
+            .checked_add(toc_len)
+            .is_none_or(|end| end > file_len)
+        {
+            return Err(invalid(path, "the table of contents is truncated"));
+        }
+        file.seek(SeekFrom::Start(toc_offset))?;
+        let mut toc = vec![0u8; toc_len as usize];
+        file.read_exact(&mut toc)?;
+        let toc: TableOfContents =
+            serde_json::from_slice(&toc).map_err(|err| invalid(path, err))?;
+
+        let mut sections = BTreeMap::new();
+        for section in toc.sections {
+            check_name(&section.name)?;
+            if section
+                .offset
+                .checked_add(section.len)
+                .is_none_or(|end| end > file_len)
+            {
+                return Err(invalid(
+                    path,
+                    format!("section {} runs past the end of the file", section.name),
+                ));
+            }
+            sections.insert(section.name.clone(), section);
+        }
+        Ok(Bundle {
+            path: path.to_path_buf(),
+            file,
+            sections,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn section_names(&self) -> impl Iterator<Item = &str> {
+        self.sections.keys().map(String::as_str)
+    }
+
+    fn section(&self, name: &str) -> io::Result<&Section> {
+        self.sections.get(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{:?} has no section {name}", self.path),
+            )
+        })
+    }
+
+    pub fn len(&self, name: &str) -> io::Result<u64> {
+        Ok(self.section(name)?.len)
+    }
+
+    /// Memory-map a section. The bundle must not be modified while it's mapped, replace it with a
+    /// rename instead.
+    pub fn map(&self, name: &str) -> io::Result<Mmap> {
+        let section = self.section(name)?;
+        unsafe {
+            MmapOptions::new()
+                .offset(section.offset)
+                .len(section.len as usize)
+                .map(&self.file)
+        }
+    }
+
+    pub fn reader(&self, name: &str) -> io::Result<io::Take<File>> {
+        let section = self.section(name)?;
+        // Opened again rather than cloned, since clones share a cursor.
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(section.offset))?;
+        Ok(file.take(section.len))
+    }
+
+    /// Open a redb database stored as a section. Writes, including the ones redb makes when
+    /// opening a database, are kept in memory and never reach the bundle.
+    pub fn database(&self, name: &str) -> Result<Database, DatabaseError> {
+        let backend = OverlayBackend::new(self.map(name)?);
+        Builder::new().create_with_backend(backend)
+    }
+
+    /// Write every section to a file of the same name in `dir`.
+    pub fn unpack(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        for name in self.sections.keys() {
+            let mut out = BufWriter::new(File::create(dir.join(name))?);
+            io::copy(&mut self.reader(name)?, &mut out)?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_bundle(out: &mut File, files: &[(String, PathBuf)]) -> io::Result<()> {
+    let mut writer = BufWriter::new(&mut *out);
+    writer.write_all(&[0u8; HEADER_LEN as usize])?;
+    let mut position = HEADER_LEN;
+    let mut sections: Vec<Section> = vec![];
+    for (name, source) in files {
+        check_name(name)?;
+        if sections.iter().any(|section| &section.name == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("more than one file is named {name}"),
+            ));
+        }
+        let offset = position.next_multiple_of(SECTION_ALIGNMENT);
+        writer.write_all(&vec![0u8; (offset - position) as usize])?;
+        let mut source_file = File::open(source)
+            .map_err(|err| io::Error::new(err.kind(), format!("{source:?}: {err}")))?;
+        let len = io::copy(&mut source_file, &mut writer)?;
+        sections.push(Section {
+            name: name.clone(),
+            offset,
+            len,
+        });
+        position = offset + len;
+    }
+    let toc = serde_json::to_vec(&TableOfContents { sections })?;
+    writer.write_all(&toc)?;
+    writer.flush()?;
+    drop(writer);
+
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+    header.extend_from_slice(&[0u8; 4]);
+    header.extend_from_slice(&position.to_le_bytes());
+    header.extend_from_slice(&(toc.len() as u64).to_le_bytes());
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(&header)?;
+    out.sync_all()
+}
+
+/// Write `files`, keyed by their section names, to a bundle at `path`. The bundle is written
+/// alongside `path` and renamed into place, so nothing ever opens a partial bundle.
+pub fn pack(path: &Path, files: &[(String, PathBuf)]) -> io::Result<()> {
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+    let mut out = File::create(&partial_path)?;
+    if let Err(err) = write_bundle(&mut out, files) {
+        let _ = fs::remove_file(&partial_path);
+        return Err(err);
+    }
+    fs::rename(&partial_path, path)
+}
+
+/// Where a timetable's or transfer graph's files are read from: either a directory holding them,
+/// or a bundle holding them as sections of the same names.
+pub enum Source {
+    Dir(PathBuf),
+    Bundle(Bundle),
+}
+
+impl Source {
+    pub fn open(path: &Path) -> io::Result<Source> {
+        if is_bundle(path) {
+            Ok(Source::Bundle(Bundle::open(path)?))
+        } else {
+            Ok(Source::Dir(path.to_path_buf()))
+        }
+    }
+
+    pub fn len(&self, name: &str) -> io::Result<u64> {
+        match self {
+            Source::Dir(dir) => Ok(fs::metadata(dir.join(name))?.len()),
+            Source::Bundle(bundle) => bundle.len(name),
+        }
+    }
+
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Source::Dir(dir) => fs::read(dir.join(name)),
+            Source::Bundle(bundle) => {
+                let mut bytes = vec![];
+                bundle.reader(name)?.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn reader(&self, name: &str) -> io::Result<Box<dyn Read>> {
+        match self {
+            Source::Dir(dir) => Ok(Box::new(File::open(dir.join(name))?)),
+            Source::Bundle(bundle) => Ok(Box::new(bundle.reader(name)?)),
+        }
+    }
+
+    pub fn map(&self, name: &str) -> io::Result<Mmap> {
+        match self {
+            Source::Dir(dir) => {
+                let file = File::open(dir.join(name))?;
+                unsafe { MmapOptions::new().map(&file) }
+            }
+            Source::Bundle(bundle) => bundle.map(name),
+        }
+    }
+
+    pub fn database(&self, name: &str) -> Result<Database, DatabaseError> {
+        match self {
+            Source::Dir(dir) => Database::open(dir.join(name)),
+            Source::Bundle(bundle) => bundle.database(name),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Overlay {
+    len: u64,
+    // Bytes of the base at or past this offset have been truncated away and read as zeros.
+    base_len: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl Overlay {
+    fn copy_base(&self, base: &[u8], position: u64, dst: &mut [u8]) {
+        let available = self.base_len.saturating_sub(position).min(dst.len() as u64) as usize;
+        if available > 0 {
+            dst[..available].copy_from_slice(&base[position as usize..][..available]);
+        }
+    }
+}
+
+// Reads from a mapped section, with writes kept in memory in copy-on-write blocks.
+#[derive(Debug)]
+struct OverlayBackend {
+    base: Mmap,
+    overlay: Mutex<Overlay>,
+}
+
+impl OverlayBackend {
+    fn new(base: Mmap) -> OverlayBackend {
+        let len = base.len() as u64;
+        OverlayBackend {
+            base,
+            overlay: Mutex::new(Overlay {
+                len,
+                base_len: len,
+                blocks: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl StorageBackend for OverlayBackend {
+    fn len(&self) -> Result<u64, io::Error> {
+        Ok(self.overlay.lock().unwrap().len)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, io::Error> {
+        let overlay = self.overlay.lock().unwrap();
+        let end = offset + len as u64;
+        if end > overlay.len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        let mut data = vec![0u8; len];
+        let mut position = offset;
+        while position < end {
+            let index = position / BLOCK_SIZE;
+            let within = (position % BLOCK_SIZE) as usize;
+            let n = (BLOCK_SIZE as usize - within).min((end - position) as usize);
+            let dst = &mut data[(position - offset) as usize..][..n];
+            match overlay.blocks.get(&index) {
+                Some(block) => dst.copy_from_slice(&block[within..within + n]),
+                None => overlay.copy_base(&self.base, position, dst),
+            }
+            position += n as u64;
+        }
+        Ok(data)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), io::Error> {
+        let mut overlay = self.overlay.lock().unwrap();
+        if len < overlay.len {
+            // Zero everything past the new end, in case the storage grows again.
+            overlay.base_len = overlay.base_len.min(len);
+            overlay.blocks.retain(|index, _| index * BLOCK_SIZE < len);
+            if let Some(block) = overlay.blocks.get_mut(&(len / BLOCK_SIZE)) {
+                block[(len % BLOCK_SIZE) as usize..].fill(0);
+            }
+        }
+        overlay.len = len;
+        Ok(())
+    }
+
+    fn sync_data(&self, _eventual: bool) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> Result<(), io::Error> {
+        let mut overlay = self.overlay.lock().unwrap();
+        let end = offset + data.len() as u64;
+        overlay.len = overlay.len.max(end);
+        let mut position = offset;
+        while position < end {
+            let index = position / BLOCK_SIZE;
+            let within = (position % BLOCK_SIZE) as usize;
+            let n = (BLOCK_SIZE as usize - within).min((end - position) as usize);
+            if !overlay.blocks.contains_key(&index) {
+                let mut block = vec![0u8; BLOCK_SIZE as usize];
+                overlay.copy_base(&self.base, index * BLOCK_SIZE, &mut block);
+                overlay.blocks.insert(index, block);
+            }
+            let block = overlay.blocks.get_mut(&index).unwrap();
+            block[within..within + n].copy_from_slice(&data[(position - offset) as usize..][..n]);
+            position += n as u64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use redb::TableDefinition;
+
+    use super::*;
+
+    const TABLE: TableDefinition<u64, &str> = TableDefinition::new("test");
+
+    #[test]
+    fn packs_maps_and_unpacks_sections() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        fs::write(dir.join("data"), &data).unwrap();
+        fs::write(dir.join("empty"), []).unwrap();
+        {
+            let db = Database::create(dir.join("metadata.db")).unwrap();
+            let write = db.begin_write().unwrap();
+            write.open_table(TABLE).unwrap().insert(7, "seven").unwrap();
+            write.commit().unwrap();
+        }
+        let files: Vec<(String, PathBuf)> = ["data", "empty", "metadata.db"]
+            .iter()
+            .map(|name| (name.to_string(), dir.join(name)))
+            .collect();
+        let bundle_path = dir.join("bundle");
+        pack(&bundle_path, &files).unwrap();
+        let packed = fs::read(&bundle_path).unwrap();
+
+        assert!(is_bundle(&bundle_path));
+        assert!(!is_bundle(&dir));
+        let Source::Bundle(bundle) = Source::open(&bundle_path).unwrap() else {
+            panic!("Not opened as a bundle");
+        };
+        let mapped = bundle.map("data").unwrap();
+        assert_eq!(&mapped[..], &data[..]);
+        assert_eq!(mapped.as_ptr() as usize % 8, 0);
+        assert_eq!(bundle.len("empty").unwrap(), 0);
+        assert_eq!(
+            bundle.map("missing").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // Writes to a database in the bundle stay in memory.
+        let db = bundle.database("metadata.db").unwrap();
+        let read = db.begin_read().unwrap();
+        let table = read.open_table(TABLE).unwrap();
+        assert_eq!(table.get(7).unwrap().unwrap().value(), "seven");
+        let write = db.begin_write().unwrap();
+        write.open_table(TABLE).unwrap().insert(8, "eight").unwrap();
+        write.commit().unwrap();
+        drop(db);
+        assert_eq!(fs::read(&bundle_path).unwrap(), packed);
+
+        let unpacked = dir.join("unpacked");
+        bundle.unpack(&unpacked).unwrap();
+        for (name, path) in &files {
+            assert_eq!(
+                fs::read(unpacked.join(name)).unwrap(),
+                fs::read(path).unwrap()
+            );
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use clap::Parser;
-use solari::timetable::build::{concat_timetables, timetable_from_feeds};
+use solari::timetable::{
+    build::{concat_timetables, rebuild_feed, timetable_from_feeds},
+    calendar::StorageMode,
+};
 use std::{fs, path::PathBuf};
 
 #[derive(Parser)]
@@ -14,6 +17,15 @@ pub struct BuildArgs {
     pub num_threads: usize,
     #[arg(long, default_value_t = false)]
     pub concat_only: bool,
+    /// "expanded" stores a copy of each trip per service day, "pattern" stores each trip once
+    /// along with the days it runs on.
+    #[arg(long, default_value = "expanded")]
+    pub storage: StorageMode,
+    /// Rebuild only the feed at --gtfs-path within the timetable already at --base-path, reusing
+    /// transfers for stops the update doesn't affect. The existing timetable's build options are
+    /// reused, so --storage is ignored.
+    #[arg(long, default_value_t = false)]
+    pub incremental: bool,
 }
 
 pub async fn run_build_timetable(args: BuildArgs) -> Result<(), anyhow::Error> {
@@ -30,6 +42,9 @@ pub async fn run_build_timetable(args: BuildArgs) -> Result<(), anyhow::Error> {
         let _timetable = concat_timetables(&paths, &args.base_path.into(), &args.valhalla_tiles)
             .await
             .unwrap();
+    } else if args.incremental {
+        let _timetable =
+            rebuild_feed(&args.gtfs_path, &args.base_path, &args.valhalla_tiles).await?;
     } else if fs::metadata(&args.gtfs_path).unwrap().is_dir() {
         let paths: Vec<PathBuf> = fs::read_dir(&args.gtfs_path)
             .unwrap()
@@ -42,6 +57,7 @@ pub async fn run_build_timetable(args: BuildArgs) -> Result<(), anyhow::Error> {
             &args.valhalla_tiles,
             None,
             None,
+            args.storage,
         )
         .await
         .unwrap();
@@ -52,6 +68,7 @@ pub async fn run_build_timetable(args: BuildArgs) -> Result<(), anyhow::Error> {
             &args.valhalla_tiles,
             None,
             None,
+            args.storage,
         )
         .await
         .unwrap();
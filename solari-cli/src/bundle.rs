@@ -0,0 +1,51 @@
+use clap::Parser;
+use solari::{
+    route::Router,
+    timetable::{bundle::pack_bundle, mmap::MmapTimetable},
+};
+use solari_bundle::Bundle;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Parser)]
+pub struct PackArgs {
+    #[arg(long)]
+    pub base_path: PathBuf,
+    /// The directory holding the transfer graph. Defaults to --base-path.
+    #[arg(long)]
+    pub valhalla_tile_path: Option<PathBuf>,
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+#[derive(Parser)]
+pub struct UnpackArgs {
+    #[arg(long)]
+    pub bundle_path: PathBuf,
+    /// The directory to write the timetable and transfer graph files to.
+    #[arg(long)]
+    pub output: PathBuf,
+}
+
+pub async fn run_pack_bundle(args: PackArgs) -> Result<(), anyhow::Error> {
+    let transfer_graph_path = args
+        .valhalla_tile_path
+        .unwrap_or_else(|| args.base_path.clone());
+    pack_bundle(&args.base_path, &transfer_graph_path, &args.output)?;
+    // Make sure the bundle opens before anyone points a server at it.
+    let _router = Router::new(MmapTimetable::open(&args.output)?, args.output.clone())?;
+    info!("Packed {:?} into {:?}", args.base_path, args.output);
+    Ok(())
+}
+
+pub async fn run_unpack_bundle(args: UnpackArgs) -> Result<(), anyhow::Error> {
+    let bundle = Bundle::open(&args.bundle_path)?;
+    bundle.unpack(&args.output)?;
+    info!(
+        "Unpacked {} files from {:?} into {:?}",
+        bundle.section_names().count(),
+        args.bundle_path,
+        args.output
+    );
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use anyhow::bail;
+use clap::Parser;
+use solari::timetable::{check::check_timetable, mmap::MmapTimetable};
+use std::path::PathBuf;
+use tracing::{error, info};
+
+#[derive(Parser)]
+pub struct CheckArgs {
+    #[arg(long)]
+    pub base_path: PathBuf,
+}
+
+pub async fn run_check_timetable(args: CheckArgs) -> Result<(), anyhow::Error> {
+    let timetable = MmapTimetable::open(&args.base_path)?;
+    let report = check_timetable(&timetable);
+    for (invariant, example) in &report.examples {
+        error!("{}: {}", invariant, example);
+    }
+    for (invariant, count) in &report.violations {
+        error!("{} violations of {}", count, invariant);
+    }
+    if !report.is_ok() {
+        bail!(
+            "{:?} violates {} invariants",
+            args.base_path,
+            report.violation_count()
+        );
+    }
+    info!("{:?} passed every check", args.base_path);
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use clap::Parser;
+use solari::timetable::{convert::convert_legacy_timetable, mmap::MmapTimetable};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+pub struct ConvertArgs {
+    /// A timetable built with format version 1.
+    #[arg(long)]
+    pub old_path: PathBuf,
+    #[arg(long)]
+    pub new_path: PathBuf,
+}
+
+pub async fn run_convert_timetable(args: ConvertArgs) -> Result<(), anyhow::Error> {
+    convert_legacy_timetable(&args.old_path, &args.new_path)?;
+    // Make sure the result opens before anyone points a server at it.
+    let _timetable = MmapTimetable::open(&args.new_path)?;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use clap::Parser;
+use solari::timetable::{
+    ServiceWindow,
+    diff::{SetDiff, diff_timetables},
+    mmap::MmapTimetable,
+};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+pub struct DiffArgs {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    /// Report stop pairs whose transfer time changed by at least this much.
+    #[arg(long, default_value_t = 120)]
+    pub min_transfer_change_seconds: u32,
+    /// How many added or removed IDs, and changed transfers, to list.
+    #[arg(long, default_value_t = 10)]
+    pub sample_size: usize,
+    /// Print the full diff as JSON instead of a summary.
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+fn print_set_diff(kind: &str, diff: &SetDiff, sample_size: usize) {
+    for (change, ids) in [("added", &diff.added), ("removed", &diff.removed)] {
+        if ids.is_empty() {
+            continue;
+        }
+        let sample: Vec<&str> = ids.iter().take(sample_size).map(String::as_str).collect();
+        let more = if ids.len() > sample_size { ", ..." } else { "" };
+        println!(
+            "  {} {} {}: {}{}",
+            ids.len(),
+            kind,
+            change,
+            sample.join(", "),
+            more
+        );
+    }
+}
+
+pub async fn run_diff_timetables(args: DiffArgs) -> Result<(), anyhow::Error> {
+    let old = MmapTimetable::open(&args.old_path)?;
+    let new = MmapTimetable::open(&args.new_path)?;
+    let diff = diff_timetables(
+        &old,
+        &new,
+        args.min_transfer_change_seconds,
+        args.sample_size,
+    )?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    let mut unchanged = 0usize;
+    for feed in &diff.feeds {
+        if feed.is_unchanged() {
+            unchanged += 1;
+            continue;
+        }
+        println!("{}:", feed.feed);
+        print_set_diff("stops", &feed.stops, args.sample_size);
+        print_set_diff("routes", &feed.routes, args.sample_size);
+        print_set_diff("trips", &feed.trips, args.sample_size);
+        if feed.service_window_changed() {
+            let window = |window: Option<_>| match window {
+                Some(ServiceWindow {
+                    start_date,
+                    num_days,
+                    ..
+                }) => format!("{} for {} days", start_date, num_days),
+                None => "none".to_string(),
+            };
+            println!(
+                "  service window changed from {} to {}",
+                window(feed.old_service_window),
+                window(feed.new_service_window)
+            );
+        }
+    }
+    println!("{} feeds unchanged", unchanged);
+    println!(
+        "Transfers: {} -> {} ({:+})",
+        diff.old_transfers,
+        diff.new_transfers,
+        diff.new_transfers as i64 - diff.old_transfers as i64
+    );
+    println!(
+        "{} stop pairs' transfer times changed by at least {} seconds",
+        diff.changed_transfers, args.min_transfer_change_seconds
+    );
+    for change in &diff.transfer_changes {
+        println!(
+            "  {}:{} -> {}:{}: {}s -> {}s",
+            change.feed_from,
+            change.stop_from,
+            change.feed_to,
+            change.stop_to,
+            change.old_seconds,
+            change.new_seconds
+        );
+    }
+    Ok(())
+}
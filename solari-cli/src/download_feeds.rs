@@ -1,7 +1,7 @@
 use clap::Parser;
 use reqwest::Client;
 use solari_dmfr::DistributedMobilityFeedRegistry;
-use std::{ffi::OsString, path::PathBuf, str::FromStr, time::Duration};
+use std::{path::PathBuf, time::Duration};
 use tokio::{fs as tokio_fs, spawn};
 use tracing::{debug, error, info, warn};
 
@@ -21,16 +21,12 @@ pub async fn download_dmfr(path: PathBuf, zip_dir: PathBuf) -> Result<(), anyhow
     debug!("Enumerating feeds from {:?}", &path);
     let dmfr: DistributedMobilityFeedRegistry =
         serde_json::from_str(&tokio_fs::read_to_string(&path).await?)?;
-    for (feed_idx, feed) in dmfr.feeds.iter().enumerate() {
+    for feed in &dmfr.feeds {
         if let Some(url) = &feed.urls.static_current {
             debug!("Downloading feed from: {:?}", url);
             let response = client.get(url.as_str()).send().await?;
-            let mut filename = path
-                .file_name()
-                .expect("GTFS feed not a file")
-                .to_os_string();
-            filename.push(OsString::from_str(&format!(".{feed_idx}.zip"))?);
-            let zip_path = zip_dir.join(filename);
+            // The file stem becomes the feed ID in stable stop, route and trip IDs.
+            let zip_path = zip_dir.join(format!("{}.zip", feed_file_stem(&feed.id)));
             tokio_fs::write(&zip_path, response.bytes().await?).await?;
             info!("Wrote zip file to {:?}", zip_path)
         }
@@ -38,6 +34,18 @@ pub async fn download_dmfr(path: PathBuf, zip_dir: PathBuf) -> Result<(), anyhow
     Ok(())
 }
 
+// DMFR feed IDs are mostly onestop IDs like "f-c23-metrokingcounty", but nothing stops one from
+// containing a path separator, or the colon that separates the feed ID from the GTFS ID.
+fn feed_file_stem(feed_id: &str) -> String {
+    feed_id
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c => c,
+        })
+        .collect()
+}
+
 pub async fn run_download_feeds(args: DownloadFeedsArgs) -> Result<(), anyhow::Error> {
     let mut read = tokio_fs::read_dir(&args.dmfr_dir).await?;
     let mut handles = Vec::new();
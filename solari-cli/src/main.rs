@@ -1,7 +1,15 @@
 mod build_timetable;
+mod bundle;
+mod check_timetable;
+mod convert_timetable;
+mod diff_timetables;
 mod download_feeds;
 
 use crate::build_timetable::{BuildArgs, run_build_timetable};
+use crate::bundle::{PackArgs, UnpackArgs, run_pack_bundle, run_unpack_bundle};
+use crate::check_timetable::{CheckArgs, run_check_timetable};
+use crate::convert_timetable::{ConvertArgs, run_convert_timetable};
+use crate::diff_timetables::{DiffArgs, run_diff_timetables};
 use crate::download_feeds::{DownloadFeedsArgs, run_download_feeds};
 use clap::{Parser, Subcommand};
 use tracing_subscriber::FmtSubscriber;
@@ -18,6 +26,11 @@ struct Cli {
 enum Commands {
     Build(BuildArgs),
     DownloadFeeds(DownloadFeedsArgs),
+    Convert(ConvertArgs),
+    Check(CheckArgs),
+    Diff(DiffArgs),
+    Pack(PackArgs),
+    Unpack(UnpackArgs),
 }
 
 #[tokio::main(worker_threads = 64)]
@@ -29,5 +42,10 @@ async fn main() -> Result<(), anyhow::Error> {
     match cli.command {
         Commands::Build(args) => run_build_timetable(args).await,
         Commands::DownloadFeeds(args) => run_download_feeds(args).await,
+        Commands::Convert(args) => run_convert_timetable(args).await,
+        Commands::Check(args) => run_check_timetable(args).await,
+        Commands::Diff(args) => run_diff_timetables(args).await,
+        Commands::Pack(args) => run_pack_bundle(args).await,
+        Commands::Unpack(args) => run_unpack_bundle(args).await,
     }
 }
@@ -2,7 +2,7 @@ use std::{path::PathBuf, sync::Arc};
 
 use clap::Parser;
 use solari_spatial::SphereIndexVec;
-use solari_transfers::{TransferGraph, fast_paths::FastGraphVec};
+use solari_transfers::{TRANSFER_METADATA_FILE, TransferGraph, fast_paths::FastGraphVec};
 use tracing_subscriber::FmtSubscriber;
 
 #[derive(Parser)]
@@ -19,7 +19,7 @@ fn main() -> Result<(), anyhow::Error> {
 
     let args = Args::parse();
     let database = Arc::new(redb::Database::create(
-        args.output.join("graph_metadata.db"),
+        args.output.join(TRANSFER_METADATA_FILE),
     )?);
     let transfer_graph =
         TransferGraph::<FastGraphVec, SphereIndexVec<usize>>::new(&args.valhalla_tiles, database)?;
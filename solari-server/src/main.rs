@@ -1,41 +1,254 @@
-use std::path::PathBuf;
+mod reload;
+
+use std::{convert::Infallible, path::PathBuf, sync::Arc, time::Duration};
 
 use clap::Parser;
-use rocket::{State, serde::json::Json};
+use rocket::{
+    Request, State,
+    fairing::AdHoc,
+    http::{Accept, ContentType, Status},
+    request::{self, FromRequest},
+    serde::json::Json,
+};
 use s2::latlng::LatLng;
 use solari::{
-    api::{request::SolariRequest, response::SolariResponse},
-    route::Router,
-    timetable::{Time, mmap::MmapTimetable},
+    api::{
+        geojson::SolariGeoJsonResponse,
+        page::PageCursor,
+        request::{RequestLocation, SolariRequest},
+        response::SolariResponse,
+        vehicle::{SolariVehiclePosition, SolariVehiclesResponse},
+    },
+    realtime::decode_feed_message,
+    timetable::Time,
 };
+use tracing::{debug, error, warn};
 use tracing_subscriber::FmtSubscriber;
 
+use crate::reload::{LiveRouter, ServerRouter};
+
 #[macro_use]
 extern crate rocket;
 
-#[post("/v1/plan", data = "<request>")]
+#[derive(Responder)]
+enum PlanResponse {
+    Json(Json<SolariResponse>),
+    GeoJson(Json<SolariGeoJsonResponse>, ContentType),
+}
+
+struct AcceptLanguage<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptLanguage<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(AcceptLanguage(request.headers().get_one("Accept-Language")))
+    }
+}
+
+// Where to route from or to. None if the request references a stop that doesn't exist or doesn't
+// give a location at all.
+fn request_location(router: &ServerRouter, location: &RequestLocation) -> Option<LatLng> {
+    location.resolve(|global_stop_id| router.stop_location(global_stop_id))
+}
+
+#[post("/v1/plan?<format>&<lang>", data = "<request>")]
 async fn plan(
     request: Json<SolariRequest>,
-    router: &State<Router<'_, MmapTimetable<'_>>>,
-) -> Json<SolariResponse> {
-    let from = LatLng::from_degrees(request.0.from.lat, request.0.from.lon);
-    let to = LatLng::from_degrees(request.0.to.lat, request.0.to.lon);
+    format: Option<&str>,
+    lang: Option<&str>,
+    accept: Option<&Accept>,
+    accept_language: AcceptLanguage<'_>,
+    live_router: &State<Arc<LiveRouter>>,
+) -> Result<PlanResponse, Status> {
+    let geojson = format == Some("geojson")
+        || accept.is_some_and(|accept| {
+            accept
+                .media_types()
+                .any(|media_type| media_type.sub() == "geo+json")
+        });
+
+    let router = live_router.current();
+    let from = request_location(&router, &request.0.from).ok_or(Status::BadRequest)?;
+    let to = request_location(&router, &request.0.to).ok_or(Status::BadRequest)?;
 
     let max_transfers = usize::min(5, request.0.max_transfers.0);
 
-    return Json(
-        router
-            .route(
-                Time::from_epoch_seconds(request.0.start_at.unix_timestamp() as u32),
-                from,
-                to,
-                Some(1500f64),
-                Some(1000),
-                Some(max_transfers),
-                Some(2),
-            )
-            .await,
-    );
+    let cursor = request
+        .0
+        .page_cursor
+        .as_deref()
+        .map(PageCursor::decode)
+        .transpose()
+        .map_err(|_| Status::BadRequest)?;
+
+    let mut languages: Vec<String> = lang.iter().map(|lang| lang.to_string()).collect();
+    languages.extend(request.0.languages(accept_language.0));
+
+    let response = router
+        .route_page(
+            cursor.as_ref(),
+            Time::from_epoch_seconds(request.0.start_at.unix_timestamp() as u32),
+            from,
+            to,
+            Some(1500f64),
+            Some(1000),
+            Some(max_transfers),
+            Some(2),
+            &languages,
+        )
+        .await;
+
+    if geojson {
+        Ok(PlanResponse::GeoJson(
+            Json(SolariGeoJsonResponse::from(&response)),
+            ContentType::new("application", "geo+json"),
+        ))
+    } else {
+        Ok(PlanResponse::Json(Json(response)))
+    }
+}
+
+#[get("/v1/vehicles?<bbox>")]
+async fn vehicles(
+    bbox: Option<&str>,
+    live_router: &State<Arc<LiveRouter>>,
+) -> Result<Json<SolariVehiclesResponse>, Status> {
+    let store = live_router.current().vehicle_positions();
+    let vehicles = if let Some(bbox) = bbox {
+        // Same order as a GeoJSON bbox: min lon, min lat, max lon, max lat.
+        let coords = bbox
+            .split(',')
+            .map(|coord| coord.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Status::BadRequest)?;
+        if coords.len() != 4 {
+            return Err(Status::BadRequest);
+        }
+        store.in_bbox(coords[1], coords[0], coords[3], coords[2])
+    } else {
+        store.in_bbox(-90f64, -180f64, 90f64, 180f64)
+    };
+    Ok(Json(SolariVehiclesResponse { vehicles }))
+}
+
+/// `global_trip_id` is `<feed_id>:<gtfs trip_id>`, since GTFS trip IDs are only unique within a
+/// feed.
+#[get("/v1/trips/<global_trip_id>/vehicle")]
+async fn trip_vehicle(
+    global_trip_id: &str,
+    live_router: &State<Arc<LiveRouter>>,
+) -> Option<Json<SolariVehiclePosition>> {
+    live_router
+        .current()
+        .vehicle_positions()
+        .for_global_trip_id(global_trip_id)
+        .map(Json)
+}
+
+struct AdminToken(Option<String>);
+
+struct BearerToken<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken<'r> {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(BearerToken(
+            request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|value| value.strip_prefix("Bearer ")),
+        ))
+    }
+}
+
+// Compare hashes of the tokens, which blake3 does in constant time, so that neither the time taken
+// nor the token lengths give away how much of a guess was right.
+fn token_matches(token: Option<&str>, admin_token: &str) -> bool {
+    token
+        .is_some_and(|token| blake3::hash(token.as_bytes()) == blake3::hash(admin_token.as_bytes()))
+}
+
+/// Swap in the timetable the server's paths currently point to, once it has opened and validated.
+#[post("/admin/reload")]
+async fn admin_reload(
+    token: BearerToken<'_>,
+    admin_token: &State<AdminToken>,
+    live_router: &State<Arc<LiveRouter>>,
+) -> Result<String, (Status, String)> {
+    match &admin_token.0 {
+        None => return Err((Status::NotFound, String::new())),
+        Some(admin_token) if !token_matches(token.0, admin_token) => {
+            return Err((Status::Forbidden, String::new()));
+        }
+        Some(_) => {}
+    }
+    match live_router.reload().await {
+        Ok(paths) => Ok(format!(
+            "Serving timetable {:?} with transfer graph {:?}\n",
+            paths.timetable, paths.transfer_graph
+        )),
+        Err(err) => {
+            error!("Failed to reload timetable: {}", err);
+            Err((
+                Status::UnprocessableEntity,
+                format!("Failed to reload timetable: {}\n", err),
+            ))
+        }
+    }
+}
+
+/// A GTFS-RT VehiclePositions feed and the ID of the GTFS feed its trips belong to.
+#[derive(Clone, Debug)]
+struct VehiclePositionsSource {
+    feed_id: String,
+    url: String,
+}
+
+fn parse_vehicle_positions_source(arg: &str) -> Result<VehiclePositionsSource, String> {
+    match arg.split_once('=') {
+        Some((feed_id, url)) if !feed_id.is_empty() && !url.is_empty() => {
+            Ok(VehiclePositionsSource {
+                feed_id: feed_id.to_string(),
+                url: url.to_string(),
+            })
+        }
+        _ => Err(format!("expected FEED_ID=URL, got {arg:?}")),
+    }
+}
+
+async fn poll_vehicle_positions(
+    live_router: Arc<LiveRouter>,
+    source: VehiclePositionsSource,
+    interval: Duration,
+) {
+    let client = reqwest::Client::new();
+    let VehiclePositionsSource { feed_id, url } = source;
+    loop {
+        let response = match client.get(&url).send().await {
+            Ok(response) => response.bytes().await,
+            Err(err) => Err(err),
+        };
+        match response {
+            Ok(bytes) => match decode_feed_message(&bytes) {
+                Ok(feed) => {
+                    debug!("Fetched {} entities from {}", feed.entity.len(), url);
+                    live_router.current().update_vehicle_positions(
+                        &url,
+                        &feed_id,
+                        &feed,
+                        Time::now(),
+                    );
+                }
+                Err(err) => warn!("Failed to decode vehicle positions from {}: {}", url, err),
+            },
+            Err(err) => warn!("Failed to fetch vehicle positions from {}: {}", url, err),
+        }
+        tokio::time::sleep(interval).await;
+    }
 }
 
 #[derive(Parser)]
@@ -46,6 +259,23 @@ struct ServeArgs {
     valhalla_tile_path: Option<PathBuf>,
     #[arg(short, long)]
     port: Option<u16>,
+    /// GTFS-RT VehiclePositions feed as `FEED_ID=URL`, where FEED_ID is the ID of the GTFS feed
+    /// whose trips it reports on. May be specified more than once.
+    #[arg(long, value_parser = parse_vehicle_positions_source)]
+    vehicle_positions_url: Vec<VehiclePositionsSource>,
+    #[arg(long, default_value_t = 15)]
+    vehicle_positions_interval_seconds: u64,
+    /// Reload when --base-path or --valhalla-tile-path resolve somewhere new, checking this often.
+    /// Point --base-path at a symlink such as `current` and repoint it after each build.
+    #[arg(long)]
+    watch_interval_seconds: Option<u64>,
+    /// Enables POST /admin/reload for requests bearing this token. The server also reloads on
+    /// SIGHUP.
+    #[arg(long)]
+    admin_token: Option<String>,
+    /// Check the content hash of every timetable file before serving it.
+    #[arg(long, default_value_t = false)]
+    verify_hashes: bool,
 }
 
 #[launch]
@@ -54,14 +284,40 @@ fn rocket() -> _ {
         .expect("setting tracing default failed");
 
     let args = ServeArgs::parse();
-    let router = Router::new(
-        MmapTimetable::open(&args.base_path).expect("Failed to open timetable"),
-        args.valhalla_tile_path.unwrap_or(args.base_path),
-    )
-    .expect("Failed to build router");
+    let live_router = Arc::new(
+        LiveRouter::open(args.base_path, args.valhalla_tile_path, args.verify_hashes)
+            .expect("Failed to build router"),
+    );
+
+    let poll_router = live_router.clone();
+    let vehicle_positions_sources = args.vehicle_positions_url;
+    let interval = Duration::from_secs(args.vehicle_positions_interval_seconds);
+    let reload_router = live_router.clone();
+    let watch_interval = args.watch_interval_seconds.map(Duration::from_secs);
 
     rocket::build()
-        .manage(router)
+        .manage(live_router)
+        .manage(AdminToken(args.admin_token))
+        .attach(AdHoc::on_liftoff("Vehicle positions", move |_| {
+            Box::pin(async move {
+                for source in vehicle_positions_sources {
+                    tokio::spawn(poll_vehicle_positions(
+                        poll_router.clone(),
+                        source,
+                        interval,
+                    ));
+                }
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Timetable reloads", move |_| {
+            Box::pin(async move {
+                #[cfg(unix)]
+                tokio::spawn(reload_router.clone().reload_on_hangup());
+                if let Some(watch_interval) = watch_interval {
+                    tokio::spawn(reload_router.watch(watch_interval));
+                }
+            })
+        }))
         .configure(rocket::Config::figment().merge(("port", args.port.unwrap_or(8000))))
-        .mount("/", routes![plan])
+        .mount("/", routes![plan, vehicles, trip_vehicle, admin_reload])
 }
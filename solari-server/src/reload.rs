@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use solari::{route::Router, timetable::mmap::MmapTimetable};
+use tracing::{error, info};
+
+pub type ServerRouter = Router<'static, MmapTimetable<'static>>;
+
+/// The files a router was opened from, with symlinks resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedPaths {
+    pub timetable: PathBuf,
+    pub transfer_graph: PathBuf,
+}
+
+/// The router requests are served from, which can be swapped for one opened from a newer
+/// timetable. Requests hold on to the router they started with, so a swap never interrupts a
+/// query and the old timetable is unmapped once the last query using it finishes.
+pub struct LiveRouter {
+    // Either may be a symlink, such as a `current` link pointing at the latest build.
+    base_path: PathBuf,
+    transfer_graph_path: Option<PathBuf>,
+    verify_hashes: bool,
+    current: RwLock<(Arc<ServerRouter>, LoadedPaths)>,
+    reloading: tokio::sync::Mutex<()>,
+}
+
+fn resolve(
+    base_path: &Path,
+    transfer_graph_path: Option<&Path>,
+) -> Result<LoadedPaths, anyhow::Error> {
+    let timetable = fs::canonicalize(base_path)?;
+    let transfer_graph = match transfer_graph_path {
+        Some(path) => fs::canonicalize(path)?,
+        None => timetable.clone(),
+    };
+    Ok(LoadedPaths {
+        timetable,
+        transfer_graph,
+    })
+}
+
+fn load(paths: &LoadedPaths, verify_hashes: bool) -> Result<ServerRouter, anyhow::Error> {
+    let timetable = MmapTimetable::open(&paths.timetable)?;
+    if verify_hashes {
+        info!("Verifying timetable contents.");
+        timetable.manifest().verify_hashes(&paths.timetable)?;
+    }
+    Router::new(timetable, paths.transfer_graph.clone())
+}
+
+impl LiveRouter {
+    pub fn open(
+        base_path: PathBuf,
+        transfer_graph_path: Option<PathBuf>,
+        verify_hashes: bool,
+    ) -> Result<LiveRouter, anyhow::Error> {
+        let paths = resolve(&base_path, transfer_graph_path.as_deref())?;
+        let router = load(&paths, verify_hashes)?;
+        info!("Serving timetable {:?}", paths.timetable);
+        Ok(LiveRouter {
+            base_path,
+            transfer_graph_path,
+            verify_hashes,
+            current: RwLock::new((Arc::new(router), paths)),
+            reloading: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn current(&self) -> Arc<ServerRouter> {
+        self.current.read().unwrap().0.clone()
+    }
+
+    pub fn loaded_paths(&self) -> LoadedPaths {
+        self.current.read().unwrap().1.clone()
+    }
+
+    /// Open the timetable and transfer graph the configured paths currently point to, and serve
+    /// new requests from them once they've opened and validated. On failure the current router
+    /// keeps serving. Vehicle positions start out empty on the new router until the next poll.
+    pub async fn reload(&self) -> Result<LoadedPaths, anyhow::Error> {
+        let _guard = self.reloading.lock().await;
+        let paths = resolve(&self.base_path, self.transfer_graph_path.as_deref())?;
+        info!("Reloading timetable from {:?}", paths.timetable);
+        let verify_hashes = self.verify_hashes;
+        let load_paths = paths.clone();
+        let router =
+            tokio::task::spawn_blocking(move || load(&load_paths, verify_hashes)).await??;
+        let previous = {
+            let mut current = self.current.write().unwrap();
+            std::mem::replace(&mut *current, (Arc::new(router), paths.clone()))
+        };
+        info!(
+            "Now serving timetable {:?}, previously {:?}",
+            paths.timetable, previous.1.timetable
+        );
+        Ok(paths)
+    }
+
+    /// Reload whenever the configured paths resolve to somewhere other than what's being served,
+    /// e.g. when a `current` symlink is repointed at a new build.
+    pub async fn watch(self: Arc<Self>, interval: Duration) {
+        // Don't retry a target that failed to load until it changes again.
+        let mut failed: Option<LoadedPaths> = None;
+        loop {
+            tokio::time::sleep(interval).await;
+            let paths = match resolve(&self.base_path, self.transfer_graph_path.as_deref()) {
+                Ok(paths) => paths,
+                Err(err) => {
+                    error!("Failed to resolve {:?}: {}", self.base_path, err);
+                    continue;
+                }
+            };
+            if paths == self.loaded_paths() || failed.as_ref() == Some(&paths) {
+                continue;
+            }
+            match self.reload().await {
+                Ok(_) => failed = None,
+                Err(err) => {
+                    error!(
+                        "Failed to reload timetable from {:?}: {}",
+                        paths.timetable, err
+                    );
+                    failed = Some(paths);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub async fn reload_on_hangup(self: Arc<Self>) {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                error!("Failed to listen for SIGHUP: {}", err);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP");
+            if let Err(err) = self.reload().await {
+                error!("Failed to reload timetable: {}", err);
+            }
+        }
+    }
+}
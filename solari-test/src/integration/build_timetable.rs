@@ -1,7 +1,7 @@
 use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use serde_json;
-use solari::timetable::mmap::MmapTimetable;
+use solari::timetable::{calendar::StorageMode, mmap::MmapTimetable};
 use std::fs;
 use std::path::Path;
 
@@ -45,6 +45,7 @@ pub(crate) async fn build_timetable<'a>(
         &valhalla_path,
         Some(params.start_date),
         Some(params.num_days),
+        StorageMode::Expanded,
     )
     .await
 }
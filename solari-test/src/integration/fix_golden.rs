@@ -58,6 +58,7 @@ pub async fn fix_golden_test_cases(goldens_dir: PathBuf) -> Result<()> {
                             Some(1000),
                             Some(6),
                             Some(4),
+                            &[],
                         )
                         .await;
 
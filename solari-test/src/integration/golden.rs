@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 use time::UtcDateTime;
 
@@ -8,5 +10,11 @@ pub(crate) struct Golden {
     pub from_location: LatLng,
     pub to_location: LatLng,
     pub start_time: UtcDateTime,
+    /// Directory of GTFS-RT snapshots to replay, relative to the cases directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realtime_snapshot: Option<PathBuf>,
+    /// ID of the GTFS feed whose trips the realtime snapshot reports on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realtime_feed_id: Option<String>,
     pub route: Option<SolariResponse>,
 }
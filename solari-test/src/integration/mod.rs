@@ -17,7 +17,10 @@ use tracing::{debug, error, info};
 use tempdir::TempDir;
 
 use crate::integration::golden::Golden;
+use solari::api::SolariLeg;
 use solari::api::response::SolariResponse;
+use solari::api::vehicle::SolariVehiclePosition;
+use solari::realtime::replay::SnapshotReplay;
 use solari::route::Router;
 use solari::timetable::Time;
 use solari::timetable::mmap::MmapTimetable;
@@ -33,6 +36,7 @@ fn s2_latlng_to_api_latlng(latlng: &LatLng) -> solari::api::LatLng {
         lat: latlng.lat.deg(),
         lon: latlng.lng.deg(),
         stop: None,
+        global_stop_id: None,
     }
 }
 
@@ -56,6 +60,33 @@ fn print_golden_diff(old: &SolariResponse, new: &SolariResponse) {
     }
 }
 
+/// Load the golden's realtime snapshot, if any, as of its start time. Goldens without one are
+/// routed without realtime data.
+fn apply_realtime_snapshot<'a>(
+    router: &'a Router<'a, MmapTimetable<'a>>,
+    golden: &Golden,
+    golden_filename: &Path,
+) -> anyhow::Result<()> {
+    let replay = if let Some(snapshot) = &golden.realtime_snapshot {
+        if golden.realtime_feed_id.is_none() {
+            bail!(
+                "Golden {:?} has a realtime snapshot but no realtime_feed_id.",
+                golden_filename
+            );
+        }
+        let cases_dir = golden_filename.parent().unwrap_or(Path::new("."));
+        SnapshotReplay::load(&cases_dir.join(snapshot))?
+    } else {
+        SnapshotReplay::default()
+    };
+    router.replay_vehicle_positions(
+        &replay,
+        golden.realtime_feed_id.as_deref().unwrap_or_default(),
+        Time::from_epoch_seconds(golden.start_time.unix_timestamp() as u32),
+    );
+    Ok(())
+}
+
 async fn test_golden<'a>(
     router: &Router<'a, MmapTimetable<'a>>,
     golden: &Golden,
@@ -65,6 +96,8 @@ async fn test_golden<'a>(
         bail!("Golden {:?} is missing a route.", golden_filename);
     }
 
+    apply_realtime_snapshot(router, golden, golden_filename)?;
+
     let from = LatLng::from_degrees(golden.from_location.lat, golden.from_location.lon);
     let to = LatLng::from_degrees(golden.to_location.lat, golden.to_location.lon);
 
@@ -79,6 +112,7 @@ async fn test_golden<'a>(
             Some(1000),
             Some(6),
             Some(4),
+            &[],
         )
         .await;
 
@@ -86,9 +120,33 @@ async fn test_golden<'a>(
         print_golden_diff(golden.route.as_ref().unwrap(), &route);
         bail!(format!("Golden {:?} failed diff", golden_filename))
     }
+    // Itineraries compare equal regardless of the vehicles on their legs, so check those separately
+    // for goldens that replay realtime data.
+    if golden.realtime_snapshot.is_some()
+        && leg_vehicles(golden.route.as_ref().unwrap()) != leg_vehicles(&route)
+    {
+        print_golden_diff(golden.route.as_ref().unwrap(), &route);
+        bail!(format!(
+            "Golden {:?} failed diff of realtime vehicles",
+            golden_filename
+        ))
+    }
     Ok(())
 }
 
+/// The vehicle attached to each transit leg of each itinerary, in order.
+fn leg_vehicles(response: &SolariResponse) -> Vec<Option<&SolariVehiclePosition>> {
+    response
+        .itineraries
+        .iter()
+        .flat_map(|itinerary| itinerary.legs.iter())
+        .filter_map(|leg| match leg {
+            SolariLeg::Transit { vehicle, .. } => Some(vehicle.as_ref()),
+            SolariLeg::Transfer { .. } => None,
+        })
+        .collect()
+}
+
 pub async fn run_test_suite(goldens_dir: PathBuf) -> anyhow::Result<()> {
     let test_dir = TempDir::new("solari-golden")?;
 
@@ -198,6 +256,7 @@ pub async fn expand_test_suite(goldens_dir: PathBuf) -> anyhow::Result<()> {
                 Some(1000),
                 Some(4),
                 Some(2),
+                &[],
             )
             .await;
 
@@ -206,6 +265,8 @@ pub async fn expand_test_suite(goldens_dir: PathBuf) -> anyhow::Result<()> {
             from_location: s2_latlng_to_api_latlng(&from_location.unwrap()),
             to_location: s2_latlng_to_api_latlng(&to_location.unwrap()),
             start_time,
+            realtime_snapshot: None,
+            realtime_feed_id: None,
             route: Some(route),
         };
 
@@ -1,15 +1,16 @@
+pub mod maneuver;
 pub mod valinor;
 pub use fast_paths;
-use redb::{Database, ReadableTable, TableDefinition, WriteTransaction};
+use redb::{Database, ReadableTable, TableDefinition, TableError, WriteTransaction};
 
 use std::{
     collections::HashMap,
-    fs::File,
     path::PathBuf,
     pin::Pin,
     sync::{Arc, RwLock},
 };
 
+use crate::maneuver::{EdgeAttributes, Maneuver, build_maneuvers};
 use crate::valinor::edge_export::enumerate_edges;
 use anyhow::{Ok, bail};
 use fast_paths::{
@@ -18,14 +19,20 @@ use fast_paths::{
 };
 use geo::{Coord, Geodesic, Length, LineString};
 use log::{error, info};
-use memmap2::MmapOptions;
+use solari_bundle::Source;
 use solari_spatial::{IndexedPoint, SphereIndex, SphereIndexMmap, SphereIndexVec};
 use valhalla_graphtile::{Access, GraphId};
 
+pub const TRANSFER_GRAPH_FILE: &str = "transfer_graph.bin";
+pub const TRANSFER_NODE_INDEX_FILE: &str = "transfer_node_index.bin";
+pub const TRANSFER_METADATA_FILE: &str = "graph_metadata.db";
+
 const EDGE_SHAPE_TABLE: TableDefinition<(u64, u64), &[u8]> =
     TableDefinition::new("valhalla_edge_shapes");
 const EDGE_LENGTH_TABLE: TableDefinition<(u64, u64), f64> =
     TableDefinition::new("valhalla_edge_lengths");
+const EDGE_ATTRIBUTES_TABLE: TableDefinition<(u64, u64), &[u8]> =
+    TableDefinition::new("valhalla_edge_attributes");
 
 pub struct TransferGraph<G: FastGraph, I: SphereIndex<usize>> {
     node_index: I,
@@ -69,12 +76,18 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
                     continue;
                 }
                 let length_meters = edge.geometry().length::<Geodesic>();
+                let attributes = EdgeAttributes::new(
+                    edge.names().to_vec(),
+                    edge.directed_edge().edge_use(),
+                    edge.geometry(),
+                );
                 if let Err(err) = Self::push_edge(
                     &txn,
                     start_node_id as u64,
                     end_node_id as u64,
                     length_meters,
                     edge.geometry(),
+                    &attributes,
                 ) {
                     error!("Failed to insert edge into database: {:?}", err);
                 };
@@ -120,9 +133,9 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
     }
 
     pub fn save_to_dir(&self, dir: PathBuf) -> Result<(), anyhow::Error> {
-        self.graph.save_static(dir.join("transfer_graph.bin"))?;
+        self.graph.save_static(dir.join(TRANSFER_GRAPH_FILE))?;
         self.node_index
-            .write_to_file(dir.join("transfer_node_index.bin"))?;
+            .write_to_file(dir.join(TRANSFER_NODE_INDEX_FILE))?;
         Ok(())
     }
 
@@ -130,12 +143,11 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
         dir: PathBuf,
         database: Arc<Database>,
     ) -> Result<TransferGraph<FastGraphStatic<'a>, SphereIndexMmap<'a, usize>>, anyhow::Error> {
-        let graph_file = File::open(dir.join("transfer_graph.bin"))?;
-        let graph_mmap = unsafe { MmapOptions::new().map(&graph_file)? };
-        let graph = FastGraphStatic::assemble(Pin::new(graph_mmap))?;
+        // `dir` may also be a bundle with a section for each file.
+        let source = Source::open(&dir)?;
+        let graph = FastGraphStatic::assemble(Pin::new(source.map(TRANSFER_GRAPH_FILE)?))?;
 
-        let index_file = File::open(dir.join("transfer_node_index.bin"))?;
-        let index_mmap = unsafe { MmapOptions::new().map(&index_file)? };
+        let index_mmap = source.map(TRANSFER_NODE_INDEX_FILE)?;
         let node_index: SphereIndexMmap<'_, usize> =
             SphereIndexMmap::assemble(Pin::new(index_mmap))?;
 
@@ -160,7 +172,15 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
         {
             let txn = self.database.begin_read()?;
             let shapes = txn.open_table(EDGE_SHAPE_TABLE)?;
+            // Graphs built before edge attributes were recorded don't have this table, so they get
+            // no maneuvers.
+            let attributes = match txn.open_table(EDGE_ATTRIBUTES_TABLE) {
+                Result::Ok(table) => Some(table),
+                Err(TableError::TableDoesNotExist(_)) => None,
+                Err(err) => return Err(err.into()),
+            };
             let mut path_shape: Vec<Coord<f64>> = Vec::new();
+            let mut path_edges = Vec::new();
             for pair in path.get_nodes().windows(2) {
                 let from = pair[0] as u64;
                 let to = pair[1] as u64;
@@ -171,11 +191,21 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
                     .to_vec();
                 let shape_string = String::from_utf8(shape_bytes)?;
                 let shape_linestring = polyline::decode_polyline(&shape_string, 5)?;
+                let edge_attributes = if let Some(attributes) = &attributes {
+                    attributes
+                        .get(&(from, to))?
+                        .map(|bytes| rmp_serde::from_slice::<EdgeAttributes>(bytes.value()))
+                        .transpose()?
+                } else {
+                    None
+                };
+                path_edges.push((edge_attributes, shape_linestring.length::<Geodesic>()));
                 path_shape.extend(shape_linestring.0);
             }
             return Ok(TransferPath {
                 length_mm: path.get_weight() as u64,
                 shape: polyline::encode_coordinates(path_shape, 5)?,
+                maneuvers: build_maneuvers(&path_edges),
             });
         } else {
             bail!("No route")
@@ -221,6 +251,7 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
         to: u64,
         length: f64,
         shape: &LineString,
+        attributes: &EdgeAttributes,
     ) -> Result<bool, anyhow::Error> {
         let key = (from, to);
         let lengths = txn.open_table(EDGE_LENGTH_TABLE)?;
@@ -239,6 +270,8 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
         let polyline = polyline::encode_coordinates(shape.0.clone(), 5)?;
         let mut shapes = txn.open_table(EDGE_SHAPE_TABLE)?;
         shapes.insert(&key, polyline.as_bytes())?;
+        let mut edge_attributes = txn.open_table(EDGE_ATTRIBUTES_TABLE)?;
+        edge_attributes.insert(&key, rmp_serde::to_vec(attributes)?.as_slice())?;
         Ok(true)
     }
 
@@ -261,6 +294,7 @@ impl<G: FastGraph, I: SphereIndex<usize>> TransferGraph<G, I> {
 pub struct TransferPath {
     pub length_mm: u64,
     pub shape: String,
+    pub maneuvers: Vec<Maneuver>,
 }
 
 pub struct TransferGraphSearcher<G: FastGraph, I: SphereIndex<usize>> {
@@ -0,0 +1,255 @@
+use geo::{Bearing, Haversine, LineString, Point};
+use serde::{Deserialize, Serialize};
+use valhalla_graphtile::RoadUse;
+
+// Turns sharper than this start a new maneuver even if the street name doesn't change.
+const MIN_TURN_DEGREES: f32 = 45.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeUse {
+    Road,
+    Sidewalk,
+    Footway,
+    Crossing,
+    Steps,
+    Elevator,
+    Escalator,
+    Other,
+}
+
+impl From<RoadUse> for EdgeUse {
+    fn from(road_use: RoadUse) -> Self {
+        match road_use {
+            RoadUse::Sidewalk => EdgeUse::Sidewalk,
+            RoadUse::Footway | RoadUse::Path | RoadUse::Pedestrian | RoadUse::Cycleway => {
+                EdgeUse::Footway
+            }
+            RoadUse::PedestrianCrossing => EdgeUse::Crossing,
+            RoadUse::Steps => EdgeUse::Steps,
+            RoadUse::Elevator => EdgeUse::Elevator,
+            RoadUse::Escalator => EdgeUse::Escalator,
+            RoadUse::Road
+            | RoadUse::Ramp
+            | RoadUse::TurnChannel
+            | RoadUse::Track
+            | RoadUse::Driveway
+            | RoadUse::Alley
+            | RoadUse::ParkingAisle
+            | RoadUse::Culdesac
+            | RoadUse::LivingStreet
+            | RoadUse::ServiceRoad => EdgeUse::Road,
+            _ => EdgeUse::Other,
+        }
+    }
+}
+
+/// The parts of a Valhalla edge needed to describe it to a pedestrian.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EdgeAttributes {
+    pub names: Vec<String>,
+    pub edge_use: EdgeUse,
+    pub begin_heading: f32,
+    pub end_heading: f32,
+}
+
+impl EdgeAttributes {
+    pub fn new(names: Vec<String>, road_use: RoadUse, shape: &LineString) -> EdgeAttributes {
+        let points: Vec<Point> = shape.points().collect();
+        let begin_heading = points
+            .windows(2)
+            .find(|pair| pair[0] != pair[1])
+            .map(|pair| Haversine::bearing(pair[0], pair[1]) as f32)
+            .unwrap_or(0.0);
+        let end_heading = points
+            .windows(2)
+            .rev()
+            .find(|pair| pair[0] != pair[1])
+            .map(|pair| Haversine::bearing(pair[0], pair[1]) as f32)
+            .unwrap_or(begin_heading);
+        EdgeAttributes {
+            names,
+            edge_use: road_use.into(),
+            begin_heading,
+            end_heading,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManeuverType {
+    Depart,
+    Continue,
+    SlightLeft,
+    Left,
+    SharpLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+    Arrive,
+}
+
+impl ManeuverType {
+    fn from_turn(degrees: f32) -> ManeuverType {
+        match degrees {
+            d if d.abs() < 20.0 => ManeuverType::Continue,
+            d if d.abs() > 160.0 => ManeuverType::UTurn,
+            d if d >= 120.0 => ManeuverType::SharpRight,
+            d if d >= 60.0 => ManeuverType::Right,
+            d if d > 0.0 => ManeuverType::SlightRight,
+            d if d <= -120.0 => ManeuverType::SharpLeft,
+            d if d <= -60.0 => ManeuverType::Left,
+            _ => ManeuverType::SlightLeft,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Maneuver {
+    pub maneuver_type: ManeuverType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub street_name: Option<String>,
+    pub edge_use: EdgeUse,
+    pub length_meters: f64,
+    pub instruction: String,
+}
+
+/// Group the edges of a walking path into maneuvers. Edges without attributes (from graphs built
+/// before they were recorded) are folded into the previous maneuver.
+pub fn build_maneuvers(edges: &[(Option<EdgeAttributes>, f64)]) -> Vec<Maneuver> {
+    let mut maneuvers: Vec<Maneuver> = vec![];
+    let mut last_heading: Option<f32> = None;
+    for (attributes, length_meters) in edges {
+        let attributes = if let Some(attributes) = attributes {
+            attributes
+        } else {
+            if let Some(maneuver) = maneuvers.last_mut() {
+                maneuver.length_meters += length_meters;
+            }
+            continue;
+        };
+        let street_name = attributes.names.first().cloned();
+        let turn = last_heading.map(|heading| turn_degrees(heading, attributes.begin_heading));
+        last_heading = Some(attributes.end_heading);
+
+        if let (Some(maneuver), Some(turn)) = (maneuvers.last_mut(), turn) {
+            let same_way =
+                maneuver.street_name == street_name && maneuver.edge_use == attributes.edge_use;
+            if same_way && turn.abs() < MIN_TURN_DEGREES {
+                maneuver.length_meters += length_meters;
+                continue;
+            }
+        }
+
+        let maneuver_type = turn
+            .map(ManeuverType::from_turn)
+            .unwrap_or(ManeuverType::Depart);
+        maneuvers.push(Maneuver {
+            instruction: instruction(
+                maneuver_type,
+                attributes.edge_use,
+                street_name.as_deref(),
+                attributes.begin_heading,
+            ),
+            maneuver_type,
+            street_name,
+            edge_use: attributes.edge_use,
+            length_meters: *length_meters,
+        });
+    }
+    if !maneuvers.is_empty() {
+        maneuvers.push(Maneuver {
+            maneuver_type: ManeuverType::Arrive,
+            street_name: None,
+            edge_use: EdgeUse::Other,
+            length_meters: 0.0,
+            instruction: "Arrive at your destination".to_string(),
+        });
+    }
+    maneuvers
+}
+
+// Signed difference between two headings in the range (-180, 180]. Positive values are right turns.
+fn turn_degrees(from_heading: f32, to_heading: f32) -> f32 {
+    let delta = (to_heading - from_heading).rem_euclid(360.0);
+    if delta > 180.0 { delta - 360.0 } else { delta }
+}
+
+fn cardinal_direction(heading: f32) -> &'static str {
+    const DIRECTIONS: [&str; 8] = [
+        "north",
+        "northeast",
+        "east",
+        "southeast",
+        "south",
+        "southwest",
+        "west",
+        "northwest",
+    ];
+    DIRECTIONS[((heading.rem_euclid(360.0) + 22.5) / 45.0) as usize % 8]
+}
+
+fn instruction(
+    maneuver_type: ManeuverType,
+    edge_use: EdgeUse,
+    street_name: Option<&str>,
+    heading: f32,
+) -> String {
+    match (edge_use, street_name) {
+        (EdgeUse::Steps, _) => return "Take the stairs".to_string(),
+        (EdgeUse::Elevator, _) => return "Take the elevator".to_string(),
+        (EdgeUse::Escalator, _) => return "Take the escalator".to_string(),
+        (EdgeUse::Crossing, Some(name)) => return format!("Cross {}", name),
+        (EdgeUse::Crossing, None) => return "Cross the street".to_string(),
+        _ => {}
+    }
+    let verb = match maneuver_type {
+        ManeuverType::Depart => format!("Head {}", cardinal_direction(heading)),
+        ManeuverType::Continue => "Continue".to_string(),
+        ManeuverType::SlightLeft => "Bear left".to_string(),
+        ManeuverType::Left => "Turn left".to_string(),
+        ManeuverType::SharpLeft => "Turn sharp left".to_string(),
+        ManeuverType::SlightRight => "Bear right".to_string(),
+        ManeuverType::Right => "Turn right".to_string(),
+        ManeuverType::SharpRight => "Turn sharp right".to_string(),
+        ManeuverType::UTurn => "Make a U-turn".to_string(),
+        ManeuverType::Arrive => "Arrive".to_string(),
+    };
+    if let Some(name) = street_name {
+        format!("{} on {}", verb, name)
+    } else {
+        verb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(name: &str, edge_use: EdgeUse, begin: f32, end: f32) -> Option<EdgeAttributes> {
+        Some(EdgeAttributes {
+            names: vec![name.to_string()],
+            edge_use,
+            begin_heading: begin,
+            end_heading: end,
+        })
+    }
+
+    #[test]
+    fn merges_straight_edges_and_detects_turns() {
+        let maneuvers = build_maneuvers(&[
+            (edge("Pine St", EdgeUse::Sidewalk, 85.0, 90.0), 50.0),
+            (edge("Pine St", EdgeUse::Sidewalk, 92.0, 90.0), 70.0),
+            (edge("4th Ave", EdgeUse::Sidewalk, 355.0, 0.0), 120.0),
+        ]);
+        assert_eq!(maneuvers.len(), 3);
+        assert_eq!(maneuvers[0].maneuver_type, ManeuverType::Depart);
+        assert_eq!(maneuvers[0].instruction, "Head east on Pine St");
+        assert_eq!(maneuvers[0].length_meters, 120.0);
+        assert_eq!(maneuvers[1].maneuver_type, ManeuverType::Left);
+        assert_eq!(maneuvers[1].instruction, "Turn left on 4th Ave");
+        assert_eq!(maneuvers[2].maneuver_type, ManeuverType::Arrive);
+    }
+}
@@ -96,7 +96,12 @@ pub fn enumerate_edges<F: FnMut(NodeRecord, Vec<EdgeRecord>)>(
                     continue;
                 }
 
-                edges.push(EdgeRecord::new(edge_info.shape()?.clone(), edge));
+                let names = edge_info
+                    .get_names()
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect();
+                edges.push(EdgeRecord::new(edge_info.shape()?.clone(), names, edge));
             }
             action(NodeRecord::new(node_id, node), edges);
         }
@@ -6,13 +6,19 @@ use valhalla_graphtile::{
 
 pub struct EdgeRecord<'a> {
     geometry: LineString,
+    names: Vec<String>,
     directed_edge: &'a DirectedEdge,
 }
 
 impl<'a> EdgeRecord<'a> {
-    pub fn new(geometry: LineString, directed_edge: &'a DirectedEdge) -> EdgeRecord<'a> {
+    pub fn new(
+        geometry: LineString,
+        names: Vec<String>,
+        directed_edge: &'a DirectedEdge,
+    ) -> EdgeRecord<'a> {
         EdgeRecord {
             geometry,
+            names,
             directed_edge,
         }
     }
@@ -21,6 +27,10 @@ impl<'a> EdgeRecord<'a> {
         &self.geometry
     }
 
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
     pub fn directed_edge(&'a self) -> &'a DirectedEdge {
         self.directed_edge
     }
@@ -0,0 +1,193 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::{
+    response::{ResponseStatus, SolariResponse},
+    LatLng, SolariItinerary, SolariLeg,
+};
+
+/// A plan response as a single GeoJSON FeatureCollection holding the features of every itinerary,
+/// each tagged with its `itinerary_index`. Everything else is carried in foreign members.
+#[derive(Debug, Clone, Serialize)]
+pub struct SolariGeoJsonResponse {
+    #[serde(rename = "type")]
+    pub geojson_type: &'static str,
+    pub features: Vec<Value>,
+    pub status: ResponseStatus,
+    // The start and end time and fares of each itinerary, by itinerary index.
+    pub itineraries: Vec<Map<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_page: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_page: Option<String>,
+}
+
+impl From<&SolariResponse> for SolariGeoJsonResponse {
+    fn from(response: &SolariResponse) -> Self {
+        SolariGeoJsonResponse {
+            geojson_type: "FeatureCollection",
+            features: response
+                .itineraries
+                .iter()
+                .enumerate()
+                .flat_map(|(itinerary_index, itinerary)| {
+                    itinerary_features(itinerary, itinerary_index)
+                })
+                .collect(),
+            status: response.status.clone(),
+            itineraries: response
+                .itineraries
+                .iter()
+                .map(itinerary_properties)
+                .collect(),
+            next_page: response.next_page.clone(),
+            previous_page: response.previous_page.clone(),
+        }
+    }
+}
+
+/// Each leg becomes a LineString feature carrying all of the leg's properties, and each stop
+/// visited becomes a Point feature.
+pub fn itinerary_features(itinerary: &SolariItinerary, itinerary_index: usize) -> Vec<Value> {
+    let mut features = vec![];
+    for (leg_index, leg) in itinerary.legs.iter().enumerate() {
+        let (leg_type, start, end, route_shape) = match leg {
+            SolariLeg::Transit {
+                start_location,
+                end_location,
+                route_shape,
+                ..
+            } => ("transit", start_location, end_location, route_shape),
+            SolariLeg::Transfer {
+                start_location,
+                end_location,
+                route_shape,
+                ..
+            } => ("transfer", start_location, end_location, route_shape),
+        };
+
+        let mut properties = leg_properties(leg);
+        properties.insert("itinerary_index".to_string(), json!(itinerary_index));
+        properties.insert("leg_type".to_string(), json!(leg_type));
+        properties.insert("leg_index".to_string(), json!(leg_index));
+        features.push(feature(
+            line_string(route_shape.as_deref(), start, end),
+            properties,
+        ));
+
+        if let SolariLeg::Transit {
+            intermediate_stops,
+            start_stop_id,
+            end_stop_id,
+            ..
+        } = leg
+        {
+            features.push(stop_feature(
+                start,
+                itinerary_index,
+                leg_index,
+                "boarding",
+                start_stop_id.as_ref(),
+            ));
+            for stop in intermediate_stops {
+                features.push(stop_feature(
+                    &stop.location,
+                    itinerary_index,
+                    leg_index,
+                    "intermediate",
+                    Some(&stop.stop_id),
+                ));
+            }
+            features.push(stop_feature(
+                end,
+                itinerary_index,
+                leg_index,
+                "alighting",
+                end_stop_id.as_ref(),
+            ));
+        }
+    }
+    features
+}
+
+fn itinerary_properties(itinerary: &SolariItinerary) -> Map<String, Value> {
+    let mut properties = Map::new();
+    properties.insert(
+        "start_time".to_string(),
+        json!(itinerary.start_time.unix_timestamp() * 1000),
+    );
+    properties.insert(
+        "end_time".to_string(),
+        json!(itinerary.end_time.unix_timestamp() * 1000),
+    );
+    if !itinerary.fares.is_empty() {
+        properties.insert("fares".to_string(), json!(itinerary.fares));
+    }
+    properties
+}
+
+// The leg's own serialization, minus the encoded shape that the geometry replaces.
+fn leg_properties(leg: &SolariLeg) -> Map<String, Value> {
+    let serialized = serde_json::to_value(leg).expect("Failed to serialize leg");
+    let mut properties = match serialized {
+        Value::Object(tagged) => match tagged.into_iter().next() {
+            Some((_, Value::Object(properties))) => properties,
+            _ => Map::new(),
+        },
+        _ => Map::new(),
+    };
+    properties.remove("route_shape");
+    properties
+}
+
+fn line_string(route_shape: Option<&str>, start: &LatLng, end: &LatLng) -> Value {
+    let decoded = route_shape.and_then(|shape| polyline::decode_polyline(shape, 5).ok());
+    let coordinates: Vec<[f64; 2]> = match decoded {
+        Some(line_string) if line_string.0.len() >= 2 => line_string
+            .0
+            .iter()
+            .map(|coord| [coord.x, coord.y])
+            .collect(),
+        // Fall back to a straight line if there's no usable shape.
+        _ => vec![[start.lon, start.lat], [end.lon, end.lat]],
+    };
+    json!({
+        "type": "LineString",
+        "coordinates": coordinates,
+    })
+}
+
+fn stop_feature(
+    location: &LatLng,
+    itinerary_index: usize,
+    leg_index: usize,
+    role: &str,
+    stop_id: Option<&String>,
+) -> Value {
+    let mut properties = Map::new();
+    properties.insert("feature_type".to_string(), json!("stop"));
+    properties.insert("itinerary_index".to_string(), json!(itinerary_index));
+    properties.insert("leg_index".to_string(), json!(leg_index));
+    properties.insert("role".to_string(), json!(role));
+    if let Some(name) = &location.stop {
+        properties.insert("name".to_string(), json!(name));
+    }
+    if let Some(stop_id) = stop_id {
+        properties.insert("stop_id".to_string(), json!(stop_id));
+    }
+    feature(
+        json!({
+            "type": "Point",
+            "coordinates": [location.lon, location.lat],
+        }),
+        properties,
+    )
+}
+
+fn feature(geometry: Value, properties: Map<String, Value>) -> Value {
+    json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": properties,
+    })
+}
@@ -1,8 +1,13 @@
 use serde::{Deserialize, Serialize};
+use solari_transfers::maneuver::Maneuver;
 use time::OffsetDateTime;
+use vehicle::SolariVehiclePosition;
 
+pub mod geojson;
+pub mod page;
 pub mod request;
 pub mod response;
+pub mod vehicle;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LatLng {
@@ -10,6 +15,9 @@ pub struct LatLng {
     pub lon: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<String>,
+    // The stop's stable `feed_id:stop_id` identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_stop_id: Option<String>,
 }
 
 impl PartialEq for LatLng {
@@ -21,6 +29,31 @@ impl PartialEq for LatLng {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SolariFare {
+    pub amount: f64,
+    pub currency: String,
+    // The GTFS fare_id (Fares v1) or fare_product_id (Fares v2) this amount was charged under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fare_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SolariIntermediateStop {
+    pub stop_id: String,
+    pub location: LatLng,
+    #[serde(
+        serialize_with = "time::serde::timestamp::milliseconds::serialize",
+        deserialize_with = "time::serde::timestamp::milliseconds::deserialize"
+    )]
+    pub arrival_time: OffsetDateTime,
+    #[serde(
+        serialize_with = "time::serde::timestamp::milliseconds::serialize",
+        deserialize_with = "time::serde::timestamp::milliseconds::deserialize"
+    )]
+    pub departure_time: OffsetDateTime,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum SolariLeg {
     #[serde(rename = "transit")]
@@ -41,6 +74,43 @@ pub enum SolariLeg {
         route_shape: Option<String>,
         transit_route: Option<String>,
         transit_agency: Option<String>,
+        // Stops passed between boarding and alighting, not including either.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        intermediate_stops: Vec<SolariIntermediateStop>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trip_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        feed_id: Option<String>,
+        // Stable `feed_id:trip_id` and `feed_id:route_id` identifiers.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        global_trip_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        global_route_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route_long_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route_color: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route_text_color: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        route_type: Option<i16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        headsign: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        start_stop_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        start_stop_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_stop_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end_stop_name: Option<String>,
+        // Zero if the leg is covered by a transfer from an earlier leg.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fare: Option<SolariFare>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        vehicle: Option<SolariVehiclePosition>,
     },
     #[serde(rename = "transfer")]
     Transfer {
@@ -57,6 +127,8 @@ pub enum SolariLeg {
         start_location: LatLng,
         end_location: LatLng,
         route_shape: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        maneuvers: Vec<Maneuver>,
     },
 }
 
@@ -75,6 +147,9 @@ pub struct SolariItinerary {
     )]
     pub end_time: OffsetDateTime,
     pub legs: Vec<SolariLeg>,
+    // One total per currency, present only if every transit leg could be priced.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fares: Vec<SolariFare>,
 }
 
 impl PartialEq for SolariItinerary {
@@ -101,6 +176,7 @@ impl PartialEq for SolariItinerary {
                         route_shape,
                         transit_route,
                         transit_agency,
+                        ..
                     },
                     SolariLeg::Transit {
                         start_time: other_start_time,
@@ -110,6 +186,7 @@ impl PartialEq for SolariItinerary {
                         route_shape: other_route_shape,
                         transit_route: other_transit_route,
                         transit_agency: other_transit_agency,
+                        ..
                     },
                 ) => {
                     transit_route == other_transit_route
@@ -132,6 +209,7 @@ impl PartialEq for SolariItinerary {
                         start_location,
                         end_location,
                         route_shape,
+                        ..
                     },
                     SolariLeg::Transfer {
                         start_time: other_start_time,
@@ -139,6 +217,7 @@ impl PartialEq for SolariItinerary {
                         start_location: other_start_location,
                         end_location: other_end_location,
                         route_shape: other_route_shape,
+                        ..
                     },
                 ) => {
                     (start_location == other_start_location || end_location == other_end_location)
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+use super::{SolariItinerary, SolariLeg};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PageDirection {
+    #[serde(rename = "next")]
+    Next,
+    #[serde(rename = "previous")]
+    Previous,
+}
+
+/// An opaque cursor handed back to clients so they can ask for earlier or later itineraries.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PageCursor {
+    pub direction: PageDirection,
+    // For the next page this is the earliest time to leave the origin, for the previous page it's
+    // the time itineraries have to arrive before. Epoch seconds.
+    pub time: u32,
+    // Signatures of the itineraries on the page that produced this cursor.
+    pub seen: Vec<String>,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("Failed to serialize cursor"))
+    }
+
+    pub fn decode(cursor: &str) -> Result<PageCursor, anyhow::Error> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .context("Page cursor is not valid base64")?;
+        serde_json::from_slice(&bytes).context("Page cursor is malformed")
+    }
+}
+
+/// Cursors for the pages after and before `itineraries`, which mustn't be empty.
+/// `latest_departure` is the latest time someone could leave the origin and still make an
+/// itinerary, so the next page starts searching just after the last of those.
+pub fn page_cursors(
+    itineraries: &[SolariItinerary],
+    latest_departure: impl Fn(&SolariItinerary) -> i64,
+) -> (PageCursor, PageCursor) {
+    let seen: Vec<String> = itineraries.iter().map(itinerary_signature).collect();
+    let last_departure = itineraries
+        .iter()
+        .map(latest_departure)
+        .max()
+        .expect("No itineraries to page from");
+    let first_arrival = itineraries
+        .iter()
+        .map(|itinerary| itinerary.end_time.unix_timestamp())
+        .min()
+        .expect("No itineraries to page from");
+    (
+        PageCursor {
+            direction: PageDirection::Next,
+            time: (last_departure + 1) as u32,
+            seen: seen.clone(),
+        },
+        PageCursor {
+            direction: PageDirection::Previous,
+            time: first_arrival as u32,
+            seen,
+        },
+    )
+}
+
+// Two itineraries are considered the same if they ride the same trips, boarding at the same times.
+pub fn itinerary_signature(itinerary: &SolariItinerary) -> String {
+    itinerary
+        .legs
+        .iter()
+        .filter_map(|leg| match leg {
+            SolariLeg::Transit {
+                start_time,
+                trip_id,
+                transit_route,
+                ..
+            } => Some(format!(
+                "{}@{}",
+                trip_id
+                    .as_deref()
+                    .or(transit_route.as_deref())
+                    .unwrap_or_default(),
+                start_time.unix_timestamp()
+            )),
+            SolariLeg::Transfer { .. } => None,
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn itinerary(trip_id: &str, boarding: i64, arrival: i64) -> SolariItinerary {
+        let location = json!({"lat": 47.6, "lon": -122.3});
+        serde_json::from_value(json!({
+            "start_location": location,
+            "end_location": location,
+            "start_time": (boarding - 600) * 1000,
+            "end_time": arrival * 1000,
+            "legs": [{"transit": {
+                "start_time": boarding * 1000,
+                "end_time": arrival * 1000,
+                "start_location": location,
+                "end_location": location,
+                "transit_route": "1 Line",
+                "transit_agency": "Sound Transit",
+                "trip_id": trip_id,
+            }}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn cursors_page_forward_from_the_last_departure_and_back_from_the_first_arrival() {
+        let itineraries = vec![
+            itinerary("early", 10_000, 12_000),
+            itinerary("late", 10_600, 11_800),
+        ];
+        // Five minutes through a station entrance to the platform.
+        let (next, previous) = page_cursors(&itineraries, |itinerary| {
+            itinerary
+                .legs
+                .iter()
+                .find_map(|leg| match leg {
+                    SolariLeg::Transit { start_time, .. } => {
+                        Some(start_time.unix_timestamp() - 300)
+                    }
+                    SolariLeg::Transfer { .. } => None,
+                })
+                .unwrap()
+        });
+        assert_eq!(next.direction, PageDirection::Next);
+        assert_eq!(next.time, 10_301);
+        assert_eq!(previous.direction, PageDirection::Previous);
+        assert_eq!(previous.time, 11_800);
+        assert_eq!(
+            next.seen,
+            vec!["early@10000".to_string(), "late@10600".to_string()]
+        );
+        assert_eq!(PageCursor::decode(&previous.encode()).unwrap(), previous);
+    }
+}
@@ -1,10 +1,9 @@
 use std::usize;
 
+use s2::latlng::LatLng;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use super::LatLng;
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum TransferMode {
     #[serde(rename = "walking")]
@@ -28,10 +27,34 @@ impl Default for TransferQuantity {
     }
 }
 
+/// Where a request routes from or to, given either as coordinates or as a stop's stable
+/// `feed_id:stop_id` identifier.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestLocation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_stop_id: Option<String>,
+}
+
+impl RequestLocation {
+    /// The referenced stop's location if there is one, looked up with `stop_location`, and the
+    /// coordinates otherwise. None if the stop doesn't exist or the coordinates are incomplete.
+    pub fn resolve(&self, stop_location: impl FnOnce(&str) -> Option<LatLng>) -> Option<LatLng> {
+        match (&self.global_stop_id, self.lat, self.lon) {
+            (Some(global_stop_id), _, _) => stop_location(global_stop_id),
+            (None, Some(lat), Some(lon)) => Some(LatLng::from_degrees(lat, lon)),
+            (None, _, _) => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SolariRequest {
-    pub from: LatLng,
-    pub to: LatLng,
+    pub from: RequestLocation,
+    pub to: RequestLocation,
     #[serde(
         serialize_with = "time::serde::timestamp::milliseconds::serialize",
         deserialize_with = "time::serde::timestamp::milliseconds::deserialize"
@@ -42,4 +65,87 @@ pub struct SolariRequest {
     pub transfer_mode: TransferMode,
     #[serde(default)]
     pub max_transfers: TransferQuantity,
+    // A `next_page` or `previous_page` cursor from an earlier response.
+    #[serde(default)]
+    pub page_cursor: Option<String>,
+    // Preferred language for stop, route and headsign names, as an IETF language tag. Takes
+    // precedence over the Accept-Language header.
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+impl SolariRequest {
+    /// Languages to localize the response into, most preferred first.
+    pub fn languages(&self, accept_language: Option<&str>) -> Vec<String> {
+        let mut languages: Vec<String> = self.lang.iter().cloned().collect();
+        if let Some(accept_language) = accept_language {
+            languages.extend(parse_accept_language(accept_language));
+        }
+        languages
+    }
+}
+
+/// Language tags from an Accept-Language header, ordered by quality value.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((tag.to_string(), quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // Stable, so tags with equal quality stay in header order.
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use s2::latlng::LatLng;
+
+    use super::{RequestLocation, SolariRequest};
+
+    fn location(json: &str) -> RequestLocation {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn resolves_coordinates_and_stops() {
+        let resolved = location(r#"{"lat":47.6,"lon":-122.3}"#)
+            .resolve(|_| panic!("no stop was referenced"))
+            .unwrap();
+        assert_eq!(resolved, LatLng::from_degrees(47.6, -122.3));
+
+        let stop = LatLng::from_degrees(47.5, -122.2);
+        let resolved = location(r#"{"global_stop_id":"kcm:1000"}"#).resolve(|global_stop_id| {
+            assert_eq!(global_stop_id, "kcm:1000");
+            Some(stop)
+        });
+        assert_eq!(resolved, Some(stop));
+        assert_eq!(
+            location(r#"{"global_stop_id":"kcm:1000"}"#).resolve(|_| None),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_missing_locations() {
+        // Neither coordinates nor a stop shouldn't route from (0, 0).
+        assert_eq!(location("{}").resolve(|_| None), None);
+        assert_eq!(location(r#"{"lat":47.6}"#).resolve(|_| None), None);
+
+        let request: SolariRequest =
+            serde_json::from_str(r#"{"from":{},"to":{"lat":47.6,"lon":-122.3},"start_at":0}"#)
+                .unwrap();
+        assert_eq!(request.from.resolve(|_| None), None);
+    }
 }
@@ -18,4 +18,8 @@ pub enum ResponseStatus {
 pub struct SolariResponse {
     pub status: ResponseStatus,
     pub itineraries: Vec<SolariItinerary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_page: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_page: Option<String>,
 }
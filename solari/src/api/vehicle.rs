@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::LatLng;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum VehicleStopStatus {
+    #[serde(rename = "incoming_at")]
+    IncomingAt,
+    #[serde(rename = "stopped_at")]
+    StoppedAt,
+    #[serde(rename = "in_transit_to")]
+    InTransitTo,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SolariVehiclePosition {
+    pub location: LatLng,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::timestamp::milliseconds::option"
+    )]
+    pub timestamp: Option<OffsetDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vehicle_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearing: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_stop_sequence: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_status: Option<VehicleStopStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SolariVehiclesResponse {
+    pub vehicles: Vec<SolariVehiclePosition>,
+}
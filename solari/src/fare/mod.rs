@@ -0,0 +1,594 @@
+use std::collections::{BTreeMap, HashMap};
+
+use gtfs_structures::{FareAttribute, FareRule, Gtfs, Transfers};
+use serde::{Deserialize, Serialize};
+
+use crate::timetable::gtfs_extra::{
+    FareLegRuleRecord, FareProductRecord, FareTransferRuleRecord, GtfsExtra,
+};
+
+/// The fare-relevant properties of a stop.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StopFareInfo {
+    pub feed_id: String,
+    pub zone_id: Option<String>,
+    pub area_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FareAttributeData {
+    pub fare_id: String,
+    pub price: f64,
+    pub currency: String,
+    // `None` means unlimited transfers.
+    pub transfers: Option<u32>,
+    pub transfer_duration: Option<u32>,
+    pub agency_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FareRuleData {
+    pub fare_id: String,
+    pub route_id: Option<String>,
+    pub origin_id: Option<String>,
+    pub destination_id: Option<String>,
+    pub contains_id: Option<String>,
+}
+
+/// All of a feed's fare tables, both Fares v1 and Fares v2.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FareData {
+    pub fare_attributes: Vec<FareAttributeData>,
+    pub fare_rules: Vec<FareRuleData>,
+    pub fare_products: Vec<FareProductRecord>,
+    pub fare_leg_rules: Vec<FareLegRuleRecord>,
+    pub fare_transfer_rules: Vec<FareTransferRuleRecord>,
+    pub route_networks: HashMap<String, String>,
+    pub route_agencies: HashMap<String, String>,
+}
+
+impl FareData {
+    pub fn new(gtfs: &Gtfs, extra: &GtfsExtra) -> FareData {
+        FareData {
+            fare_attributes: gtfs
+                .fare_attributes
+                .values()
+                .filter_map(FareAttributeData::from_gtfs)
+                .collect(),
+            fare_rules: gtfs
+                .fare_rules
+                .values()
+                .flatten()
+                .map(FareRuleData::from_gtfs)
+                .collect(),
+            fare_products: extra.fare_products.clone(),
+            fare_leg_rules: extra.fare_leg_rules.clone(),
+            fare_transfer_rules: extra.fare_transfer_rules.clone(),
+            route_networks: extra
+                .route_networks
+                .iter()
+                .filter_map(|record| {
+                    record
+                        .network_id
+                        .clone()
+                        .map(|network_id| (record.route_id.clone(), network_id))
+                })
+                .collect(),
+            route_agencies: gtfs
+                .routes
+                .values()
+                .filter_map(|route| {
+                    route
+                        .agency_id
+                        .clone()
+                        .map(|agency_id| (route.id.clone(), agency_id))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fare_attributes.is_empty() && self.fare_leg_rules.is_empty()
+    }
+}
+
+impl FareAttributeData {
+    fn from_gtfs(attribute: &FareAttribute) -> Option<FareAttributeData> {
+        Some(FareAttributeData {
+            fare_id: attribute.id.clone(),
+            price: attribute.price.parse().ok()?,
+            currency: attribute.currency.clone(),
+            transfers: match attribute.transfers {
+                Transfers::Unlimited => None,
+                Transfers::NoTransfer => Some(0),
+                Transfers::UniqueTransfer => Some(1),
+                Transfers::TwoTransfers => Some(2),
+                Transfers::Other(count) => Some(count.max(0) as u32),
+            },
+            transfer_duration: attribute.transfer_duration.map(|duration| duration as u32),
+            agency_id: attribute.agency_id.clone(),
+        })
+    }
+}
+
+impl FareRuleData {
+    fn from_gtfs(rule: &FareRule) -> FareRuleData {
+        FareRuleData {
+            fare_id: rule.fare_id.clone(),
+            route_id: rule.route_id.clone(),
+            origin_id: rule.origin_id.clone(),
+            destination_id: rule.destination_id.clone(),
+            contains_id: rule.contains_id.clone(),
+        }
+    }
+}
+
+/// A transit leg as seen by the fare engine.
+#[derive(Debug, Clone, Default)]
+pub struct FareLeg {
+    pub feed_id: Option<String>,
+    pub route_id: Option<String>,
+    pub departure: u32,
+    pub arrival: u32,
+    pub from: StopFareInfo,
+    pub to: StopFareInfo,
+    // Every zone the leg passes through, including the boarding and alighting stops.
+    pub zones: Vec<String>,
+}
+
+/// The amount charged for a single leg. Legs covered by a transfer have an amount of zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegFare {
+    pub fare_id: String,
+    pub amount: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItineraryFare {
+    pub legs: Vec<Option<LegFare>>,
+    // Totals per currency. Empty if any leg couldn't be priced.
+    pub totals: BTreeMap<String, f64>,
+}
+
+/// Price a sequence of transit legs. Transfer discounts only apply between consecutive legs from
+/// the same feed.
+pub fn calculate_fares(legs: &[FareLeg], fare_data: &HashMap<String, FareData>) -> ItineraryFare {
+    let mut leg_fares: Vec<Option<LegFare>> = vec![None; legs.len()];
+    let mut run_start = 0;
+    while run_start < legs.len() {
+        let feed_id = &legs[run_start].feed_id;
+        let run_end = legs[run_start..]
+            .iter()
+            .position(|leg| &leg.feed_id != feed_id)
+            .map(|len| run_start + len)
+            .unwrap_or(legs.len());
+        let run = &legs[run_start..run_end];
+        if let Some(data) = feed_id.as_ref().and_then(|feed_id| fare_data.get(feed_id)) {
+            let run_fares = if !data.fare_leg_rules.is_empty() {
+                fares_v2(run, data)
+            } else {
+                fares_v1(run, data)
+            };
+            for (idx, fare) in run_fares.into_iter().enumerate() {
+                leg_fares[run_start + idx] = fare;
+            }
+        }
+        run_start = run_end;
+    }
+
+    let mut totals = BTreeMap::new();
+    if leg_fares.iter().all(|fare| fare.is_some()) {
+        for fare in leg_fares.iter().flatten() {
+            *totals.entry(fare.currency.clone()).or_insert(0.0) += fare.amount;
+        }
+    }
+    ItineraryFare {
+        legs: leg_fares,
+        totals,
+    }
+}
+
+// Fares v1 prices groups of consecutive legs with a single fare, so find the cheapest way to split
+// the legs into groups.
+fn fares_v1(legs: &[FareLeg], data: &FareData) -> Vec<Option<LegFare>> {
+    let mut best_cost: Vec<Option<f64>> = vec![None; legs.len() + 1];
+    let mut best_group: Vec<Option<(usize, &FareAttributeData)>> = vec![None; legs.len() + 1];
+    best_cost[0] = Some(0.0);
+    for end in 1..=legs.len() {
+        for start in 0..end {
+            let (Some(cost), Some(attribute)) =
+                (best_cost[start], cheapest_v1(&legs[start..end], data))
+            else {
+                continue;
+            };
+            let cost = cost + attribute.price;
+            if best_cost[end].is_none_or(|best| cost < best) {
+                best_cost[end] = Some(cost);
+                best_group[end] = Some((start, attribute));
+            }
+        }
+    }
+
+    let mut fares = vec![None; legs.len()];
+    if best_cost[legs.len()].is_none() {
+        // At least one leg can't be priced. Fall back to pricing each leg on its own.
+        for (idx, leg) in legs.iter().enumerate() {
+            fares[idx] = cheapest_v1(std::slice::from_ref(leg), data).map(|attribute| LegFare {
+                fare_id: attribute.fare_id.clone(),
+                amount: attribute.price,
+                currency: attribute.currency.clone(),
+            });
+        }
+        return fares;
+    }
+    let mut end = legs.len();
+    while end > 0 {
+        let (start, attribute) = best_group[end].unwrap();
+        for (idx, fare) in fares.iter_mut().enumerate().take(end).skip(start) {
+            *fare = Some(LegFare {
+                fare_id: attribute.fare_id.clone(),
+                amount: if idx == start { attribute.price } else { 0.0 },
+                currency: attribute.currency.clone(),
+            });
+        }
+        end = start;
+    }
+    fares
+}
+
+fn cheapest_v1<'a>(group: &[FareLeg], data: &'a FareData) -> Option<&'a FareAttributeData> {
+    let first = group.first()?;
+    let last = group.last()?;
+    data.fare_attributes
+        .iter()
+        .filter(|attribute| {
+            attribute
+                .transfers
+                .is_none_or(|transfers| group.len() - 1 <= transfers as usize)
+        })
+        .filter(|attribute| {
+            attribute
+                .transfer_duration
+                .is_none_or(|duration| last.departure.saturating_sub(first.departure) <= duration)
+        })
+        .filter(|attribute| {
+            attribute.agency_id.as_ref().is_none_or(|agency_id| {
+                group.iter().all(|leg| {
+                    leg.route_id
+                        .as_ref()
+                        .and_then(|route_id| data.route_agencies.get(route_id))
+                        .is_none_or(|route_agency| route_agency == agency_id)
+                })
+            })
+        })
+        .filter(|attribute| {
+            let rules: Vec<&FareRuleData> = data
+                .fare_rules
+                .iter()
+                .filter(|rule| rule.fare_id == attribute.fare_id)
+                .collect();
+            rules.is_empty() || v1_rules_match(group, &rules)
+        })
+        .min_by(|a, b| a.price.total_cmp(&b.price))
+}
+
+fn v1_rules_match(group: &[FareLeg], rules: &[&FareRuleData]) -> bool {
+    // Each leg needs a single row whose route, origin and destination all hold, so fields from
+    // different rows are never combined.
+    let origin = &group[0].from.zone_id;
+    let destination = &group[group.len() - 1].to.zone_id;
+    let rows_match = group.iter().all(|leg| {
+        rules.iter().any(|rule| {
+            (rule.route_id.is_none() || rule.route_id == leg.route_id)
+                && (rule.origin_id.is_none() || &rule.origin_id == origin)
+                && (rule.destination_id.is_none() || &rule.destination_id == destination)
+        })
+    });
+    // Rows with a contains_id together list every zone the rider is allowed to pass through.
+    let contains: Vec<&String> = rules
+        .iter()
+        .filter_map(|rule| rule.contains_id.as_ref())
+        .collect();
+    let contains_match = contains.is_empty()
+        || group
+            .iter()
+            .flat_map(|leg| leg.zones.iter())
+            .all(|zone| contains.contains(&zone));
+    rows_match && contains_match
+}
+
+struct V2LegFare<'a> {
+    leg_group_id: Option<&'a String>,
+    product: &'a FareProductRecord,
+}
+
+fn fares_v2(legs: &[FareLeg], data: &FareData) -> Vec<Option<LegFare>> {
+    let mut fares: Vec<Option<LegFare>> = vec![None; legs.len()];
+    let mut previous: Option<(usize, V2LegFare)> = None;
+    let mut transfers_in_chain = 0;
+    let mut chain_start = 0;
+    for (idx, leg) in legs.iter().enumerate() {
+        let leg_fare = if let Some(leg_fare) = v2_leg_fare(leg, data) {
+            leg_fare
+        } else {
+            previous = None;
+            continue;
+        };
+
+        let transfer = previous.as_ref().and_then(|(previous_idx, previous_fare)| {
+            v2_transfer(
+                &legs[*previous_idx],
+                previous_fare,
+                leg,
+                &leg_fare,
+                transfers_in_chain,
+                data,
+            )
+        });
+        if let Some((rule, transfer_amount)) = transfer {
+            transfers_in_chain += 1;
+            let amount = match rule.fare_transfer_type {
+                // A + AB + B: the rider pays for both legs and the transfer.
+                1 => leg_fare.product.amount + transfer_amount,
+                // AB: only transfer products are paid for, so the first leg of the chain is free.
+                2 => {
+                    if let Some(fare) = fares[chain_start].as_mut() {
+                        fare.amount = 0.0;
+                    }
+                    transfer_amount
+                }
+                // A + AB: the transfer replaces the fare for the second leg.
+                _ => transfer_amount,
+            };
+            fares[idx] = Some(LegFare {
+                fare_id: rule
+                    .fare_product_id
+                    .clone()
+                    .unwrap_or(leg_fare.product.fare_product_id.clone()),
+                amount,
+                currency: leg_fare.product.currency.clone(),
+            });
+        } else {
+            transfers_in_chain = 0;
+            chain_start = idx;
+            fares[idx] = Some(LegFare {
+                fare_id: leg_fare.product.fare_product_id.clone(),
+                amount: leg_fare.product.amount,
+                currency: leg_fare.product.currency.clone(),
+            });
+        }
+        previous = Some((idx, leg_fare));
+    }
+    fares
+}
+
+fn v2_leg_fare<'a>(leg: &FareLeg, data: &'a FareData) -> Option<V2LegFare<'a>> {
+    let network_id = leg
+        .route_id
+        .as_ref()
+        .and_then(|route_id| data.route_networks.get(route_id));
+    let matches_area = |rule_area: &Option<String>, areas: &Vec<String>| {
+        rule_area.as_ref().is_none_or(|area| areas.contains(area))
+    };
+    // Timeframes aren't supported, so rules restricted to a timeframe never match.
+    let candidates: Vec<&FareLegRuleRecord> = data
+        .fare_leg_rules
+        .iter()
+        .filter(|rule| {
+            rule.from_timeframe_group_id.is_none() && rule.to_timeframe_group_id.is_none()
+        })
+        .filter(|rule| rule.network_id.is_none() || rule.network_id.as_ref() == network_id)
+        .filter(|rule| matches_area(&rule.from_area_id, &leg.from.area_ids))
+        .filter(|rule| matches_area(&rule.to_area_id, &leg.to.area_ids))
+        .collect();
+    // An explicit priority wins. Otherwise a rule that names a network or area beats one that
+    // leaves it empty.
+    let specificity = |rule: &FareLegRuleRecord| {
+        (
+            rule.rule_priority.unwrap_or(0),
+            rule.network_id.is_some() as u8
+                + rule.from_area_id.is_some() as u8
+                + rule.to_area_id.is_some() as u8,
+        )
+    };
+    let best = candidates.iter().map(|rule| specificity(rule)).max()?;
+    candidates
+        .into_iter()
+        .filter(|rule| specificity(rule) == best)
+        .filter_map(|rule| {
+            cheapest_product(&rule.fare_product_id, data).map(|product| V2LegFare {
+                leg_group_id: rule.leg_group_id.as_ref(),
+                product,
+            })
+        })
+        .min_by(|a, b| a.product.amount.total_cmp(&b.product.amount))
+}
+
+fn v2_transfer<'a>(
+    from_leg: &FareLeg,
+    from_fare: &V2LegFare,
+    to_leg: &FareLeg,
+    to_fare: &V2LegFare,
+    transfers_in_chain: i32,
+    data: &'a FareData,
+) -> Option<(&'a FareTransferRuleRecord, f64)> {
+    if from_fare.product.currency != to_fare.product.currency {
+        return None;
+    }
+    data.fare_transfer_rules
+        .iter()
+        .filter(|rule| {
+            rule.from_leg_group_id.is_none()
+                || rule.from_leg_group_id.as_ref() == from_fare.leg_group_id
+        })
+        .filter(|rule| {
+            rule.to_leg_group_id.is_none() || rule.to_leg_group_id.as_ref() == to_fare.leg_group_id
+        })
+        .filter(|rule| {
+            rule.transfer_count
+                .is_none_or(|count| count < 0 || transfers_in_chain < count)
+        })
+        .filter(|rule| {
+            rule.duration_limit.is_none_or(|limit| {
+                let elapsed = match rule.duration_limit_type.unwrap_or(0) {
+                    0 => to_leg.arrival.saturating_sub(from_leg.departure),
+                    1 => to_leg.departure.saturating_sub(from_leg.departure),
+                    2 => to_leg.departure.saturating_sub(from_leg.arrival),
+                    _ => to_leg.arrival.saturating_sub(from_leg.arrival),
+                };
+                elapsed <= limit
+            })
+        })
+        .filter_map(|rule| {
+            let amount = if let Some(product_id) = &rule.fare_product_id {
+                cheapest_product(product_id, data)?.amount
+            } else {
+                0.0
+            };
+            Some((rule, amount))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+// Products can have several rows for different rider categories and fare media. Without knowing the
+// rider, use the cheapest.
+fn cheapest_product<'a>(
+    fare_product_id: &str,
+    data: &'a FareData,
+) -> Option<&'a FareProductRecord> {
+    data.fare_products
+        .iter()
+        .filter(|product| product.fare_product_id == fare_product_id)
+        .min_by(|a, b| a.amount.total_cmp(&b.amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(route_id: &str, departure: u32, from_area: &str, to_area: &str) -> FareLeg {
+        FareLeg {
+            feed_id: Some("feed".to_string()),
+            route_id: Some(route_id.to_string()),
+            departure,
+            arrival: departure + 600,
+            from: StopFareInfo {
+                feed_id: "feed".to_string(),
+                zone_id: None,
+                area_ids: vec![from_area.to_string()],
+            },
+            to: StopFareInfo {
+                feed_id: "feed".to_string(),
+                zone_id: None,
+                area_ids: vec![to_area.to_string()],
+            },
+            zones: vec![],
+        }
+    }
+
+    #[test]
+    fn v1_transfer_covers_second_leg() {
+        let data = FareData {
+            fare_attributes: vec![FareAttributeData {
+                fare_id: "regular".to_string(),
+                price: 2.75,
+                currency: "USD".to_string(),
+                transfers: None,
+                transfer_duration: Some(7200),
+                agency_id: None,
+            }],
+            ..Default::default()
+        };
+        let fare_data = HashMap::from([("feed".to_string(), data)]);
+        let fares = calculate_fares(
+            &[
+                leg("1", 0, "a", "b"),
+                leg("2", 1800, "b", "c"),
+                leg("3", 9600, "c", "d"),
+            ],
+            &fare_data,
+        );
+        let amounts: Vec<f64> = fares
+            .legs
+            .iter()
+            .map(|fare| fare.as_ref().unwrap().amount)
+            .collect();
+        assert_eq!(amounts, vec![2.75, 0.0, 2.75]);
+        assert_eq!(fares.totals.get("USD"), Some(&5.5));
+    }
+
+    #[test]
+    fn v1_rules_match_rows_as_a_whole() {
+        let rule = |origin_id: &str, destination_id: &str| FareRuleData {
+            fare_id: "zonal".to_string(),
+            route_id: None,
+            origin_id: Some(origin_id.to_string()),
+            destination_id: Some(destination_id.to_string()),
+            contains_id: None,
+        };
+        let rules = [rule("1", "2"), rule("3", "4")];
+        let rules: Vec<&FareRuleData> = rules.iter().collect();
+        let zonal_leg = |from_zone: &str, to_zone: &str| {
+            let mut leg = leg("1", 0, "a", "b");
+            leg.from.zone_id = Some(from_zone.to_string());
+            leg.to.zone_id = Some(to_zone.to_string());
+            leg
+        };
+        assert!(v1_rules_match(&[zonal_leg("1", "2")], &rules));
+        assert!(v1_rules_match(&[zonal_leg("3", "4")], &rules));
+        assert!(!v1_rules_match(&[zonal_leg("1", "4")], &rules));
+    }
+
+    #[test]
+    fn v2_transfer_rule_discounts_second_leg() {
+        let product = |id: &str, amount: f64| FareProductRecord {
+            fare_product_id: id.to_string(),
+            fare_product_name: None,
+            rider_category_id: None,
+            fare_media_id: None,
+            amount,
+            currency: "EUR".to_string(),
+        };
+        let data = FareData {
+            fare_products: vec![product("single", 3.0), product("upgrade", 1.0)],
+            fare_leg_rules: vec![FareLegRuleRecord {
+                leg_group_id: Some("city".to_string()),
+                network_id: None,
+                from_area_id: None,
+                to_area_id: None,
+                from_timeframe_group_id: None,
+                to_timeframe_group_id: None,
+                fare_product_id: "single".to_string(),
+                rule_priority: None,
+            }],
+            fare_transfer_rules: vec![FareTransferRuleRecord {
+                from_leg_group_id: Some("city".to_string()),
+                to_leg_group_id: Some("city".to_string()),
+                transfer_count: Some(1),
+                duration_limit: Some(3600),
+                duration_limit_type: Some(1),
+                fare_transfer_type: 0,
+                fare_product_id: Some("upgrade".to_string()),
+            }],
+            ..Default::default()
+        };
+        let fare_data = HashMap::from([("feed".to_string(), data)]);
+        let fares = calculate_fares(
+            &[
+                leg("1", 0, "a", "b"),
+                leg("2", 1200, "b", "c"),
+                leg("3", 2400, "c", "d"),
+            ],
+            &fare_data,
+        );
+        let amounts: Vec<f64> = fares
+            .legs
+            .iter()
+            .map(|fare| fare.as_ref().unwrap().amount)
+            .collect();
+        // Only one transfer is allowed per chain, so the third leg starts a new one.
+        assert_eq!(amounts, vec![3.0, 1.0, 3.0]);
+        assert_eq!(fares.totals.get("EUR"), Some(&7.0));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod api;
+pub mod fare;
+pub mod realtime;
 pub mod route;
 mod spatial;
 pub mod timetable;
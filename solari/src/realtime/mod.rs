@@ -0,0 +1,11 @@
+pub mod proto;
+pub mod replay;
+pub mod vehicle_positions;
+
+use prost::Message;
+
+use proto::FeedMessage;
+
+pub fn decode_feed_message(bytes: &[u8]) -> Result<FeedMessage, anyhow::Error> {
+    Ok(FeedMessage::decode(bytes)?)
+}
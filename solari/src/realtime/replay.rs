@@ -0,0 +1,60 @@
+use std::{fs, path::Path};
+
+use anyhow::bail;
+use tracing::debug;
+
+use crate::timetable::Time;
+
+use super::{decode_feed_message, proto::FeedMessage};
+
+/// A recorded sequence of GTFS-RT snapshots, replayed against a simulated clock.
+///
+/// Snapshots are read from a directory of `<epoch seconds>.pb` files. If a file name isn't a
+/// timestamp, the feed header timestamp is used instead.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotReplay {
+    snapshots: Vec<(Time, FeedMessage)>,
+}
+
+impl SnapshotReplay {
+    pub fn load(dir: &Path) -> Result<SnapshotReplay, anyhow::Error> {
+        let mut snapshots = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "pb") {
+                continue;
+            }
+            let feed = decode_feed_message(&fs::read(&path)?)?;
+            let timestamp = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u32>().ok())
+                .or(feed.header.timestamp.map(|timestamp| timestamp as u32));
+            let timestamp = if let Some(timestamp) = timestamp {
+                timestamp
+            } else {
+                bail!("Can't determine the timestamp of snapshot {:?}", path);
+            };
+            snapshots.push((Time::from_epoch_seconds(timestamp), feed));
+        }
+        snapshots.sort_by_key(|(time, _)| time.epoch_seconds());
+        debug!(
+            "Loaded {} realtime snapshots from {:?}",
+            snapshots.len(),
+            dir
+        );
+        Ok(SnapshotReplay { snapshots })
+    }
+
+    /// The most recent snapshot taken at or before `now`.
+    pub fn snapshot_at(&self, now: Time) -> Option<&FeedMessage> {
+        let idx = self
+            .snapshots
+            .partition_point(|(time, _)| time.epoch_seconds() <= now.epoch_seconds());
+        if idx == 0 {
+            None
+        } else {
+            Some(&self.snapshots[idx - 1].1)
+        }
+    }
+}
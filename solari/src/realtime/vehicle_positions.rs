@@ -0,0 +1,174 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use time::OffsetDateTime;
+
+use crate::{
+    api::{
+        vehicle::{SolariVehiclePosition, VehicleStopStatus},
+        LatLng,
+    },
+    timetable::Time,
+};
+
+use super::proto;
+
+// Positions older than this are assumed to belong to vehicles that have gone out of service.
+static MAX_VEHICLE_AGE_SECONDS: u32 = 10 * 60;
+
+#[derive(Debug, Clone)]
+pub struct MatchedVehicle {
+    pub trip_id: Option<usize>,
+    pub position: SolariVehiclePosition,
+}
+
+#[derive(Debug, Default)]
+struct SourceVehicles {
+    feed_id: String,
+    vehicles: Vec<SolariVehiclePosition>,
+    by_trip: HashMap<usize, usize>,
+    // Indices of vehicles by the GTFS trip ID they report, whether or not it matched a trip.
+    by_gtfs_trip: HashMap<String, usize>,
+}
+
+/// The most recent vehicle positions from each GTFS-RT source, keyed by the Solari trip they were
+/// matched to.
+#[derive(Debug, Default)]
+pub struct VehiclePositionStore {
+    sources: RwLock<HashMap<String, SourceVehicles>>,
+}
+
+impl VehiclePositionStore {
+    pub fn new() -> VehiclePositionStore {
+        VehiclePositionStore::default()
+    }
+
+    /// Replace the vehicles from `source`, a GTFS-RT feed published for the GTFS feed `feed_id`.
+    pub fn replace_source(&self, source: &str, feed_id: &str, vehicles: Vec<MatchedVehicle>) {
+        let mut source_vehicles = SourceVehicles {
+            feed_id: feed_id.to_string(),
+            ..Default::default()
+        };
+        for vehicle in vehicles {
+            let idx = source_vehicles.vehicles.len();
+            if let Some(trip_id) = vehicle.trip_id {
+                source_vehicles.by_trip.insert(trip_id, idx);
+            }
+            if let Some(gtfs_trip_id) = &vehicle.position.trip_id {
+                source_vehicles
+                    .by_gtfs_trip
+                    .entry(gtfs_trip_id.clone())
+                    .or_insert(idx);
+            }
+            source_vehicles.vehicles.push(vehicle.position);
+        }
+        self.sources
+            .write()
+            .expect("Lock poisoned")
+            .insert(source.to_string(), source_vehicles);
+    }
+
+    pub fn clear(&self) {
+        self.sources.write().expect("Lock poisoned").clear();
+    }
+
+    pub fn for_trip(&self, trip_id: usize) -> Option<SolariVehiclePosition> {
+        let sources = self.sources.read().expect("Lock poisoned");
+        sources.values().find_map(|source| {
+            source
+                .by_trip
+                .get(&trip_id)
+                .map(|idx| source.vehicles[*idx].clone())
+        })
+    }
+
+    /// The vehicle serving the trip with the global ID `global_trip_id`, which is
+    /// `<feed_id>:<gtfs trip_id>`.
+    pub fn for_global_trip_id(&self, global_trip_id: &str) -> Option<SolariVehiclePosition> {
+        let sources = self.sources.read().expect("Lock poisoned");
+        sources.values().find_map(|source| {
+            let gtfs_trip_id = global_trip_id
+                .strip_prefix(source.feed_id.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))?;
+            source
+                .by_gtfs_trip
+                .get(gtfs_trip_id)
+                .map(|idx| source.vehicles[*idx].clone())
+        })
+    }
+
+    pub fn in_bbox(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Vec<SolariVehiclePosition> {
+        let sources = self.sources.read().expect("Lock poisoned");
+        sources
+            .values()
+            .flat_map(|source| source.vehicles.iter())
+            .filter(|vehicle| {
+                vehicle.location.lat >= min_lat
+                    && vehicle.location.lat <= max_lat
+                    && vehicle.location.lon >= min_lon
+                    && vehicle.location.lon <= max_lon
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Convert a GTFS-RT vehicle position into its API representation, or return `None` if it has no
+/// position or is stale as of `now`.
+pub(crate) fn vehicle_position_from_feed(
+    vehicle: &proto::VehiclePosition,
+    header_timestamp: Option<u64>,
+    now: Time,
+) -> Option<SolariVehiclePosition> {
+    let position = vehicle.position.as_ref()?;
+    let timestamp = vehicle.timestamp.or(header_timestamp);
+    if let Some(timestamp) = timestamp {
+        if (timestamp as u32).saturating_add(MAX_VEHICLE_AGE_SECONDS) < now.epoch_seconds() {
+            return None;
+        }
+    }
+    let current_status =
+        vehicle
+            .current_status
+            .map(|status| match proto::VehicleStopStatus::try_from(status) {
+                Ok(proto::VehicleStopStatus::IncomingAt) => VehicleStopStatus::IncomingAt,
+                Ok(proto::VehicleStopStatus::StoppedAt) => VehicleStopStatus::StoppedAt,
+                _ => VehicleStopStatus::InTransitTo,
+            });
+    Some(SolariVehiclePosition {
+        location: LatLng {
+            lat: position.latitude as f64,
+            lon: position.longitude as f64,
+            stop: None,
+            global_stop_id: None,
+        },
+        timestamp: timestamp
+            .and_then(|timestamp| OffsetDateTime::from_unix_timestamp(timestamp as i64).ok()),
+        vehicle_id: vehicle
+            .vehicle
+            .as_ref()
+            .and_then(|descriptor| descriptor.id.clone()),
+        vehicle_label: vehicle
+            .vehicle
+            .as_ref()
+            .and_then(|descriptor| descriptor.label.clone()),
+        bearing: position.bearing,
+        speed: position.speed,
+        trip_id: vehicle
+            .trip
+            .as_ref()
+            .and_then(|descriptor| descriptor.trip_id.clone()),
+        route_id: vehicle
+            .trip
+            .as_ref()
+            .and_then(|descriptor| descriptor.route_id.clone()),
+        stop_id: vehicle.stop_id.clone(),
+        current_stop_sequence: vehicle.current_stop_sequence,
+        current_status,
+    })
+}
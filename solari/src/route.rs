@@ -6,38 +6,56 @@ use std::{
     sync::Arc,
 };
 
+use chrono::NaiveDate;
 use geo::ClosestPoint;
 use geo_types::{Coord, Line, LineString, Point};
 use s2::latlng::LatLng;
 use serde::Serialize;
+use solari_bundle::Source;
 use solari_geomath::EARTH_RADIUS_APPROX;
 use solari_spatial::SphereIndexMmap;
-use solari_transfers::{fast_paths::FastGraphStatic, TransferGraph, TransferGraphSearcher};
+use solari_transfers::{
+    fast_paths::FastGraphStatic, TransferGraph, TransferGraphSearcher, TRANSFER_METADATA_FILE,
+};
 use time::OffsetDateTime;
 use tracing::{debug, error, info, trace};
 
 use crate::{
     api::{
+        page::{itinerary_signature, page_cursors, PageCursor, PageDirection},
         response::{ResponseStatus, SolariResponse},
-        SolariItinerary, SolariLeg,
+        vehicle::SolariVehiclePosition,
+        SolariFare, SolariIntermediateStop, SolariItinerary, SolariLeg,
+    },
+    fare::{calculate_fares, FareLeg, ItineraryFare},
+    realtime::{
+        proto::{FeedMessage, TripDescriptor},
+        replay::SnapshotReplay,
+        vehicle_positions::{vehicle_position_from_feed, MatchedVehicle, VehiclePositionStore},
     },
     spatial::FAKE_WALK_SPEED_SECONDS_PER_METER,
     timetable::TripStopTime,
 };
 
-use crate::timetable::{Route, RouteStop, Stop, Time, Timetable, Trip};
+use crate::timetable::{
+    boarding::BoardingIndex, calendar::ServiceCalendar, global_id, Route, RouteStop, Stop, Time,
+    Timetable, Trip, TripMetadata,
+};
+
+const PREVIOUS_PAGE_LOOKBACK_SECONDS: [u32; 4] = [900, 1800, 3600, 7200];
 
 pub struct Router<'a, T: Timetable<'a>> {
     timetable: T,
     transfer_graph: Arc<TransferGraph<FastGraphStatic<'a>, SphereIndexMmap<'a, usize>>>,
+    vehicle_positions: Arc<VehiclePositionStore>,
+    boarding: BoardingIndex,
 }
 
 impl<'a, T: Timetable<'a>> Router<'a, T> {
     pub fn new(timetable: T, transfer_graph_path: PathBuf) -> Result<Router<'a, T>, anyhow::Error> {
         info!("Opening transfer graph metadata db.");
-        let database = Arc::new(redb::Database::open(
-            transfer_graph_path.join("graph_metadata.db"),
-        )?);
+        let database =
+            Arc::new(Source::open(&transfer_graph_path)?.database(TRANSFER_METADATA_FILE)?);
         info!("Opening transfer graph.");
         let transfer_graph = Arc::new(
             TransferGraph::<FastGraphStatic, SphereIndexMmap<usize>>::read_from_dir(
@@ -49,6 +67,128 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         Ok(Router {
             timetable,
             transfer_graph,
+            vehicle_positions: Arc::new(VehiclePositionStore::new()),
+            boarding: BoardingIndex::new(),
+        })
+    }
+
+    /// The location of a stop referenced by its stable `feed_id:stop_id` identifier.
+    pub fn stop_location(&'a self, global_stop_id: &str) -> Option<LatLng> {
+        self.timetable
+            .stop_by_global_id(global_stop_id)
+            .map(|stop| stop.location())
+    }
+
+    pub fn vehicle_positions(&self) -> Arc<VehiclePositionStore> {
+        self.vehicle_positions.clone()
+    }
+
+    /// Replace the vehicle positions previously received from `source` with the ones in `feed`,
+    /// matching each vehicle to the Solari trip it's serving.
+    ///
+    /// GTFS trip IDs are only unique within a feed, so vehicles are only matched to trips from the
+    /// GTFS feed `feed_id`.
+    pub fn update_vehicle_positions(
+        &'a self,
+        source: &str,
+        feed_id: &str,
+        feed: &FeedMessage,
+        now: Time,
+    ) {
+        let vehicles: Vec<MatchedVehicle> = feed
+            .entity
+            .iter()
+            .filter(|entity| !entity.is_deleted.unwrap_or(false))
+            .filter_map(|entity| entity.vehicle.as_ref())
+            .filter_map(|vehicle| {
+                let position = vehicle_position_from_feed(vehicle, feed.header.timestamp, now)?;
+                let seen_at = position
+                    .timestamp
+                    .map(|timestamp| Time::from_epoch_seconds(timestamp.unix_timestamp() as u32))
+                    .unwrap_or(now);
+                let trip_id = vehicle
+                    .trip
+                    .as_ref()
+                    .and_then(|descriptor| self.match_trip(feed_id, descriptor, seen_at))
+                    .map(|trip| trip.id());
+                Some(MatchedVehicle { trip_id, position })
+            })
+            .collect();
+        debug!(
+            "Matched {} of {} vehicles from {} to trips",
+            vehicles
+                .iter()
+                .filter(|vehicle| vehicle.trip_id.is_some())
+                .count(),
+            vehicles.len(),
+            source
+        );
+        self.vehicle_positions
+            .replace_source(source, feed_id, vehicles);
+    }
+
+    /// Replace all vehicle positions with the ones recorded in `replay` for the GTFS feed `feed_id`
+    /// as of the simulated time `now`.
+    pub fn replay_vehicle_positions(&'a self, replay: &SnapshotReplay, feed_id: &str, now: Time) {
+        self.vehicle_positions.clear();
+        if let Some(feed) = replay.snapshot_at(now) {
+            self.update_vehicle_positions("replay", feed_id, feed, now);
+        }
+    }
+
+    fn match_trip(
+        &'a self,
+        feed_id: &str,
+        descriptor: &TripDescriptor,
+        seen_at: Time,
+    ) -> Option<Trip> {
+        let calendar = self.timetable.service_calendar();
+        // Pattern trips are stored once, so consider each service day they run on.
+        let candidates: Vec<Trip> = self
+            .timetable
+            .trips_by_global_id(&global_id(feed_id, descriptor.trip_id.as_ref()?))
+            .into_iter()
+            .flat_map(
+                |trip| match calendar.filter(|calendar| calendar.is_pattern(trip)) {
+                    Some(calendar) => calendar.trip_service_days(trip),
+                    None => vec![*trip],
+                },
+            )
+            .collect();
+        // Solari expands each GTFS trip into one trip per service day, so the start date tells us
+        // which one the vehicle is running.
+        if let Some(start_date) = descriptor
+            .start_date
+            .as_ref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+        {
+            return candidates
+                .into_iter()
+                .find(|trip| trip.metadata(&self.timetable).service_date == Some(start_date));
+        }
+        // Without a start date, pick the trip whose scheduled span is closest to when the vehicle
+        // was seen.
+        candidates.into_iter().min_by_key(|trip| {
+            let stop_count = trip.stop_times(&self.timetable).len();
+            if stop_count == 0 {
+                return u32::MAX;
+            }
+            let start = trip
+                .stop_time(&self.timetable, 0)
+                .departure()
+                .epoch_seconds();
+            let end = trip
+                .stop_time(&self.timetable, stop_count - 1)
+                .arrival()
+                .epoch_seconds();
+            let seen_at = seen_at.epoch_seconds();
+            if seen_at < start {
+                start - seen_at
+            } else if seen_at > end {
+                seen_at - end
+            } else {
+                0
+            }
         })
     }
 
@@ -90,7 +230,26 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         max_candidate_stops_each_side: Option<usize>,
         max_steps: Option<usize>,
         max_step_delta: Option<usize>,
+        languages: &[String],
     ) -> SolariResponse {
+        if let Some(window) = self.timetable.service_window() {
+            let status = if route_start_time.epoch_seconds() < window.valid_from {
+                Some(ResponseStatus::TooEarly)
+            } else if route_start_time.epoch_seconds() >= window.valid_until {
+                Some(ResponseStatus::TooLate)
+            } else {
+                None
+            };
+            if let Some(status) = status {
+                return SolariResponse {
+                    status,
+                    itineraries: vec![],
+                    next_page: None,
+                    previous_page: None,
+                };
+            }
+        }
+
         let start_stops = self.nearest_stops(
             start_location,
             max_candidate_stops_each_side,
@@ -107,9 +266,7 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
             .map(|stop| {
                 (
                     stop.id(),
-                    (FAKE_WALK_SPEED_SECONDS_PER_METER
-                        * stop.location().distance(&target_location).rad()
-                        * EARTH_RADIUS_APPROX) as u32,
+                    access_seconds(&self.timetable, stop, &target_location),
                 )
             })
             .collect();
@@ -119,6 +276,7 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
             marked_stops: Vec::new(),
             marked_routes: Vec::new(),
             timetable: &self.timetable,
+            boarding: &self.boarding,
             targets: target_costs.clone(),
             max_steps,
             max_step_delta,
@@ -149,16 +307,134 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                     &target_costs,
                     start_location,
                     target_location,
+                    languages,
                 )
             })
-            .collect();
+            .collect::<Vec<_>>();
 
         SolariResponse {
-            status: ResponseStatus::Ok,
+            status: if best_itineraries.is_empty() {
+                ResponseStatus::NoRouteFound
+            } else {
+                ResponseStatus::Ok
+            },
             itineraries: best_itineraries,
+            next_page: None,
+            previous_page: None,
         }
     }
 
+    /// Like `route`, but resumes from a page cursor if one is given and fills in cursors for the
+    /// pages before and after the returned itineraries.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn route_page(
+        &'a self,
+        cursor: Option<&PageCursor>,
+        route_start_time: Time,
+        start_location: LatLng,
+        target_location: LatLng,
+        max_distance_meters: Option<f64>,
+        max_candidate_stops_each_side: Option<usize>,
+        max_steps: Option<usize>,
+        max_step_delta: Option<usize>,
+        languages: &[String],
+    ) -> SolariResponse {
+        let route_at = |time: Time| {
+            self.route(
+                time,
+                start_location,
+                target_location,
+                max_distance_meters,
+                max_candidate_stops_each_side,
+                max_steps,
+                max_step_delta,
+                languages,
+            )
+        };
+        let mut response = match cursor {
+            None => route_at(route_start_time).await,
+            Some(cursor) if cursor.direction == PageDirection::Next => {
+                let mut response = route_at(Time::from_epoch_seconds(cursor.time)).await;
+                response
+                    .itineraries
+                    .retain(|itinerary| !cursor.seen.contains(&itinerary_signature(itinerary)));
+                response
+            }
+            Some(cursor) => {
+                // RAPTOR only searches forward in time, so look for earlier itineraries by
+                // starting further and further back until something turns up.
+                let mut response = SolariResponse {
+                    status: ResponseStatus::NoRouteFound,
+                    itineraries: vec![],
+                    next_page: None,
+                    previous_page: None,
+                };
+                for lookback in PREVIOUS_PAGE_LOOKBACK_SECONDS {
+                    let start = Time::from_epoch_seconds(cursor.time.saturating_sub(lookback));
+                    let mut earlier = route_at(start).await;
+                    earlier.itineraries.retain(|itinerary| {
+                        itinerary.end_time.unix_timestamp() < cursor.time as i64
+                            && !cursor.seen.contains(&itinerary_signature(itinerary))
+                    });
+                    if earlier.status == ResponseStatus::TooEarly || !earlier.itineraries.is_empty()
+                    {
+                        response = earlier;
+                        break;
+                    }
+                }
+                response
+            }
+        };
+        if response.itineraries.is_empty() {
+            if response.status == ResponseStatus::Ok {
+                response.status = ResponseStatus::NoRouteFound;
+            }
+            return response;
+        }
+
+        let (next_page, previous_page) = page_cursors(&response.itineraries, |itinerary| {
+            self.latest_departure(itinerary)
+        });
+        response.next_page = Some(next_page.encode());
+        response.previous_page = Some(previous_page.encode());
+        response
+    }
+
+    // The latest time someone could leave the origin and still make the itinerary's first
+    // boarding, using the same access times the router starts its search with.
+    fn latest_departure(&'a self, itinerary: &SolariItinerary) -> i64 {
+        let origin =
+            LatLng::from_degrees(itinerary.start_location.lat, itinerary.start_location.lon);
+        itinerary
+            .legs
+            .iter()
+            .find_map(|leg| match leg {
+                SolariLeg::Transit {
+                    start_time,
+                    start_location: stop,
+                    ..
+                } => {
+                    let access =
+                        match stop.global_stop_id.as_deref().and_then(|global_stop_id| {
+                            self.timetable.stop_by_global_id(global_stop_id)
+                        }) {
+                            Some(stop) => access_seconds(&self.timetable, stop, &origin),
+                            None => {
+                                (FAKE_WALK_SPEED_SECONDS_PER_METER
+                                    * origin
+                                        .distance(&LatLng::from_degrees(stop.lat, stop.lon))
+                                        .rad()
+                                    * EARTH_RADIUS_APPROX) as u32
+                            }
+                        };
+                    Some(start_time.unix_timestamp() - access as i64)
+                }
+                SolariLeg::Transfer { .. } => None,
+            })
+            .unwrap_or(itinerary.start_time.unix_timestamp())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn unwind_itinerary(
         &'a self,
         context: &RouterContext<'a, T>,
@@ -167,6 +443,7 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         target_costs: &[(usize, u32)],
         start_location: LatLng,
         target_location: LatLng,
+        languages: &[String],
     ) -> SolariItinerary {
         let mut steps = vec![];
         let mut step_cursor = itinerary.last_step;
@@ -190,7 +467,7 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                 .expect("Target cost not found");
             steps.push((
                 Step::End(EndStep {
-                    last_stop: from.metadata(&self.timetable).name.clone(),
+                    last_stop: self.stop_name(from, languages),
                     last_stop_latlng: [from_location.lat.deg(), from_location.lng.deg()],
                     last_stop_departure_epoch_seconds: step.arrival.epoch_seconds() as u64,
                     end_latlng: [target_location.lat.deg(), target_location.lng.deg()],
@@ -217,9 +494,11 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
             steps.push((
                 if step.route.is_none() {
                     Step::Transfer(TransferStep {
-                        from_stop: from.metadata(&self.timetable).name.clone(),
+                        from_stop: self.stop_name(from, languages),
+                        from_stop_global_id: from.global_id(&self.timetable),
                         from_stop_latlng: [from_location.lat.deg(), from_location.lng.deg()],
-                        to_stop: to.metadata(&self.timetable).name.clone(),
+                        to_stop: self.stop_name(to, languages),
+                        to_stop_global_id: to.global_id(&self.timetable),
                         to_stop_latlng: [to_location.lat.deg(), to_location.lng.deg()],
                         departure_epoch_seconds: step.departure.epoch_seconds() as u64,
                         arrival_epoch_seconds: step.arrival.epoch_seconds() as u64,
@@ -229,27 +508,51 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                     let from_location = from.location();
 
                     let shape = self.clip_shape(step);
+                    let intermediate_stop_times = self.intermediate_stop_times(step);
+                    // Look up every stop of the leg at once, rather than one at a time.
+                    let mut stops: Vec<&Stop> = intermediate_stop_times
+                        .iter()
+                        .map(|(stop, _)| *stop)
+                        .collect();
+                    stops.push(from);
+                    stops.push(to);
+                    let mut stop_metadata = self.timetable.stop_metadata_batch(&stops);
+                    let to_metadata = stop_metadata.pop().unwrap();
+                    let from_metadata = stop_metadata.pop().unwrap();
+                    let intermediate_stops = intermediate_stop_times
+                        .iter()
+                        .zip(stop_metadata)
+                        .map(|((stop, stop_time), metadata)| {
+                            self.intermediate_stop(stop, metadata, stop_time, languages)
+                        })
+                        .collect();
+                    let trip = step.trip.unwrap();
+                    let trip_metadata = self.localized_trip_metadata(&trip, languages);
+                    let fare_leg =
+                        self.fare_leg(step, &trip_metadata, from, to, &intermediate_stop_times);
 
                     Step::Trip(TripStep {
-                        on_route: step
-                            .trip
-                            .unwrap()
-                            .metadata(&self.timetable)
-                            .route_name
-                            .clone(),
-                        agency: step
-                            .trip
-                            .unwrap()
-                            .metadata(&self.timetable)
-                            .agency_name
-                            .clone(),
-                        departure_stop: from.metadata(&self.timetable).name.clone(),
+                        on_route: trip_metadata.route_name.clone(),
+                        agency: trip_metadata.agency_name.clone(),
+                        departure_stop: self
+                            .translated_stop_name(from, languages)
+                            .or(from_metadata.name),
+                        departure_stop_id: from_metadata.id,
+                        departure_stop_global_id: from.global_id(&self.timetable),
                         departure_stop_latlng: [from_location.lat.deg(), from_location.lng.deg()],
                         departure_epoch_seconds: step.departure.epoch_seconds() as u64,
-                        arrival_stop: to.metadata(&self.timetable).name.clone(),
+                        arrival_stop: self
+                            .translated_stop_name(to, languages)
+                            .or(to_metadata.name),
+                        arrival_stop_id: to_metadata.id,
+                        arrival_stop_global_id: to.global_id(&self.timetable),
                         arrival_stop_latlng: [to_location.lat.deg(), to_location.lng.deg()],
                         arrival_epoch_seconds: step.arrival.epoch_seconds() as u64,
                         shape,
+                        intermediate_stops,
+                        trip_metadata,
+                        fare_leg,
+                        vehicle: self.vehicle_positions.for_trip(trip.id()),
                     })
                 },
                 step_cursor,
@@ -261,6 +564,19 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         } else {
             panic!("First step is not a Begin step.");
         };
+        let fare_legs: Vec<FareLeg> = steps
+            .iter()
+            .rev()
+            .filter_map(|(step, _)| match step {
+                Step::Trip(trip) => Some(trip.fare_leg.clone()),
+                _ => None,
+            })
+            .collect();
+        let ItineraryFare {
+            legs: leg_fares,
+            totals: fare_totals,
+        } = self.calculate_fares(&fare_legs);
+        let mut leg_fares = leg_fares.into_iter();
         let transfer_graph = self.transfer_graph.clone();
         let mut search_context = TransferGraphSearcher::new(transfer_graph);
         let legs = steps
@@ -280,15 +596,38 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                         lat: trip.departure_stop_latlng[0],
                         lon: trip.departure_stop_latlng[1],
                         stop: trip.departure_stop.clone(),
+                        global_stop_id: trip.departure_stop_global_id.clone(),
                     },
                     end_location: crate::api::LatLng {
                         lat: trip.arrival_stop_latlng[0],
                         lon: trip.arrival_stop_latlng[1],
                         stop: trip.arrival_stop.clone(),
+                        global_stop_id: trip.arrival_stop_global_id.clone(),
                     },
                     transit_route: trip.on_route.clone(),
                     transit_agency: trip.agency.clone(),
                     route_shape: trip.shape.clone(),
+                    intermediate_stops: trip.intermediate_stops.clone(),
+                    trip_id: trip.trip_metadata.trip_id.clone(),
+                    route_id: trip.trip_metadata.route_id.clone(),
+                    feed_id: trip.trip_metadata.feed_id.clone(),
+                    global_trip_id: trip.trip_metadata.global_trip_id(),
+                    global_route_id: trip.trip_metadata.global_route_id(),
+                    route_long_name: trip.trip_metadata.route_long_name.clone(),
+                    route_color: trip.trip_metadata.route_color.clone(),
+                    route_text_color: trip.trip_metadata.route_text_color.clone(),
+                    route_type: trip.trip_metadata.route_type,
+                    headsign: trip.trip_metadata.headsign.clone(),
+                    start_stop_id: Some(trip.departure_stop_id.clone()),
+                    start_stop_name: trip.departure_stop.clone(),
+                    end_stop_id: Some(trip.arrival_stop_id.clone()),
+                    end_stop_name: trip.arrival_stop.clone(),
+                    fare: leg_fares.next().flatten().map(|fare| SolariFare {
+                        amount: fare.amount,
+                        currency: fare.currency,
+                        fare_id: Some(fare.fare_id),
+                    }),
+                    vehicle: trip.vehicle.clone(),
                 }),
                 Step::Transfer(transfer) => {
                     let from_coord = Coord {
@@ -299,18 +638,18 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                         y: transfer.to_stop_latlng[0],
                         x: transfer.to_stop_latlng[1],
                     };
-                    let transfer_shape = match self.transfer_graph.transfer_path(
+                    let (transfer_shape, maneuvers) = match self.transfer_graph.transfer_path(
                         &mut search_context,
                         &from_coord,
                         &to_coord,
                     ) {
-                        Ok(transfer_path) => Some(transfer_path.shape),
+                        Ok(transfer_path) => (Some(transfer_path.shape), transfer_path.maneuvers),
                         Err(err) => {
                             error!(
                                 "Failed to calculate transfer path: {}, step: {:?}",
                                 err, transfer
                             );
-                            None
+                            (None, vec![])
                         }
                     };
                     Some(SolariLeg::Transfer {
@@ -326,13 +665,16 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                             lat: transfer.from_stop_latlng[0],
                             lon: transfer.from_stop_latlng[1],
                             stop: transfer.from_stop.clone(),
+                            global_stop_id: transfer.from_stop_global_id.clone(),
                         },
                         end_location: crate::api::LatLng {
                             lat: transfer.to_stop_latlng[0],
                             lon: transfer.to_stop_latlng[1],
                             stop: transfer.to_stop.clone(),
+                            global_stop_id: transfer.to_stop_global_id.clone(),
                         },
                         route_shape: transfer_shape,
+                        maneuvers,
                     })
                 }
                 _ => None,
@@ -343,11 +685,13 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
                 lat: start_location.lat.deg(),
                 lon: start_location.lng.deg(),
                 stop: None,
+                global_stop_id: None,
             },
             end_location: crate::api::LatLng {
                 lat: target_location.lat.deg(),
                 lon: target_location.lng.deg(),
                 stop: None,
+                global_stop_id: None,
             },
             start_time:
                 OffsetDateTime::from_unix_timestamp(route_start_time.epoch_seconds() as i64)
@@ -355,6 +699,14 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
             end_time: OffsetDateTime::from_unix_timestamp(end_time as i64)
                 .expect("Invalid Unix timestamp"),
             legs,
+            fares: fare_totals
+                .into_iter()
+                .map(|(currency, amount)| SolariFare {
+                    amount,
+                    currency,
+                    fare_id: None,
+                })
+                .collect(),
         }
     }
 
@@ -451,8 +803,41 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         itineraries
     }
 
+    // The shape between two stops of a route, using the shape indices precomputed at build time.
+    fn sliced_shape(&'a self, step: &InternalStep, route: &Route) -> Option<Vec<Coord>> {
+        let indices = self.timetable.route_shape_indices(route)?;
+        let shape = self.timetable.route_shape(route)?;
+        // Index by the trip's own stop times rather than by stop ID, since loop routes visit some
+        // stops twice.
+        let (stop_times, boarding, alighting) = self.step_stop_times(step)?;
+        let start = *indices.get(stop_times[boarding].route_stop_seq())? as usize;
+        let end = *indices.get(stop_times[alighting].route_stop_seq())? as usize;
+        if start > end || end >= shape.len() {
+            return None;
+        }
+        let mut coords = Vec::with_capacity(end - start + 2);
+        coords.push(Coord {
+            x: step.from.latlng().lng.deg(),
+            y: step.from.latlng().lat.deg(),
+        });
+        coords.extend(shape[start + 1..=end].iter().map(|coord| Coord {
+            x: coord.lon(),
+            y: coord.lat(),
+        }));
+        coords.push(Coord {
+            x: step.to.latlng().lng.deg(),
+            y: step.to.latlng().lat.deg(),
+        });
+        coords.dedup();
+        Some(coords)
+    }
+
     fn clip_shape(&'a self, step: &InternalStep) -> Option<String> {
         if let Some(route) = &step.route {
+            if let Some(coords) = self.sliced_shape(step, route) {
+                return polyline::encode_coordinates(LineString::new(coords), 5).ok();
+            }
+            // Timetables built without shape indices.
             if let Some(shape) = self.timetable.route_shape(route) {
                 let departure_stop_distance = if let InternalStepLocation::Stop(stop) = step.from {
                     route
@@ -530,6 +915,162 @@ impl<'a, T: Timetable<'a>> Router<'a, T> {
         None
     }
 
+    // The trip's stop times for a transit step, along with the positions in them of the stop times
+    // the step boards and alights at.
+    fn step_stop_times(&'a self, step: &InternalStep) -> Option<(Vec<TripStopTime>, usize, usize)> {
+        let (trip, route, from, to) = match (step.trip, &step.route, &step.from, &step.to) {
+            (
+                Some(trip),
+                Some(route),
+                InternalStepLocation::Stop(from),
+                InternalStepLocation::Stop(to),
+            ) => (trip, route, from, to),
+            _ => return None,
+        };
+        let route_stops = route.route_stops(&self.timetable);
+        let stop_times: Vec<TripStopTime> = (0..trip.stop_times(&self.timetable).len())
+            .map(|stop_seq| trip.stop_time(&self.timetable, stop_seq))
+            .collect();
+        // Routes can visit the same stop more than once, so match on times as well as stops.
+        let boarding = stop_times.iter().position(|stop_time| {
+            route_stops[stop_time.route_stop_seq()].id() == from.id()
+                && stop_time.departure() == step.departure
+        })?;
+        let alighting = boarding
+            + 1
+            + stop_times[boarding + 1..].iter().position(|stop_time| {
+                route_stops[stop_time.route_stop_seq()].id() == to.id()
+                    && stop_time.arrival() == step.arrival
+            })?;
+        Some((stop_times, boarding, alighting))
+    }
+
+    fn intermediate_stop_times(&'a self, step: &InternalStep) -> Vec<(&'a Stop, TripStopTime)> {
+        let (route, (stop_times, boarding, alighting)) =
+            match (&step.route, self.step_stop_times(step)) {
+                (Some(route), Some(step_stop_times)) => (route, step_stop_times),
+                _ => return vec![],
+            };
+        let route_stops = route.route_stops(&self.timetable);
+        stop_times[boarding + 1..alighting]
+            .iter()
+            .map(|stop_time| {
+                (
+                    route_stops[stop_time.route_stop_seq()].stop(&self.timetable),
+                    *stop_time,
+                )
+            })
+            .collect()
+    }
+
+    fn intermediate_stop(
+        &'a self,
+        stop: &Stop,
+        metadata: gtfs_structures::Stop,
+        stop_time: &TripStopTime,
+        languages: &[String],
+    ) -> SolariIntermediateStop {
+        let name = self.translated_stop_name(stop, languages).or(metadata.name);
+        let location = stop.location();
+        SolariIntermediateStop {
+            stop_id: metadata.id,
+            location: crate::api::LatLng {
+                lat: location.lat.deg(),
+                lon: location.lng.deg(),
+                stop: name,
+                global_stop_id: stop.global_id(&self.timetable),
+            },
+            arrival_time: OffsetDateTime::from_unix_timestamp(
+                stop_time.arrival().epoch_seconds() as i64
+            )
+            .expect("Invalid Unix timestamp"),
+            departure_time: OffsetDateTime::from_unix_timestamp(
+                stop_time.departure().epoch_seconds() as i64,
+            )
+            .expect("Invalid Unix timestamp"),
+        }
+    }
+
+    // The stop's name in the first of `languages` translations.txt covers, or its default name.
+    fn stop_name(&'a self, stop: &Stop, languages: &[String]) -> Option<String> {
+        self.translated_stop_name(stop, languages)
+            .or_else(|| stop.metadata(&self.timetable).name)
+    }
+
+    fn translated_stop_name(&'a self, stop: &Stop, languages: &[String]) -> Option<String> {
+        if languages.is_empty() {
+            return None;
+        }
+        self.timetable
+            .stop_translations(stop)
+            .and_then(|translations| translations.get("stop_name", languages).map(str::to_string))
+    }
+
+    fn localized_trip_metadata(&'a self, trip: &Trip, languages: &[String]) -> TripMetadata {
+        let mut metadata = trip.metadata(&self.timetable);
+        if languages.is_empty() {
+            return metadata;
+        }
+        if let Some(translations) = self.timetable.trip_translations(trip) {
+            if let Some(headsign) = translations.get("trip_headsign", languages) {
+                metadata.headsign = Some(headsign.to_string());
+            }
+        }
+        let route = trip.route(&self.timetable);
+        if let Some(translations) = self.timetable.route_translations(&route) {
+            if let Some(short_name) = translations.get("route_short_name", languages) {
+                metadata.route_name = Some(short_name.to_string());
+            }
+            if let Some(long_name) = translations.get("route_long_name", languages) {
+                metadata.route_long_name = Some(long_name.to_string());
+            }
+        }
+        metadata
+    }
+
+    fn fare_leg(
+        &'a self,
+        step: &InternalStep,
+        trip_metadata: &TripMetadata,
+        from: &Stop,
+        to: &Stop,
+        intermediate_stop_times: &[(&'a Stop, TripStopTime)],
+    ) -> FareLeg {
+        let from = self.timetable.stop_fare_info(from).unwrap_or_default();
+        let to = self.timetable.stop_fare_info(to).unwrap_or_default();
+        let zones = std::iter::once(from.zone_id.clone())
+            .chain(intermediate_stop_times.iter().map(|(stop, _)| {
+                self.timetable
+                    .stop_fare_info(stop)
+                    .and_then(|info| info.zone_id)
+            }))
+            .chain(std::iter::once(to.zone_id.clone()))
+            .flatten()
+            .collect();
+        FareLeg {
+            feed_id: trip_metadata.feed_id.clone(),
+            route_id: trip_metadata.route_id.clone(),
+            departure: step.departure.epoch_seconds(),
+            arrival: step.arrival.epoch_seconds(),
+            from,
+            to,
+            zones,
+        }
+    }
+
+    fn calculate_fares(&'a self, legs: &[FareLeg]) -> ItineraryFare {
+        let mut fare_data = HashMap::new();
+        for feed_id in legs.iter().filter_map(|leg| leg.feed_id.as_ref()) {
+            if fare_data.contains_key(feed_id) {
+                continue;
+            }
+            if let Some(data) = self.timetable.fare_data(feed_id) {
+                fare_data.insert(feed_id.clone(), data);
+            }
+        }
+        calculate_fares(legs, &fare_data)
+    }
+
     fn closest_point(target: &Point, points: &Vec<Coord>) -> Option<(usize, Point)> {
         let (idx, closest) = points
             .windows(2)
@@ -592,19 +1133,30 @@ pub struct TripStep {
     pub on_route: Option<String>,
     pub agency: Option<String>,
     pub departure_stop: Option<String>,
+    pub departure_stop_id: String,
+    pub departure_stop_global_id: Option<String>,
     pub departure_stop_latlng: [f64; 2],
     pub departure_epoch_seconds: u64,
     pub arrival_stop: Option<String>,
+    pub arrival_stop_id: String,
+    pub arrival_stop_global_id: Option<String>,
     pub arrival_stop_latlng: [f64; 2],
     pub arrival_epoch_seconds: u64,
     pub shape: Option<String>,
+    pub intermediate_stops: Vec<SolariIntermediateStop>,
+    pub trip_metadata: TripMetadata,
+    #[serde(skip)]
+    pub fare_leg: FareLeg,
+    pub vehicle: Option<SolariVehiclePosition>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TransferStep {
     pub from_stop: Option<String>,
+    pub from_stop_global_id: Option<String>,
     pub from_stop_latlng: [f64; 2],
     pub to_stop: Option<String>,
+    pub to_stop_global_id: Option<String>,
     pub to_stop_latlng: [f64; 2],
     pub departure_epoch_seconds: u64,
     pub arrival_epoch_seconds: u64,
@@ -632,6 +1184,7 @@ pub struct RouterContext<'a, T: Timetable<'a>> {
     marked_stops: Vec<Vec<StopMark>>,
     marked_routes: Vec<RefCell<Vec<TripStopTime>>>,
     timetable: &'a T,
+    boarding: &'a BoardingIndex,
     targets: Vec<(usize, u32)>,
     max_steps: Option<usize>,
     max_step_delta: Option<usize>,
@@ -747,14 +1300,7 @@ where
         let start_costs: HashMap<usize, u32> = starts
             .iter()
             .enumerate()
-            .map(|(i, start)| {
-                (
-                    i,
-                    (FAKE_WALK_SPEED_SECONDS_PER_METER
-                        * start.location().distance(&start_location).rad()
-                        * EARTH_RADIUS_APPROX) as u32,
-                )
-            })
+            .map(|(i, start)| (i, access_seconds(self.timetable, start, &start_location)))
             .collect();
         for (stop_option_index, stop) in starts.iter().enumerate() {
             if let Some(cost) = start_costs.get(&stop_option_index) {
@@ -773,18 +1319,27 @@ where
     }
 
     fn earliest_trip_from(&self, route_stop: &RouteStop, not_before: &Time) -> Option<Trip> {
-        let trips = route_stop.route(self.timetable).route_trips(self.timetable);
+        let route = route_stop.route(self.timetable);
+        if let Some(calendar) = pattern_calendar(self.timetable, route) {
+            return calendar.earliest_trip(
+                self.timetable,
+                self.boarding,
+                route,
+                route_stop.stop_seq(),
+                not_before,
+            );
+        }
+        let trips = route.route_trips(self.timetable);
         let position = match trips.binary_search_by_key(not_before, |trip| {
             trip.stop_times(self.timetable)[route_stop.stop_seq()].departure()
         }) {
             Ok(position) => position,
             Err(position) => position,
         };
-        if position >= trips.len() {
-            None
-        } else {
-            Some(trips[position])
-        }
+        // Skip trips that don't pick up here, like express runs that only drop off.
+        self.boarding
+            .first_boardable(self.timetable, route, route_stop.stop_seq(), position)
+            .map(|position| trips[position])
     }
 
     async fn do_round(&mut self, round: u32) -> bool {
@@ -820,6 +1375,7 @@ where
                     *stop_marked = StopMark::MarkedForTransfersOnly;
                     Self::explore_routes_for_marked_stop(
                         self.timetable,
+                        self.boarding,
                         &mut *new_marked_routes,
                         self.timetable.stop(stop_id),
                         &self.best_times_per_round[round as usize - 1][stop_id]
@@ -840,12 +1396,12 @@ where
                     .enumerate()
                     .collect();
                 // Sort the marked routes for deterministic ordering.
-                marked_routes.sort_by_key(|(_, trip_stop_time)| trip_stop_time.route_stop_seq);
+                marked_routes.sort_by_key(|(_, trip_stop_time)| trip_stop_time.route_stop_seq());
                 // Drop mutability.
                 let marked_routes = marked_routes;
 
                 for (route_id, departure) in marked_routes {
-                    if departure.trip_index == usize::MAX {
+                    if departure.is_marked() {
                         continue;
                     }
                     let route = self.timetable.route(route_id);
@@ -863,7 +1419,7 @@ where
                         }
                         if let Some((current_trip, current_trip_start)) = &mut current_trip {
                             let departure_trip_stop_time =
-                                &current_trip.stop_times(self.timetable)[departure_stop_seq];
+                                current_trip.stop_time(self.timetable, departure_stop_seq);
                             let previous_step = if let Some(previous_step) = self
                                 .best_times_per_round[round as usize - 1]
                                 [departure.route_stop(self.timetable).id()]
@@ -878,19 +1434,23 @@ where
                                 );
                                 continue;
                             };
-                            if self.maybe_update_arrival_time_and_route(
-                                round,
-                                &InternalStepLocation::Stop(
-                                    current_trip_start.stop(self.timetable),
-                                ),
-                                departure_trip_stop_time.departure(),
-                                &InternalStepLocation::Stop(route_stop.stop(self.timetable)),
-                                current_trip.stop_times(self.timetable)[route_stop.stop_seq()]
-                                    .arrival(),
-                                Some(current_trip.route(self.timetable)),
-                                Some(current_trip.clone()),
-                                previous_step,
-                            ) {
+                            // Riding past a stop that's pickup-only doesn't let us get off there.
+                            let trip_stop_time =
+                                current_trip.stop_time(self.timetable, route_stop.stop_seq());
+                            if trip_stop_time.can_alight()
+                                && self.maybe_update_arrival_time_and_route(
+                                    round,
+                                    &InternalStepLocation::Stop(
+                                        current_trip_start.stop(self.timetable),
+                                    ),
+                                    departure_trip_stop_time.departure(),
+                                    &InternalStepLocation::Stop(route_stop.stop(self.timetable)),
+                                    trip_stop_time.arrival(),
+                                    Some(current_trip.route(self.timetable)),
+                                    Some(current_trip.clone()),
+                                    previous_step,
+                                )
+                            {
                                 marked_stops_count += 1;
 
                                 if let Some(trip) = self.earliest_trip_from(
@@ -901,7 +1461,8 @@ where
                                     .unwrap()
                                     .final_time,
                                 ) {
-                                    if trip.stop_times(self.timetable)[route_stop.stop_seq()]
+                                    if trip
+                                        .stop_time(self.timetable, route_stop.stop_seq())
                                         .arrival()
                                         < self.best_times_per_round[round as usize - 1]
                                             [departure.route_stop(self.timetable).id()]
@@ -989,24 +1550,43 @@ where
 
     fn explore_routes_for_marked_stop(
         timetable: &'a T,
+        boarding: &BoardingIndex,
         marked_routes: &mut [TripStopTime],
         marked_stop: &Stop,
         not_before: &Time,
     ) {
         for stop_route in marked_stop.stop_routes(timetable) {
             let route = stop_route.route(timetable);
-            if marked_routes[route.id()].trip_index == usize::MAX {
+            if let Some(calendar) = pattern_calendar(timetable, route) {
+                // Trips on different service days aren't ordered by index, so ask the calendar.
+                if let Some(trip) = calendar.earliest_trip(
+                    timetable,
+                    boarding,
+                    route,
+                    stop_route.stop_seq(),
+                    not_before,
+                ) {
+                    let trip_stop_time = trip.stop_time(timetable, stop_route.stop_seq());
+                    if trip_stop_time.departure() < marked_routes[route.id()].departure()
+                        || (trip_stop_time.departure() == marked_routes[route.id()].departure()
+                            && trip_stop_time.route_stop_seq()
+                                > marked_routes[route.id()].route_stop_seq())
+                    {
+                        marked_routes[route.id()] = trip_stop_time;
+                    }
+                }
+            } else if marked_routes[route.id()].is_marked() {
                 for trip in route.route_trips(timetable) {
                     let trip_stop_time = &trip.stop_times(timetable)[stop_route.stop_seq()];
-                    if &trip_stop_time.departure() < &not_before {
+                    if &trip_stop_time.departure() < &not_before || !trip_stop_time.can_board() {
                         continue;
                     }
 
                     // The clause after the && here is included for determinism. It specifies that we will prefer getting onto a vehicle later rather than earlier if we can do so at multiple locations.
                     if trip_stop_time.departure() < marked_routes[route.id()].departure()
                         || (trip_stop_time.departure() == marked_routes[route.id()].departure()
-                            && trip_stop_time.route_stop_seq
-                                > marked_routes[route.id()].route_stop_seq)
+                            && trip_stop_time.route_stop_seq()
+                                > marked_routes[route.id()].route_stop_seq())
                     {
                         marked_routes[route.id()] = *trip_stop_time;
                         // Any trips after this one do not need to be examined.
@@ -1015,7 +1595,7 @@ where
                 }
             } else {
                 for trip in route.route_trips(timetable)
-                    [0..=(marked_routes[route.id()].trip_index - route.first_route_trip)]
+                    [0..=(marked_routes[route.id()].trip_id() - route.first_route_trip())]
                     .iter()
                     .rev()
                 {
@@ -1024,12 +1604,15 @@ where
                         // We are iterating in reverse, so nothing "after" this (before, temporally) needs to be examined.
                         break;
                     }
+                    if !trip_stop_time.can_board() {
+                        continue;
+                    }
 
                     // The clause after the && here is included for determinism. It specifies that we will prefer getting onto a vehicle later rather than earlier if we can do so at multiple locations.
                     if trip_stop_time.departure() < marked_routes[route.id()].departure()
                         || (trip_stop_time.departure() == marked_routes[route.id()].departure()
-                            && trip_stop_time.route_stop_seq
-                                > marked_routes[route.id()].route_stop_seq)
+                            && trip_stop_time.route_stop_seq()
+                                > marked_routes[route.id()].route_stop_seq())
                     {
                         marked_routes[route.id()] = *trip_stop_time;
                         // We are iterating in reverse, so we can't break here.
@@ -1060,3 +1643,38 @@ where
         }
     }
 }
+
+// Walking time between a location and a stop. Stops in stations with mapped entrances are reached
+// through the nearest entrance rather than in a straight line to the platform.
+fn access_seconds<'a, T: Timetable<'a>>(timetable: &'a T, stop: &Stop, location: &LatLng) -> u32 {
+    let walk_seconds = |to: LatLng| {
+        (FAKE_WALK_SPEED_SECONDS_PER_METER * to.distance(location).rad() * EARTH_RADIUS_APPROX)
+            as u32
+    };
+    timetable
+        .stop_station_info(stop)
+        .and_then(|station_info| {
+            station_info
+                .entrances
+                .iter()
+                .map(|entrance| {
+                    walk_seconds(LatLng::from_degrees(entrance.lat, entrance.lon))
+                        + entrance.seconds
+                })
+                .min()
+        })
+        .unwrap_or_else(|| walk_seconds(stop.location()))
+}
+
+// The calendar of a route stored as trip patterns, or None if its trips were expanded per day.
+fn pattern_calendar<'a, T: Timetable<'a>>(
+    timetable: &'a T,
+    route: &Route,
+) -> Option<&'a ServiceCalendar> {
+    let calendar = timetable.service_calendar()?;
+    route
+        .route_trips(timetable)
+        .first()
+        .is_some_and(|trip| calendar.is_pattern(trip))
+        .then_some(calendar)
+}
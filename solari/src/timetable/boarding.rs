@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use super::{metadata_cache::MetadataCache, Route, Timetable};
+
+const BOARDING_CACHE_CAPACITY: usize = 1 << 18;
+
+/// Which of a route's trips pick up at each of its stops, so finding the next trip to board at a
+/// stop some trips only drop off at is a binary search rather than a scan. Built lazily per route
+/// stop.
+pub struct BoardingIndex {
+    // Positions in the route's trips of the ones that pick up, or None if they all do.
+    boardable: MetadataCache<Option<Arc<[u32]>>>,
+}
+
+impl Default for BoardingIndex {
+    fn default() -> Self {
+        BoardingIndex::new()
+    }
+}
+
+impl BoardingIndex {
+    pub fn new() -> BoardingIndex {
+        BoardingIndex {
+            boardable: MetadataCache::new(BOARDING_CACHE_CAPACITY),
+        }
+    }
+
+    /// The position in `route`'s trips of the first one at or after `position` that picks up at
+    /// `stop_seq`.
+    pub fn first_boardable<'a>(
+        &self,
+        timetable: &'a dyn Timetable<'a>,
+        route: &Route,
+        stop_seq: usize,
+        position: usize,
+    ) -> Option<usize> {
+        let trips = route.route_trips(timetable);
+        let key = (route.id() as u64) << 32 | stop_seq as u64;
+        let boardable = self.boardable.get_or_insert_with(key, || {
+            boardable_positions(
+                trips
+                    .iter()
+                    .map(|trip| trip.stop_times(timetable)[stop_seq].can_board()),
+            )
+        });
+        first_at_or_after(boardable.as_deref(), trips.len(), position)
+    }
+}
+
+fn boardable_positions(can_board: impl Iterator<Item = bool>) -> Option<Arc<[u32]>> {
+    let mut all = true;
+    let mut positions = vec![];
+    for (position, can_board) in can_board.enumerate() {
+        if can_board {
+            positions.push(position as u32);
+        } else {
+            all = false;
+        }
+    }
+    if all {
+        None
+    } else {
+        Some(positions.into())
+    }
+}
+
+fn first_at_or_after(
+    boardable: Option<&[u32]>,
+    trip_count: usize,
+    position: usize,
+) -> Option<usize> {
+    match boardable {
+        None => (position < trip_count).then_some(position),
+        Some(boardable) => boardable
+            .get(boardable.partition_point(|boardable| (*boardable as usize) < position))
+            .map(|boardable| *boardable as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_trips_that_dont_pick_up() {
+        let boardable = boardable_positions([true, false, false, true, false].into_iter());
+        assert_eq!(boardable.as_deref(), Some(&[0, 3][..]));
+        assert_eq!(first_at_or_after(boardable.as_deref(), 5, 0), Some(0));
+        assert_eq!(first_at_or_after(boardable.as_deref(), 5, 1), Some(3));
+        assert_eq!(first_at_or_after(boardable.as_deref(), 5, 3), Some(3));
+        assert_eq!(first_at_or_after(boardable.as_deref(), 5, 4), None);
+
+        // A stop that's drop-off only for every trip, like the end of the line.
+        let boardable = boardable_positions([false, false].into_iter());
+        assert_eq!(first_at_or_after(boardable.as_deref(), 2, 0), None);
+
+        let boardable = boardable_positions([true, true].into_iter());
+        assert_eq!(boardable, None);
+        assert_eq!(first_at_or_after(None, 2, 1), Some(1));
+        assert_eq!(first_at_or_after(None, 2, 2), None);
+    }
+}
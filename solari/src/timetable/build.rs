@@ -1,34 +1,40 @@
+use crate::timetable::calendar::StorageMode;
+use crate::timetable::gtfs_extra::GtfsExtra;
 use crate::timetable::in_memory::InMemoryTimetableBuilder;
+use crate::timetable::manifest::{hash_file, Manifest};
 use crate::timetable::mmap::MmapTimetable;
 use anyhow::{bail, Result};
 use chrono::NaiveDate;
 use gtfs_structures::GtfsReader;
 use rayon::prelude::*;
 use std::fs;
-use std::hash::{DefaultHasher, Hasher};
 use std::path::PathBuf;
-use tracing::{debug, error};
+use tracing::{debug, error, info, warn};
 
 fn process_gtfs<'a>(
     path: &PathBuf,
     base_path: &PathBuf,
     start_date: Option<NaiveDate>,
     num_days: Option<u16>,
+    storage_mode: StorageMode,
 ) -> Result<MmapTimetable<'a>, anyhow::Error> {
     let feed = if let Ok(feed) = GtfsReader::default().read_from_path(path.to_str().unwrap()) {
         feed
     } else {
         bail!(format!("Failed to load feed: {:?}", path));
     };
+    let extra = GtfsExtra::read_from_path(path);
     debug!("Processing feed: {:?}", path);
-    let in_memory_timetable_builder = InMemoryTimetableBuilder::new(&feed, start_date, num_days)?;
-    let hash = {
-        let mut hasher = DefaultHasher::new();
-        hasher.write(path.to_str().unwrap().as_bytes());
-        format!("{:x}", hasher.finish())
-    };
+    // Feeds are downloaded to a zip named after their DMFR feed ID.
+    let feed_id = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut in_memory_timetable_builder =
+        InMemoryTimetableBuilder::new(&feed, &extra, &feed_id, start_date, num_days, storage_mode)?;
+    in_memory_timetable_builder.source_hash = Some(hash_file(path)?);
 
-    let timetable_dir = base_path.join(hash);
+    let timetable_dir = base_path.join(&feed_id);
     fs::create_dir_all(&timetable_dir).unwrap();
     Ok(MmapTimetable::from_in_memory(
         &in_memory_timetable_builder,
@@ -45,7 +51,11 @@ pub async fn concat_timetables<'a>(
 
     let timetables: Vec<MmapTimetable<'_>> = paths
         .par_iter()
-        .filter_map(|path| MmapTimetable::open(path).ok())
+        .filter_map(|path| {
+            MmapTimetable::open(path)
+                .map_err(|err| error!("Failed to open timetable {:?}: {}", path, err))
+                .ok()
+        })
         .collect();
 
     // Combine all timetables into one.
@@ -59,6 +69,7 @@ pub async fn timetable_from_feeds<'a>(
     valhalla_tile_path: &PathBuf,
     start_date: Option<NaiveDate>,
     num_days: Option<u16>,
+    storage_mode: StorageMode,
 ) -> Result<MmapTimetable<'a>, anyhow::Error> {
     let paths = paths.to_vec();
 
@@ -66,7 +77,7 @@ pub async fn timetable_from_feeds<'a>(
         .par_iter()
         .filter(|path| path.extension().map(|ext| ext == "zip") == Some(true))
         .filter_map(|path| {
-            process_gtfs(&path, base_path, start_date, num_days)
+            process_gtfs(&path, base_path, start_date, num_days, storage_mode)
                 .map_err(|err| {
                     error!("Failed to process GTFS feed: {}", err);
                     err
@@ -79,3 +90,54 @@ pub async fn timetable_from_feeds<'a>(
     let timetable = MmapTimetable::concatenate(&timetables, base_path, valhalla_tile_path).await;
     Ok(timetable)
 }
+
+/// Rebuild a single feed of a timetable previously built into `base_path` and re-concatenate it
+/// with the other feeds there, using the build parameters the other feeds were built with.
+/// Transfers are only recalculated for stops near ones that were added, moved or removed.
+pub async fn rebuild_feed<'a>(
+    path: &PathBuf,
+    base_path: &PathBuf,
+    valhalla_tile_path: &PathBuf,
+) -> Result<MmapTimetable<'a>, anyhow::Error> {
+    let build = Manifest::read(base_path)?.build;
+
+    // Capture the old transfers before anything overwrites the files backing them.
+    let previous = match MmapTimetable::open(base_path).and_then(|tt| tt.previous_transfers()) {
+        Ok(previous) => Some(previous),
+        Err(err) => {
+            warn!("No previous timetable to reuse transfers from: {}", err);
+            None
+        }
+    };
+
+    info!("Rebuilding feed: {:?}", path);
+    process_gtfs(
+        path,
+        base_path,
+        build.start_date,
+        build.num_days,
+        build.storage_mode,
+    )?;
+
+    let feed_dirs: Vec<PathBuf> = fs::read_dir(base_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    let timetables: Vec<MmapTimetable<'_>> = feed_dirs
+        .par_iter()
+        .filter_map(|path| {
+            MmapTimetable::open(path)
+                .map_err(|err| error!("Failed to open timetable {:?}: {}", path, err))
+                .ok()
+        })
+        .collect();
+
+    let timetable = MmapTimetable::concatenate_reusing_transfers(
+        &timetables,
+        base_path,
+        valhalla_tile_path,
+        previous.as_ref(),
+    )
+    .await;
+    Ok(timetable)
+}
@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use solari_bundle::is_bundle;
+use solari_transfers::{TRANSFER_GRAPH_FILE, TRANSFER_METADATA_FILE, TRANSFER_NODE_INDEX_FILE};
+
+use super::manifest::{Manifest, MANIFEST_FILE};
+
+/// The files a deployment needs, keyed by their section names in a bundle: the timetable's raw
+/// files, metadata database and manifest, then the transfer graph.
+pub fn deployment_files(
+    base_path: &Path,
+    transfer_graph_path: &Path,
+) -> Result<Vec<(String, PathBuf)>, anyhow::Error> {
+    if is_bundle(base_path) {
+        bail!("{:?} is already a bundle", base_path);
+    }
+    let manifest = Manifest::read(base_path)?;
+    manifest.validate(base_path)?;
+    let timetable_files = manifest
+        .files
+        .iter()
+        .map(|file| file.name.as_str())
+        .chain(["metadata.db", MANIFEST_FILE])
+        .map(|name| (name.to_string(), base_path.join(name)));
+    let transfer_graph_files = [
+        TRANSFER_GRAPH_FILE,
+        TRANSFER_NODE_INDEX_FILE,
+        TRANSFER_METADATA_FILE,
+    ]
+    .into_iter()
+    .map(|name| (name.to_string(), transfer_graph_path.join(name)));
+    Ok(timetable_files.chain(transfer_graph_files).collect())
+}
+
+/// Pack a timetable and its transfer graph into a single bundle file, which `MmapTimetable::open`
+/// and `Router::new` can both be pointed at.
+pub fn pack_bundle(
+    base_path: &Path,
+    transfer_graph_path: &Path,
+    bundle_path: &Path,
+) -> Result<(), anyhow::Error> {
+    let files = deployment_files(base_path, transfer_graph_path)?;
+    solari_bundle::pack(bundle_path, &files)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::timetable::manifest::BuildParams;
+
+    #[test]
+    fn packed_timetables_validate_like_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path();
+        for name in [
+            "routes",
+            "route_stops",
+            "route_trips",
+            "stops",
+            "stop_routes",
+            "trip_stop_times",
+            "transfer_index",
+            "transfers",
+            "metadata.db",
+            TRANSFER_GRAPH_FILE,
+            TRANSFER_NODE_INDEX_FILE,
+            TRANSFER_METADATA_FILE,
+        ] {
+            fs::write(base_path.join(name), []).unwrap();
+        }
+        Manifest::describe(&base_path, BuildParams::default(), vec![])
+            .unwrap()
+            .write(&base_path)
+            .unwrap();
+
+        let bundle_path = base_path.join("timetable.bundle");
+        pack_bundle(&base_path, &base_path, &bundle_path).unwrap();
+        let manifest = Manifest::read(&bundle_path).unwrap();
+        assert_eq!(manifest, Manifest::read(&base_path).unwrap());
+        manifest.validate(&bundle_path).unwrap();
+        manifest.verify_hashes(&bundle_path).unwrap();
+        assert!(deployment_files(&bundle_path, &base_path).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use super::{boarding::BoardingIndex, Route, Time, Timetable, Trip};
+
+// GTFS times can run past 24:00:00, so a trip can still be running a couple of days after its
+// service day starts.
+const MAX_TRIP_SPAN_SECONDS: u32 = 3 * 86_400;
+// Marks trips that were expanded into one copy per service day and store absolute times.
+const EXPANDED: u32 = u32::MAX;
+
+/// How trips are laid out in a timetable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMode {
+    /// One copy of every trip per service day, with absolute times.
+    #[default]
+    Expanded,
+    /// One copy of every trip with times relative to its service day, plus a calendar of the days
+    /// it runs on. Boarding resolves the service day at query time.
+    Pattern,
+}
+
+impl FromStr for StorageMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expanded" => Ok(StorageMode::Expanded),
+            "pattern" => Ok(StorageMode::Pattern),
+            _ => bail!("Unknown storage mode {s}, expected \"expanded\" or \"pattern\""),
+        }
+    }
+}
+
+/// The service days of pattern trips, as a bitset per GTFS service.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceCalendar {
+    zones: Vec<CalendarZone>,
+    services: Vec<ServiceDays>,
+    // Indexed by trip.
+    trip_services: Vec<u32>,
+}
+
+// The service days of a feed in one timezone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CalendarZone {
+    start_date: NaiveDate,
+    // Epoch seconds at which each service day starts, noon minus 12 hours local time.
+    day_starts: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ServiceDays {
+    zone: u32,
+    // One bit per day of the zone.
+    days: Vec<u64>,
+}
+
+impl ServiceCalendar {
+    pub(crate) fn add_zone(&mut self, start_date: NaiveDate, day_starts: Vec<u32>) -> u32 {
+        self.zones.push(CalendarZone {
+            start_date,
+            day_starts,
+        });
+        (self.zones.len() - 1) as u32
+    }
+
+    pub(crate) fn add_service(&mut self, zone: u32, days: impl IntoIterator<Item = u16>) -> u32 {
+        let mut bits = vec![0u64; self.zones[zone as usize].day_starts.len().div_ceil(64)];
+        for day in days {
+            if let Some(word) = bits.get_mut(day as usize / 64) {
+                *word |= 1 << (day % 64);
+            }
+        }
+        self.services.push(ServiceDays { zone, days: bits });
+        (self.services.len() - 1) as u32
+    }
+
+    /// Record the service of the next trip in the timetable, or `None` for an expanded trip.
+    pub(crate) fn push_trip(&mut self, service: Option<u32>) {
+        self.trip_services.push(service.unwrap_or(EXPANDED));
+    }
+
+    /// Append the calendar of a timetable being concatenated after the trips already recorded.
+    /// Timetables without a calendar contribute `trip_count` expanded trips.
+    pub(crate) fn append(&mut self, other: Option<&ServiceCalendar>, trip_count: usize) {
+        let other = if let Some(other) = other {
+            other
+        } else {
+            self.trip_services
+                .extend(std::iter::repeat_n(EXPANDED, trip_count));
+            return;
+        };
+        let (zone_offset, service_offset) = (self.zones.len() as u32, self.services.len() as u32);
+        self.zones.extend(other.zones.iter().cloned());
+        self.services
+            .extend(other.services.iter().map(|service| ServiceDays {
+                zone: service.zone + zone_offset,
+                days: service.days.clone(),
+            }));
+        self.trip_services
+            .extend(other.trip_services.iter().map(|service| {
+                if *service == EXPANDED {
+                    EXPANDED
+                } else {
+                    service + service_offset
+                }
+            }));
+    }
+
+    pub(crate) fn has_patterns(&self) -> bool {
+        !self.services.is_empty()
+    }
+
+    pub fn is_pattern(&self, trip: &Trip) -> bool {
+        self.trip_services
+            .get(trip.id())
+            .is_some_and(|service| *service != EXPANDED)
+    }
+
+    fn service(&self, trip: &Trip) -> Option<(&ServiceDays, &CalendarZone)> {
+        let service = self
+            .services
+            .get(*self.trip_services.get(trip.id())? as usize)?;
+        Some((service, &self.zones[service.zone as usize]))
+    }
+
+    fn runs_on(service: &ServiceDays, day: usize) -> bool {
+        service
+            .days
+            .get(day / 64)
+            .is_some_and(|word| word & (1 << (day % 64)) != 0)
+    }
+
+    /// The date of the service day a resolved pattern trip runs on.
+    pub fn service_date(&self, trip: &Trip) -> Option<NaiveDate> {
+        let (_, zone) = self.service(trip)?;
+        let day = zone
+            .day_starts
+            .binary_search(&trip.service_day_start())
+            .ok()?;
+        zone.start_date.checked_add_days(Days::new(day as u64))
+    }
+
+    /// The pattern trip resolved to every service day it runs on.
+    pub fn trip_service_days(&self, trip: &Trip) -> Vec<Trip> {
+        let (service, zone) = if let Some(service) = self.service(trip) {
+            service
+        } else {
+            return vec![];
+        };
+        zone.day_starts
+            .iter()
+            .enumerate()
+            .filter(|(day, _)| Self::runs_on(service, *day))
+            .map(|(_, day_start)| trip.on_service_day(*day_start))
+            .collect()
+    }
+
+    /// The first trip of a pattern route that can be boarded at `stop_seq` at or after
+    /// `not_before`, resolved to the service day it runs on.
+    pub fn earliest_trip<'a>(
+        &self,
+        timetable: &'a dyn Timetable<'a>,
+        boarding: &BoardingIndex,
+        route: &Route,
+        stop_seq: usize,
+        not_before: &Time,
+    ) -> Option<Trip> {
+        let trips = route.route_trips(timetable);
+        let (_, zone) = self.service(trips.first()?)?;
+        let not_before = not_before.epoch_seconds();
+        let mut best: Option<(u32, Trip)> = None;
+        for (day, day_start) in zone.day_starts.iter().copied().enumerate() {
+            if day_start.saturating_add(MAX_TRIP_SPAN_SECONDS) < not_before {
+                continue;
+            }
+            // Nothing departs before its service day starts, so later days can't do any better.
+            if best.is_some_and(|(departure, _)| departure <= day_start) {
+                break;
+            }
+            let offset = not_before.saturating_sub(day_start);
+            let position = trips.partition_point(|trip| {
+                trip.stop_times(timetable)[stop_seq]
+                    .departure()
+                    .epoch_seconds()
+                    < offset
+            });
+            let mut trip = None;
+            let mut position = boarding.first_boardable(timetable, route, stop_seq, position);
+            while let Some(boardable) = position {
+                if self
+                    .service(&trips[boardable])
+                    .is_some_and(|(service, _)| Self::runs_on(service, day))
+                {
+                    trip = Some(&trips[boardable]);
+                    break;
+                }
+                position = boarding.first_boardable(timetable, route, stop_seq, boardable + 1);
+            }
+            if let Some(trip) = trip {
+                let trip = trip.on_service_day(day_start);
+                let departure = trip
+                    .stop_time(timetable, stop_seq)
+                    .departure()
+                    .epoch_seconds();
+                if best.is_none_or(|(best_departure, _)| departure < best_departure) {
+                    best = Some((departure, trip));
+                }
+            }
+        }
+        best.map(|(_, trip)| trip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appended_calendars_offset_services() {
+        let start_date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let mut first = ServiceCalendar::default();
+        let zone = first.add_zone(start_date, vec![0, 86_400, 172_800]);
+        let weekdays = first.add_service(zone, [0, 1]);
+        first.push_trip(Some(weekdays));
+
+        let mut second = ServiceCalendar::default();
+        let zone = second.add_zone(start_date, vec![0, 86_400, 172_800]);
+        let last_day = second.add_service(zone, [2]);
+        second.push_trip(Some(last_day));
+
+        let mut combined = ServiceCalendar::default();
+        combined.append(Some(&first), 1);
+        combined.append(None, 2);
+        combined.append(Some(&second), 1);
+
+        assert_eq!(combined.trip_services, vec![0, EXPANDED, EXPANDED, 1]);
+        assert_eq!(combined.services[1].zone, 1);
+        assert!(ServiceCalendar::runs_on(&combined.services[1], 2));
+        assert!(!ServiceCalendar::runs_on(&combined.services[1], 0));
+    }
+}
@@ -0,0 +1,922 @@
+use std::{collections::BTreeMap, fmt};
+
+use redb::{
+    Database, Key, ReadOnlyTable, ReadableTable, ReadableTableMetadata, TableDefinition,
+    TableError, Value,
+};
+
+use super::{
+    mmap::MmapTimetable, ShapeCoordinate, Timetable, GLOBAL_ROUTE_INDEX_TABLE,
+    GLOBAL_STOP_INDEX_TABLE, GLOBAL_TRIP_INDEX_TABLE, ROUTE_SHAPE_INDEX_TABLE, ROUTE_SHAPE_TABLE,
+    STOP_GLOBAL_ID_TABLE, STOP_METADATA_TABLE, TRIP_METADATA_TABLE,
+};
+
+// Only this many violations of each invariant are kept as examples, the rest are just counted.
+const MAX_EXAMPLES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Invariant {
+    /// The raw files match the content hashes in the manifest.
+    ContentHashes,
+    /// Stops, routes and trips are stored at the index they're identified by.
+    EntityIds,
+    /// Every `first_*` index and stop time range is in bounds and ordered.
+    IndexRanges,
+    /// A route's route stops belong to it and are numbered by their position.
+    RouteStops,
+    /// A trip has one stop time per stop of its route, in order.
+    TripStopTimes,
+    /// Times never go backwards along a trip.
+    MonotoneStopTimes,
+    /// Trips of a route are sorted by departure at every stop, as boarding searches assume.
+    SortedDepartures,
+    /// Every stop route points back at a route stop at that stop, and there's one per route stop.
+    StopRoutes,
+    /// Transfers leave from the stop they're indexed under and arrive at a valid stop.
+    Transfers,
+    /// Every stop, trip and route has a metadata record, and shape indices and stable IDs point at
+    /// entities that exist.
+    Metadata,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Invariant::ContentHashes => "content hashes",
+            Invariant::EntityIds => "entity IDs",
+            Invariant::IndexRanges => "index ranges",
+            Invariant::RouteStops => "route stops",
+            Invariant::TripStopTimes => "trip stop times",
+            Invariant::MonotoneStopTimes => "monotone stop times",
+            Invariant::SortedDepartures => "sorted departures",
+            Invariant::StopRoutes => "stop routes",
+            Invariant::Transfers => "transfers",
+            Invariant::Metadata => "metadata",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub violations: BTreeMap<Invariant, usize>,
+    pub examples: Vec<(Invariant, String)>,
+}
+
+impl CheckReport {
+    fn violation(&mut self, invariant: Invariant, message: impl FnOnce() -> String) {
+        let count = self.violations.entry(invariant).or_default();
+        *count += 1;
+        if *count <= MAX_EXAMPLES {
+            self.examples.push((invariant, message()));
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn violation_count(&self) -> usize {
+        self.violations.values().sum()
+    }
+}
+
+/// Check every structural invariant the router relies on. Unlike the router this never indexes
+/// out of bounds, so it reports corrupt data rather than panicking on it.
+pub fn check_timetable<'a>(timetable: &'a MmapTimetable<'a>) -> CheckReport {
+    let mut report = CheckReport::default();
+    if let Err(err) = timetable.manifest().verify_hashes(timetable.base_path()) {
+        report.violation(Invariant::ContentHashes, || err.to_string());
+    }
+    check_structure(timetable, &mut report);
+    check_metadata(timetable, &mut report);
+    report
+}
+
+fn check_structure<'a>(timetable: &'a dyn Timetable<'a>, report: &mut CheckReport) {
+    check_routes(timetable, report);
+    check_stops(timetable, report);
+    check_transfers(timetable, report);
+}
+
+// The ranges that consecutive `first_*` indices delimit, or None for a range that's out of bounds.
+fn ranges(firsts: &[usize], len: usize) -> Vec<Option<(usize, usize)>> {
+    firsts
+        .iter()
+        .enumerate()
+        .map(|(idx, first)| {
+            let end = firsts.get(idx + 1).copied().unwrap_or(len);
+            if *first <= end && end <= len {
+                Some((*first, end))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn check_routes<'a>(timetable: &'a dyn Timetable<'a>, report: &mut CheckReport) {
+    let routes = timetable.routes();
+    let route_stops = timetable.route_stops();
+    let trips = timetable.route_trips();
+    let stop_times = timetable.trip_stop_times();
+
+    let stop_ranges = ranges(
+        &routes
+            .iter()
+            .map(|route| route.first_route_stop())
+            .collect::<Vec<_>>(),
+        route_stops.len(),
+    );
+    let trip_ranges = ranges(
+        &routes
+            .iter()
+            .map(|route| route.first_route_trip())
+            .collect::<Vec<_>>(),
+        trips.len(),
+    );
+    for (route_id, route) in routes.iter().enumerate() {
+        if route.id() != route_id {
+            report.violation(Invariant::EntityIds, || {
+                format!("Route {} is stored at index {}", route.id(), route_id)
+            });
+        }
+        let (Some((first_stop, end_stop)), Some((first_trip, end_trip))) =
+            (stop_ranges[route_id], trip_ranges[route_id])
+        else {
+            report.violation(Invariant::IndexRanges, || {
+                format!(
+                    "Route {} has route stops from {} and trips from {}, out of order or past the ends of {} route stops and {} trips",
+                    route_id,
+                    route.first_route_stop(),
+                    route.first_route_trip(),
+                    route_stops.len(),
+                    trips.len()
+                )
+            });
+            continue;
+        };
+        let this_route_stops = &route_stops[first_stop..end_stop];
+        for (stop_seq, route_stop) in this_route_stops.iter().enumerate() {
+            if route_stop.route_id() != route_id || route_stop.stop_seq() != stop_seq {
+                report.violation(Invariant::RouteStops, || {
+                    format!(
+                        "Route stop {} of route {} claims to be stop {} of route {}",
+                        stop_seq,
+                        route_id,
+                        route_stop.stop_seq(),
+                        route_stop.route_id()
+                    )
+                });
+            }
+            if route_stop.id() >= timetable.stops().len() {
+                report.violation(Invariant::IndexRanges, || {
+                    format!(
+                        "Route stop {} of route {} is at stop {}, but there are {} stops",
+                        stop_seq,
+                        route_id,
+                        route_stop.id(),
+                        timetable.stops().len()
+                    )
+                });
+            }
+        }
+
+        // The departures of the previous valid trip at each stop.
+        let mut previous_departures: Option<(usize, Vec<u32>)> = None;
+        for (trip_id, trip) in trips[first_trip..end_trip]
+            .iter()
+            .enumerate()
+            .map(|(idx, trip)| (first_trip + idx, trip))
+        {
+            if trip.id() != trip_id {
+                report.violation(Invariant::EntityIds, || {
+                    format!("Trip {} is stored at index {}", trip.id(), trip_id)
+                });
+            }
+            if trip.route_id() != route_id {
+                report.violation(Invariant::EntityIds, || {
+                    format!(
+                        "Trip {} of route {} claims to be on route {}",
+                        trip_id,
+                        route_id,
+                        trip.route_id()
+                    )
+                });
+            }
+            let first = trip.first_trip_stop_time.usize();
+            let last = trip.last_trip_stop_time.usize();
+            if first > last || last > stop_times.len() {
+                report.violation(Invariant::IndexRanges, || {
+                    format!(
+                        "Trip {} has stop times {}..{}, but there are {} stop times",
+                        trip_id,
+                        first,
+                        last,
+                        stop_times.len()
+                    )
+                });
+                continue;
+            }
+            let this_stop_times = &stop_times[first..last];
+            if this_stop_times.len() != this_route_stops.len() {
+                report.violation(Invariant::TripStopTimes, || {
+                    format!(
+                        "Trip {} has {} stop times, but route {} has {} stops",
+                        trip_id,
+                        this_stop_times.len(),
+                        route_id,
+                        this_route_stops.len()
+                    )
+                });
+                continue;
+            }
+            let mut previous_time = 0u32;
+            for (stop_seq, stop_time) in this_stop_times.iter().enumerate() {
+                if stop_time.trip_id() != trip_id || stop_time.route_stop_seq() != stop_seq {
+                    report.violation(Invariant::TripStopTimes, || {
+                        format!(
+                            "Stop time {} of trip {} claims to be stop time {} of trip {}",
+                            stop_seq,
+                            trip_id,
+                            stop_time.route_stop_seq(),
+                            stop_time.trip_id()
+                        )
+                    });
+                }
+                let arrival = stop_time.arrival_time.get();
+                let departure = stop_time.departure_time.get();
+                if arrival < previous_time || departure < arrival {
+                    report.violation(Invariant::MonotoneStopTimes, || {
+                        format!(
+                            "Trip {} arrives at stop {} at {} and departs at {}, after reaching {}",
+                            trip_id, stop_seq, arrival, departure, previous_time
+                        )
+                    });
+                }
+                previous_time = previous_time.max(departure);
+            }
+            let departures: Vec<u32> = this_stop_times
+                .iter()
+                .map(|stop_time| stop_time.departure_time.get())
+                .collect();
+            if let Some((previous_trip_id, previous_departures)) = &previous_departures {
+                if let Some(stop_seq) = previous_departures
+                    .iter()
+                    .zip(&departures)
+                    .position(|(previous, departure)| departure < previous)
+                {
+                    report.violation(Invariant::SortedDepartures, || {
+                        format!(
+                            "Trip {} of route {} departs stop {} at {}, before trip {} at {}",
+                            trip_id,
+                            route_id,
+                            stop_seq,
+                            departures[stop_seq],
+                            previous_trip_id,
+                            previous_departures[stop_seq]
+                        )
+                    });
+                }
+            }
+            previous_departures = Some((trip_id, departures));
+        }
+    }
+}
+
+fn check_stops<'a>(timetable: &'a dyn Timetable<'a>, report: &mut CheckReport) {
+    let stops = timetable.stops();
+    let stop_routes = timetable.stop_routes();
+    let routes = timetable.routes();
+    let route_stops = timetable.route_stops();
+
+    let stop_route_ranges = ranges(
+        &stops
+            .iter()
+            .map(|stop| stop.first_stop_route_index.usize())
+            .collect::<Vec<_>>(),
+        stop_routes.len(),
+    );
+    for (stop_id, stop) in stops.iter().enumerate() {
+        if stop.id() != stop_id {
+            report.violation(Invariant::EntityIds, || {
+                format!("Stop {} is stored at index {}", stop.id(), stop_id)
+            });
+        }
+        let Some((first, end)) = stop_route_ranges[stop_id] else {
+            report.violation(Invariant::IndexRanges, || {
+                format!(
+                    "Stop {} has stop routes from {}, out of order or past the end of {} stop routes",
+                    stop_id,
+                    stop.first_stop_route_index.usize(),
+                    stop_routes.len()
+                )
+            });
+            continue;
+        };
+        for stop_route in &stop_routes[first..end] {
+            let route_stop = routes.get(stop_route.route_id()).and_then(|route| {
+                route_stops.get(route.first_route_stop() + stop_route.stop_seq())
+            });
+            let matches = route_stop.is_some_and(|route_stop| {
+                route_stop.route_id() == stop_route.route_id()
+                    && route_stop.stop_seq() == stop_route.stop_seq()
+                    && route_stop.id() == stop_id
+            });
+            if !matches {
+                report.violation(Invariant::StopRoutes, || {
+                    format!(
+                        "Stop {} is served by stop {} of route {}, but that route stop is {:?}",
+                        stop_id,
+                        stop_route.stop_seq(),
+                        stop_route.route_id(),
+                        route_stop
+                    )
+                });
+            }
+        }
+    }
+    if stop_routes.len() != route_stops.len() {
+        report.violation(Invariant::StopRoutes, || {
+            format!(
+                "There are {} stop routes, but {} route stops",
+                stop_routes.len(),
+                route_stops.len()
+            )
+        });
+    }
+}
+
+fn check_transfers<'a>(timetable: &'a dyn Timetable<'a>, report: &mut CheckReport) {
+    let stops = timetable.stops();
+    let transfers = timetable.transfers();
+    let transfer_index = timetable.transfer_index();
+    if transfer_index.len() != stops.len() {
+        report.violation(Invariant::IndexRanges, || {
+            format!(
+                "The transfer index has {} entries, but there are {} stops",
+                transfer_index.len(),
+                stops.len()
+            )
+        });
+        return;
+    }
+    let transfer_ranges = ranges(
+        &transfer_index
+            .iter()
+            .map(|first| first.usize())
+            .collect::<Vec<_>>(),
+        transfers.len(),
+    );
+    for (stop_id, range) in transfer_ranges.into_iter().enumerate() {
+        let Some((first, end)) = range else {
+            report.violation(Invariant::IndexRanges, || {
+                format!(
+                    "Stop {} has transfers from {}, out of order or past the end of {} transfers",
+                    stop_id,
+                    transfer_index[stop_id].usize(),
+                    transfers.len()
+                )
+            });
+            continue;
+        };
+        for transfer in &transfers[first..end] {
+            if transfer.from_id() != stop_id || transfer.to_id() >= stops.len() {
+                report.violation(Invariant::Transfers, || {
+                    format!(
+                        "Transfer indexed under stop {} goes from stop {} to stop {}, but there are {} stops",
+                        stop_id,
+                        transfer.from_id(),
+                        transfer.to_id(),
+                        stops.len()
+                    )
+                });
+            }
+        }
+    }
+}
+
+// Open a metadata table, reporting it if it can't be read. Tables that timetables built by older
+// versions don't have are None without a violation unless `required`.
+fn open_metadata_table<K: Key + 'static, V: Value + 'static>(
+    metadata_db: &Database,
+    table: TableDefinition<K, V>,
+    entity: &str,
+    required: bool,
+    report: &mut CheckReport,
+) -> Option<ReadOnlyTable<K, V>> {
+    let result = metadata_db
+        .begin_read()
+        .map_err(|err| err.to_string())
+        .and_then(|txn| match txn.open_table(table) {
+            Ok(table) => Ok(Some(table)),
+            Err(TableError::TableDoesNotExist(_)) if !required => Ok(None),
+            Err(err) => Err(err.to_string()),
+        });
+    match result {
+        Ok(table) => table,
+        Err(err) => {
+            report.violation(Invariant::Metadata, || {
+                format!("Can't read {} metadata: {}", entity, err)
+            });
+            None
+        }
+    }
+}
+
+fn for_each_entry<K: Key + 'static, V: Value + 'static>(
+    table: &ReadOnlyTable<K, V>,
+    entity: &str,
+    report: &mut CheckReport,
+    mut visit: impl FnMut(K::SelfType<'_>, V::SelfType<'_>, &mut CheckReport),
+) {
+    let entries = match table.iter() {
+        Ok(entries) => entries,
+        Err(err) => {
+            report.violation(Invariant::Metadata, || {
+                format!("Can't read {} metadata: {}", entity, err)
+            });
+            return;
+        }
+    };
+    for entry in entries {
+        match entry {
+            Ok((key, value)) => visit(key.value(), value.value(), report),
+            Err(err) => report.violation(Invariant::Metadata, || {
+                format!("Can't read {} metadata: {}", entity, err)
+            }),
+        }
+    }
+}
+
+fn check_metadata_table(
+    metadata_db: &Database,
+    table: TableDefinition<u64, &[u8]>,
+    entity: &str,
+    count: usize,
+    report: &mut CheckReport,
+) {
+    let Some(table) = open_metadata_table(metadata_db, table, entity, true, report) else {
+        return;
+    };
+    for id in 0..count {
+        match table.get(id as u64) {
+            Ok(Some(_)) => {}
+            Ok(None) => report.violation(Invariant::Metadata, || {
+                format!("{} {} has no metadata", entity, id)
+            }),
+            Err(err) => report.violation(Invariant::Metadata, || {
+                format!("Can't read metadata of {} {}: {}", entity, id, err)
+            }),
+        }
+    }
+    if let Ok(len) = table.len() {
+        if len as usize != count {
+            report.violation(Invariant::Metadata, || {
+                format!(
+                    "There are {} {} metadata records, but {} {}s",
+                    len, entity, count, entity
+                )
+            });
+        }
+    }
+}
+
+// Shape indices are only stored for routes with a shape, but those need one per route stop, in
+// order and within the shape. `route_stop_counts` is None for routes whose stops are out of bounds.
+fn check_shape_indices(
+    metadata_db: &Database,
+    route_stop_counts: &[Option<usize>],
+    report: &mut CheckReport,
+) {
+    let Some(table) = open_metadata_table(
+        metadata_db,
+        ROUTE_SHAPE_INDEX_TABLE,
+        "route shape index",
+        false,
+        report,
+    ) else {
+        return;
+    };
+    let shapes = open_metadata_table(metadata_db, ROUTE_SHAPE_TABLE, "route", true, report);
+    for_each_entry(
+        &table,
+        "route shape index",
+        report,
+        |route_id, bytes, report| {
+            let route_id = route_id as usize;
+            let Some(stop_count) = route_stop_counts.get(route_id) else {
+                report.violation(Invariant::Metadata, || {
+                    format!(
+                        "Route {} has shape indices, but there are {} routes",
+                        route_id,
+                        route_stop_counts.len()
+                    )
+                });
+                return;
+            };
+            let indices: Vec<u32> = match rmp_serde::from_slice(bytes) {
+                Ok(indices) => indices,
+                Err(err) => {
+                    report.violation(Invariant::Metadata, || {
+                        format!("Can't decode shape indices of route {}: {}", route_id, err)
+                    });
+                    return;
+                }
+            };
+            if stop_count.is_some_and(|stop_count| stop_count != indices.len()) {
+                report.violation(Invariant::Metadata, || {
+                    format!(
+                        "Route {} has {} shape indices, but {} stops",
+                        route_id,
+                        indices.len(),
+                        stop_count.unwrap_or_default()
+                    )
+                });
+            }
+            if indices.windows(2).any(|pair| pair[1] < pair[0]) {
+                report.violation(Invariant::Metadata, || {
+                    format!("Shape indices of route {} go backwards", route_id)
+                });
+            }
+            let shape_len = shapes
+                .as_ref()
+                .and_then(|shapes| shapes.get(route_id as u64).ok().flatten())
+                .and_then(|bytes| {
+                    rmp_serde::from_slice::<Option<Vec<ShapeCoordinate>>>(bytes.value()).ok()
+                })
+                .flatten()
+                .map(|shape| shape.len())
+                .unwrap_or_default();
+            // Each index is the start of a segment, so it needs a point after it.
+            if indices.iter().any(|index| *index as usize + 1 >= shape_len) {
+                report.violation(Invariant::Metadata, || {
+                    format!(
+                        "Route {} has shape indices past the end of its {} point shape",
+                        route_id, shape_len
+                    )
+                });
+            }
+        },
+    );
+}
+
+// Stable IDs are only stored for timetables built since they were introduced.
+fn check_global_ids(
+    metadata_db: &Database,
+    stop_count: usize,
+    route_count: usize,
+    trip_count: usize,
+    report: &mut CheckReport,
+) {
+    if let Some(table) = open_metadata_table(
+        metadata_db,
+        STOP_GLOBAL_ID_TABLE,
+        "stable stop ID",
+        false,
+        report,
+    ) {
+        for_each_entry(
+            &table,
+            "stable stop ID",
+            report,
+            |stop_id, global_id, report| {
+                if stop_id as usize >= stop_count {
+                    report.violation(Invariant::Metadata, || {
+                        format!(
+                            "Stop {} has stable ID {}, but there are {} stops",
+                            stop_id, global_id, stop_count
+                        )
+                    });
+                }
+            },
+        );
+    }
+    if let Some(table) = open_metadata_table(
+        metadata_db,
+        GLOBAL_STOP_INDEX_TABLE,
+        "stable stop ID",
+        false,
+        report,
+    ) {
+        for_each_entry(
+            &table,
+            "stable stop ID",
+            report,
+            |global_id, stop_id, report| {
+                if stop_id as usize >= stop_count {
+                    report.violation(Invariant::Metadata, || {
+                        format!(
+                            "Stable stop ID {} points at stop {}, but there are {} stops",
+                            global_id, stop_id, stop_count
+                        )
+                    });
+                }
+            },
+        );
+    }
+    for (table, entity, count) in [
+        (GLOBAL_ROUTE_INDEX_TABLE, "route", route_count),
+        (GLOBAL_TRIP_INDEX_TABLE, "trip", trip_count),
+    ] {
+        let Some(table) = open_metadata_table(metadata_db, table, entity, false, report) else {
+            continue;
+        };
+        for_each_entry(&table, entity, report, |global_id, bytes, report| {
+            match rmp_serde::from_slice::<Vec<u64>>(bytes) {
+                Ok(indices) => {
+                    if let Some(index) = indices.iter().find(|index| **index as usize >= count) {
+                        report.violation(Invariant::Metadata, || {
+                            format!(
+                                "Stable {} ID {} points at {} {}, but there are {} {}s",
+                                entity, global_id, entity, index, count, entity
+                            )
+                        });
+                    }
+                }
+                Err(err) => report.violation(Invariant::Metadata, || {
+                    format!("Can't decode stable {} ID {}: {}", entity, global_id, err)
+                }),
+            }
+        });
+    }
+}
+
+fn check_metadata<'a>(timetable: &'a MmapTimetable<'a>, report: &mut CheckReport) {
+    let metadata_db = timetable.metadata_db();
+    check_metadata_table(
+        metadata_db,
+        STOP_METADATA_TABLE,
+        "stop",
+        timetable.stops().len(),
+        report,
+    );
+    check_metadata_table(
+        metadata_db,
+        TRIP_METADATA_TABLE,
+        "trip",
+        timetable.route_trips().len(),
+        report,
+    );
+    check_metadata_table(
+        metadata_db,
+        ROUTE_SHAPE_TABLE,
+        "route",
+        timetable.routes().len(),
+        report,
+    );
+    let route_stop_counts: Vec<Option<usize>> = ranges(
+        &timetable
+            .routes()
+            .iter()
+            .map(|route| route.first_route_stop())
+            .collect::<Vec<_>>(),
+        timetable.route_stops().len(),
+    )
+    .into_iter()
+    .map(|range| range.map(|(first, end)| end - first))
+    .collect();
+    check_shape_indices(metadata_db, &route_stop_counts, report);
+    check_global_ids(
+        metadata_db,
+        timetable.stops().len(),
+        timetable.routes().len(),
+        timetable.route_trips().len(),
+        report,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Tz;
+    use redb::backends::InMemoryBackend;
+
+    use super::*;
+    use crate::timetable::{
+        in_memory::InMemoryTimetable, le::U64Le, Route, RouteStop, Stop, StopRoute, Transfer, Trip,
+        TripStopTime,
+    };
+
+    // Two stops served by one route with two trips, and a transfer each way between them.
+    struct Entities {
+        routes: Vec<Route>,
+        route_stops: Vec<RouteStop>,
+        trips: Vec<Trip>,
+        stops: Vec<Stop>,
+        stop_routes: Vec<StopRoute>,
+        stop_times: Vec<TripStopTime>,
+        transfer_index: Vec<U64Le>,
+        transfers: Vec<Transfer>,
+    }
+
+    fn stop_time(trip: usize, stop_seq: usize, arrival: i64, departure: i64) -> TripStopTime {
+        TripStopTime::new(
+            trip,
+            stop_seq,
+            Tz::UTC.timestamp_opt(arrival, 0).unwrap(),
+            Tz::UTC.timestamp_opt(departure, 0).unwrap(),
+            0,
+        )
+    }
+
+    impl Entities {
+        fn new() -> Entities {
+            Entities {
+                routes: vec![Route::new(0, 0, 0)],
+                route_stops: vec![RouteStop::new(0, 0, 0, 0.0), RouteStop::new(0, 1, 1, 0.0)],
+                trips: vec![Trip::new(0, 0, 0, 2), Trip::new(1, 0, 2, 4)],
+                stops: vec![Stop::new(0, 0, 0), Stop::new(1, 0, 1)],
+                stop_routes: vec![StopRoute::new(0, 0), StopRoute::new(0, 1)],
+                stop_times: vec![
+                    stop_time(0, 0, 100, 110),
+                    stop_time(0, 1, 200, 210),
+                    stop_time(1, 0, 300, 310),
+                    stop_time(1, 1, 400, 410),
+                ],
+                transfer_index: vec![U64Le::index(0), U64Le::index(1)],
+                transfers: vec![Transfer::new(0, 1, 60), Transfer::new(1, 0, 60)],
+            }
+        }
+
+        fn violations(self) -> Vec<Invariant> {
+            let timetable = InMemoryTimetable::from_entities(
+                self.routes,
+                self.route_stops,
+                self.trips,
+                self.stops,
+                self.stop_routes,
+                self.stop_times,
+                self.transfer_index,
+                self.transfers,
+            );
+            let mut report = CheckReport::default();
+            check_structure(&timetable, &mut report);
+            report.violations.into_keys().collect()
+        }
+    }
+
+    fn metadata_db() -> Database {
+        Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap()
+    }
+
+    fn insert<'a, K: Key + 'static, V: Value + 'static>(
+        metadata_db: &Database,
+        table: TableDefinition<K, V>,
+        entries: impl IntoIterator<Item = (K::SelfType<'a>, V::SelfType<'a>)>,
+    ) {
+        let write = metadata_db.begin_write().unwrap();
+        {
+            let mut table = write.open_table(table).unwrap();
+            for (key, value) in entries {
+                table.insert(key, value).unwrap();
+            }
+        }
+        write.commit().unwrap();
+    }
+
+    #[test]
+    fn consistent_timetable_passes() {
+        assert_eq!(Entities::new().violations(), vec![]);
+    }
+
+    #[test]
+    fn unsorted_departures_are_reported() {
+        let mut entities = Entities::new();
+        entities.stop_times[2] = stop_time(1, 0, 50, 60);
+        entities.stop_times[3] = stop_time(1, 1, 150, 160);
+        assert_eq!(entities.violations(), vec![Invariant::SortedDepartures]);
+    }
+
+    #[test]
+    fn stop_times_going_backwards_are_reported() {
+        let mut entities = Entities::new();
+        entities.stop_times[1] = stop_time(0, 1, 90, 95);
+        assert_eq!(entities.violations(), vec![Invariant::MonotoneStopTimes]);
+    }
+
+    #[test]
+    fn stop_routes_disagreeing_with_route_stops_are_reported() {
+        let mut entities = Entities::new();
+        entities.stop_routes[1] = StopRoute::new(0, 0);
+        assert_eq!(entities.violations(), vec![Invariant::StopRoutes]);
+    }
+
+    #[test]
+    fn transfers_to_missing_stops_are_reported() {
+        let mut entities = Entities::new();
+        entities.transfers[1] = Transfer::new(1, 5, 60);
+        assert_eq!(entities.violations(), vec![Invariant::Transfers]);
+    }
+
+    #[test]
+    fn missing_metadata_is_reported() {
+        let metadata_db = metadata_db();
+        insert(
+            &metadata_db,
+            STOP_METADATA_TABLE,
+            [(0, &[][..]), (1, &[][..])],
+        );
+        let mut report = CheckReport::default();
+        check_metadata_table(&metadata_db, STOP_METADATA_TABLE, "stop", 2, &mut report);
+        assert!(report.is_ok());
+        check_metadata_table(&metadata_db, STOP_METADATA_TABLE, "stop", 3, &mut report);
+        // Stop 2 is missing, and there are fewer records than stops.
+        assert_eq!(report.violations.get(&Invariant::Metadata), Some(&2));
+        // There's no trip metadata at all.
+        check_metadata_table(&metadata_db, TRIP_METADATA_TABLE, "trip", 1, &mut report);
+        assert_eq!(report.violations.get(&Invariant::Metadata), Some(&3));
+    }
+
+    #[test]
+    fn shape_indices_must_fit_their_route() {
+        let shape = |len: usize| {
+            rmp_serde::to_vec(&Some(
+                (0..len)
+                    .map(|idx| ShapeCoordinate {
+                        lat: idx as f64,
+                        lon: 0.0,
+                        distance_along_shape: None,
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+            .unwrap()
+        };
+        let indices = |indices: &[u32]| rmp_serde::to_vec(indices).unwrap();
+        let violations = |route_indices: &[Vec<u8>]| {
+            let metadata_db = metadata_db();
+            let shapes: Vec<Vec<u8>> = (0..route_indices.len()).map(|_| shape(4)).collect();
+            insert(
+                &metadata_db,
+                ROUTE_SHAPE_TABLE,
+                shapes
+                    .iter()
+                    .enumerate()
+                    .map(|(route, bytes)| (route as u64, bytes.as_slice())),
+            );
+            insert(
+                &metadata_db,
+                ROUTE_SHAPE_INDEX_TABLE,
+                route_indices
+                    .iter()
+                    .enumerate()
+                    .map(|(route, bytes)| (route as u64, bytes.as_slice())),
+            );
+            let mut report = CheckReport::default();
+            check_shape_indices(&metadata_db, &[Some(3)], &mut report);
+            report.violation_count()
+        };
+        assert_eq!(violations(&[indices(&[0, 1, 2])]), 0);
+        // Too few, backwards, past the last segment, and for a route that doesn't exist.
+        assert_eq!(violations(&[indices(&[0, 1])]), 1);
+        assert_eq!(violations(&[indices(&[0, 2, 1])]), 1);
+        assert_eq!(violations(&[indices(&[0, 1, 3])]), 1);
+        assert_eq!(violations(&[indices(&[0, 1, 2]), indices(&[0])]), 1);
+    }
+
+    #[test]
+    fn stable_ids_must_point_at_entities() {
+        let metadata_db = metadata_db();
+        let mut report = CheckReport::default();
+        // Timetables built before stable IDs don't have the tables at all.
+        check_global_ids(&metadata_db, 2, 1, 2, &mut report);
+        assert!(report.is_ok());
+
+        insert(
+            &metadata_db,
+            STOP_GLOBAL_ID_TABLE,
+            [(0, "feed:a"), (1, "feed:b")],
+        );
+        insert(
+            &metadata_db,
+            GLOBAL_STOP_INDEX_TABLE,
+            [("feed:a", 0), ("feed:b", 1)],
+        );
+        let trips = rmp_serde::to_vec(&[0u64, 2]).unwrap();
+        insert(
+            &metadata_db,
+            GLOBAL_TRIP_INDEX_TABLE,
+            [("feed:trip", trips.as_slice())],
+        );
+        check_global_ids(&metadata_db, 2, 1, 3, &mut report);
+        assert!(report.is_ok());
+        check_global_ids(&metadata_db, 1, 1, 2, &mut report);
+        // Stop 1 in both directions, and trip 2.
+        assert_eq!(report.violations.get(&Invariant::Metadata), Some(&3));
+    }
+
+    #[test]
+    fn out_of_order_ranges_are_rejected() {
+        assert_eq!(
+            ranges(&[0, 2, 2, 5], 6),
+            vec![Some((0, 2)), Some((2, 2)), Some((2, 5)), Some((5, 6))]
+        );
+        assert_eq!(
+            ranges(&[0, 3, 1], 4),
+            vec![Some((0, 3)), None, Some((1, 4))]
+        );
+        assert_eq!(ranges(&[0, 7], 4), vec![None, None]);
+    }
+}
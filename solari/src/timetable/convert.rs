@@ -0,0 +1,574 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem::size_of,
+    path::Path,
+};
+
+use anyhow::bail;
+use bytemuck::{bytes_of, Pod, Zeroable};
+use memmap2::Mmap;
+use tracing::{info, warn};
+
+use super::{
+    le::{F32Le, U32Le, U64Le},
+    manifest::{Manifest, ManifestError},
+    Route, RouteStop, Stop, StopRoute, Transfer, Trip, TripStopTime,
+};
+
+// The layouts of timetables built before format version 2, which stored indices as usize. Those
+// were only ever built on 64-bit little-endian hosts, so usize is read as u64 here. Timetables
+// from before manifests existed have no record of a format version, and use the same layouts as
+// version 1 except for trips and stop times.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LegacyLayout {
+    // No manifest: trips have no service day start, and stop times have no pickup or drop-off
+    // codes.
+    Unversioned,
+    Version1,
+}
+
+impl std::fmt::Display for LegacyLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LegacyLayout::Unversioned => write!(f, "unversioned"),
+            LegacyLayout::Version1 => write!(f, "format version 1"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyStop {
+    stop_index: u64,
+    s2cell: u64,
+    first_stop_route_index: u64,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyRouteStop {
+    route_index: u64,
+    stop_index: u64,
+    stop_seq: u32,
+    distance_along_route: f32,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct UnversionedTrip {
+    trip_index: u64,
+    route_index: u64,
+    first_trip_stop_time: u64,
+    last_trip_stop_time: u64,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyTrip {
+    trip_index: u64,
+    route_index: u64,
+    first_trip_stop_time: u64,
+    last_trip_stop_time: u64,
+    service_day_start: u64,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyRoute {
+    route_index: u64,
+    first_route_stop: u64,
+    first_route_trip: u64,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyStopRoute {
+    route_index: u64,
+    stop_seq: u64,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct UnversionedTripStopTime {
+    trip_index: u64,
+    route_stop_seq: u64,
+    arrival_time: u32,
+    departure_time: u32,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyTripStopTime {
+    trip_index: u64,
+    route_stop_seq: u64,
+    arrival_time: u32,
+    departure_time: u32,
+    boarding: u32,
+    _padding: u32,
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct LegacyTransfer {
+    to: u64,
+    from: u64,
+    time: u64,
+}
+
+// Legacy files are only checked for their length, so any field could hold garbage.
+fn u32_field(value: u64, field: &str) -> Result<U32Le, anyhow::Error> {
+    match u32::try_from(value) {
+        Ok(value) => Ok(U32Le::new(value)),
+        Err(_) => bail!("{field} {value} doesn't fit in 32 bits"),
+    }
+}
+
+// The records of one of an old timetable's raw files, which must be a whole number of `T`s.
+fn read_records<T: Pod>(
+    old_path: &Path,
+    name: &str,
+    layout: LegacyLayout,
+) -> Result<Vec<T>, anyhow::Error> {
+    let file = File::open(old_path.join(name))?;
+    let backing = unsafe { Mmap::map(&file)? };
+    if backing.len() % size_of::<T>() != 0 {
+        bail!(
+            "{:?} is {} bytes long, which isn't a whole number of {} byte records, so it doesn't have the {} layout",
+            old_path.join(name),
+            backing.len(),
+            size_of::<T>(),
+            layout
+        );
+    }
+    Ok(backing
+        .chunks_exact(size_of::<T>())
+        .map(bytemuck::pod_read_unaligned)
+        .collect())
+}
+
+fn convert_file<Old: Pod, New: Pod>(
+    old_path: &Path,
+    new_path: &Path,
+    name: &str,
+    layout: LegacyLayout,
+    convert: impl Fn(&Old) -> Result<New, anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let old = read_records::<Old>(old_path, name, layout)?;
+    let mut writer = BufWriter::new(File::create(new_path.join(name))?);
+    for (record_number, record) in old.iter().enumerate() {
+        let record = match convert(record) {
+            Ok(record) => record,
+            Err(err) => bail!(
+                "Record {record_number} of {:?} can't be converted: {err}",
+                old_path.join(name)
+            ),
+        };
+        writer.write_all(bytes_of(&record))?;
+    }
+    writer.flush()?;
+    info!(
+        "Converted {name}: {} bytes to {} bytes",
+        old.len() * size_of::<Old>(),
+        old.len() * size_of::<New>()
+    );
+    Ok(())
+}
+
+// File lengths alone can't always tell the layouts apart, so check that every trip's stop times
+// are in range before converting anything.
+fn check_trip_ranges(
+    old_path: &Path,
+    layout: LegacyLayout,
+    ranges: impl Iterator<Item = (u64, u64)>,
+) -> Result<(), anyhow::Error> {
+    let stop_time_size = match layout {
+        LegacyLayout::Unversioned => size_of::<UnversionedTripStopTime>(),
+        LegacyLayout::Version1 => size_of::<LegacyTripStopTime>(),
+    };
+    let stop_times = fs::metadata(old_path.join("trip_stop_times"))?.len() / stop_time_size as u64;
+    for (first, last) in ranges {
+        if first > last || last > stop_times {
+            bail!(
+                "{:?} has a trip with stop times {first}..{last} of {stop_times}, so it doesn't have the {layout} layout",
+                old_path
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite a timetable built before format version 2 in the current layout. Timetables without a
+/// manifest predate format versions entirely. Build parameters and feeds are carried over from
+/// the manifest, if there is one.
+pub fn convert_legacy_timetable(old_path: &Path, new_path: &Path) -> Result<(), anyhow::Error> {
+    let (layout, old_manifest) = match Manifest::read(old_path) {
+        Ok(manifest) => {
+            if manifest.format_version != 1 {
+                bail!(
+                    "{:?} has timetable format version {}, only version 1 and unversioned timetables can be converted",
+                    old_path,
+                    manifest.format_version
+                );
+            }
+            (LegacyLayout::Version1, Some(manifest))
+        }
+        Err(ManifestError::Missing(_)) => {
+            warn!("{old_path:?} has no manifest, converting it from the unversioned layout");
+            (LegacyLayout::Unversioned, None)
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if cfg!(target_endian = "big") {
+        bail!("Timetables built before format version 2 can only be converted on a little-endian host");
+    }
+
+    // Trips and stop times are the only records whose layout differs between the two.
+    let trips: Vec<LegacyTrip> = match layout {
+        LegacyLayout::Unversioned => {
+            read_records::<UnversionedTrip>(old_path, "route_trips", layout)?
+                .iter()
+                .map(|trip| LegacyTrip {
+                    trip_index: trip.trip_index,
+                    route_index: trip.route_index,
+                    first_trip_stop_time: trip.first_trip_stop_time,
+                    last_trip_stop_time: trip.last_trip_stop_time,
+                    service_day_start: 0,
+                })
+                .collect()
+        }
+        LegacyLayout::Version1 => read_records(old_path, "route_trips", layout)?,
+    };
+    check_trip_ranges(
+        old_path,
+        layout,
+        trips
+            .iter()
+            .map(|trip| (trip.first_trip_stop_time, trip.last_trip_stop_time)),
+    )?;
+    fs::create_dir_all(new_path)?;
+
+    convert_file(old_path, new_path, "stops", layout, |stop: &LegacyStop| {
+        Ok(Stop {
+            stop_index: u32_field(stop.stop_index, "stop_index")?,
+            first_stop_route_index: u32_field(
+                stop.first_stop_route_index,
+                "first_stop_route_index",
+            )?,
+            s2cell: U64Le::new(stop.s2cell),
+        })
+    })?;
+    convert_file(
+        old_path,
+        new_path,
+        "route_stops",
+        layout,
+        |route_stop: &LegacyRouteStop| {
+            Ok(RouteStop {
+                route_index: u32_field(route_stop.route_index, "route_index")?,
+                stop_index: u32_field(route_stop.stop_index, "stop_index")?,
+                stop_seq: U32Le::new(route_stop.stop_seq),
+                distance_along_route: F32Le::new(route_stop.distance_along_route),
+            })
+        },
+    )?;
+    let mut writer = BufWriter::new(File::create(new_path.join("route_trips"))?);
+    for (record_number, trip) in trips.iter().enumerate() {
+        let convert = |trip: &LegacyTrip| -> Result<Trip, anyhow::Error> {
+            Ok(Trip {
+                trip_index: u32_field(trip.trip_index, "trip_index")?,
+                route_index: u32_field(trip.route_index, "route_index")?,
+                first_trip_stop_time: U64Le::new(trip.first_trip_stop_time),
+                last_trip_stop_time: U64Le::new(trip.last_trip_stop_time),
+                service_day_start: u32_field(trip.service_day_start, "service_day_start")?,
+                _padding: U32Le::new(0),
+            })
+        };
+        let trip = match convert(trip) {
+            Ok(trip) => trip,
+            Err(err) => bail!(
+                "Record {record_number} of {:?} can't be converted: {err}",
+                old_path.join("route_trips")
+            ),
+        };
+        writer.write_all(bytes_of(&trip))?;
+    }
+    writer.flush()?;
+    convert_file(
+        old_path,
+        new_path,
+        "routes",
+        layout,
+        |route: &LegacyRoute| {
+            Ok(Route {
+                route_index: u32_field(route.route_index, "route_index")?,
+                first_route_stop: u32_field(route.first_route_stop, "first_route_stop")?,
+                first_route_trip: u32_field(route.first_route_trip, "first_route_trip")?,
+            })
+        },
+    )?;
+    convert_file(
+        old_path,
+        new_path,
+        "stop_routes",
+        layout,
+        |stop_route: &LegacyStopRoute| {
+            Ok(StopRoute {
+                route_index: u32_field(stop_route.route_index, "route_index")?,
+                stop_seq: u32_field(stop_route.stop_seq, "stop_seq")?,
+            })
+        },
+    )?;
+    match layout {
+        LegacyLayout::Unversioned => {
+            // Regular pickup and drop-off everywhere, since nothing else was stored.
+            let boarding = TripStopTime::boarding_flags(0, 0, 1, 1, None);
+            convert_file(
+                old_path,
+                new_path,
+                "trip_stop_times",
+                layout,
+                |stop_time: &UnversionedTripStopTime| {
+                    Ok(TripStopTime {
+                        trip_index: u32_field(stop_time.trip_index, "trip_index")?,
+                        route_stop_seq: u32_field(stop_time.route_stop_seq, "route_stop_seq")?,
+                        arrival_time: U32Le::new(stop_time.arrival_time),
+                        departure_time: U32Le::new(stop_time.departure_time),
+                        boarding: U32Le::new(boarding),
+                    })
+                },
+            )?
+        }
+        LegacyLayout::Version1 => convert_file(
+            old_path,
+            new_path,
+            "trip_stop_times",
+            layout,
+            |stop_time: &LegacyTripStopTime| {
+                Ok(TripStopTime {
+                    trip_index: u32_field(stop_time.trip_index, "trip_index")?,
+                    route_stop_seq: u32_field(stop_time.route_stop_seq, "route_stop_seq")?,
+                    arrival_time: U32Le::new(stop_time.arrival_time),
+                    departure_time: U32Le::new(stop_time.departure_time),
+                    boarding: U32Le::new(stop_time.boarding),
+                })
+            },
+        )?,
+    }
+    convert_file(
+        old_path,
+        new_path,
+        "transfer_index",
+        layout,
+        |index: &u64| Ok(U64Le::new(*index)),
+    )?;
+    convert_file(
+        old_path,
+        new_path,
+        "transfers",
+        layout,
+        |transfer: &LegacyTransfer| {
+            Ok(Transfer {
+                to: u32_field(transfer.to, "to")?,
+                from: u32_field(transfer.from, "from")?,
+                time: u32_field(transfer.time, "time")?,
+            })
+        },
+    )?;
+    fs::copy(old_path.join("metadata.db"), new_path.join("metadata.db"))?;
+
+    let (build, feeds) = old_manifest
+        .map(|manifest| (manifest.build, manifest.feeds))
+        .unwrap_or_default();
+    Manifest::describe(new_path, build, feeds)?.write(new_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::cast_slice;
+
+    use super::*;
+
+    fn write_records<T: Pod>(path: &Path, name: &str, records: &[T]) {
+        fs::write(path.join(name), cast_slice::<T, u8>(records)).unwrap();
+    }
+
+    #[test]
+    fn converts_unversioned_timetables() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path();
+        let old_path = base_path.join("old");
+        let new_path = base_path.join("new");
+        fs::create_dir_all(&old_path).unwrap();
+        // Two stops served by one route with one trip, in the layout of timetables built before
+        // manifests existed.
+        write_records(
+            &old_path,
+            "stops",
+            &[
+                LegacyStop {
+                    stop_index: 0,
+                    s2cell: 0x8085_8000_0000_0001,
+                    first_stop_route_index: 0,
+                },
+                LegacyStop {
+                    stop_index: 1,
+                    s2cell: 0x8085_8000_0000_0003,
+                    first_stop_route_index: 1,
+                },
+            ],
+        );
+        write_records(
+            &old_path,
+            "routes",
+            &[LegacyRoute {
+                route_index: 0,
+                first_route_stop: 0,
+                first_route_trip: 0,
+            }],
+        );
+        write_records(
+            &old_path,
+            "route_stops",
+            &[
+                LegacyRouteStop {
+                    route_index: 0,
+                    stop_index: 0,
+                    stop_seq: 0,
+                    distance_along_route: 0.0,
+                },
+                LegacyRouteStop {
+                    route_index: 0,
+                    stop_index: 1,
+                    stop_seq: 1,
+                    distance_along_route: f32::NAN,
+                },
+            ],
+        );
+        write_records(
+            &old_path,
+            "stop_routes",
+            &[
+                LegacyStopRoute {
+                    route_index: 0,
+                    stop_seq: 0,
+                },
+                LegacyStopRoute {
+                    route_index: 0,
+                    stop_seq: 1,
+                },
+            ],
+        );
+        write_records(
+            &old_path,
+            "route_trips",
+            &[UnversionedTrip {
+                trip_index: 0,
+                route_index: 0,
+                first_trip_stop_time: 0,
+                last_trip_stop_time: 2,
+            }],
+        );
+        write_records(
+            &old_path,
+            "trip_stop_times",
+            &[
+                UnversionedTripStopTime {
+                    trip_index: 0,
+                    route_stop_seq: 0,
+                    arrival_time: 1_700_000_000,
+                    departure_time: 1_700_000_060,
+                },
+                UnversionedTripStopTime {
+                    trip_index: 0,
+                    route_stop_seq: 1,
+                    arrival_time: 1_700_000_600,
+                    departure_time: 1_700_000_600,
+                },
+            ],
+        );
+        write_records(&old_path, "transfer_index", &[0u64, 0u64]);
+        write_records::<LegacyTransfer>(&old_path, "transfers", &[]);
+        fs::write(old_path.join("metadata.db"), []).unwrap();
+
+        convert_legacy_timetable(&old_path, &new_path).unwrap();
+        let manifest = Manifest::read(&new_path).unwrap();
+        manifest.validate(&new_path).unwrap();
+
+        let trips: Vec<Trip> =
+            read_records(&new_path, "route_trips", LegacyLayout::Version1).unwrap();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].first_trip_stop_time.get(), 0);
+        assert_eq!(trips[0].last_trip_stop_time.get(), 2);
+        assert_eq!(trips[0].service_day_start.get(), 0);
+        let stop_times: Vec<TripStopTime> =
+            read_records(&new_path, "trip_stop_times", LegacyLayout::Version1).unwrap();
+        assert_eq!(stop_times.len(), 2);
+        assert_eq!(stop_times[1].route_stop_seq.get(), 1);
+        assert_eq!(stop_times[1].arrival().epoch_seconds(), 1_700_000_600);
+        assert!(stop_times
+            .iter()
+            .all(|stop_time| stop_time.can_board() && stop_time.can_alight()));
+        let stops: Vec<Stop> = read_records(&new_path, "stops", LegacyLayout::Version1).unwrap();
+        assert_eq!(stops[1].s2cell(), 0x8085_8000_0000_0003);
+
+        // Format version 1 trips are 40 bytes, which the unversioned layout can't be read as.
+        write_records(
+            &old_path,
+            "route_trips",
+            &[LegacyTrip {
+                trip_index: 0,
+                route_index: 0,
+                first_trip_stop_time: 0,
+                last_trip_stop_time: 2,
+                service_day_start: 0,
+            }],
+        );
+        assert!(convert_legacy_timetable(&old_path, &base_path.join("rejected")).is_err());
+
+        // A corrupt index is an error rather than a panic.
+        write_records(
+            &old_path,
+            "route_trips",
+            &[UnversionedTrip {
+                trip_index: 0,
+                route_index: 0,
+                first_trip_stop_time: 0,
+                last_trip_stop_time: 2,
+            }],
+        );
+        write_records(
+            &old_path,
+            "transfers",
+            &[LegacyTransfer {
+                to: 1,
+                from: 0,
+                time: 60,
+            }],
+        );
+        convert_legacy_timetable(&old_path, &base_path.join("transfers")).unwrap();
+        write_records(
+            &old_path,
+            "transfers",
+            &[
+                LegacyTransfer {
+                    to: 1,
+                    from: 0,
+                    time: 60,
+                },
+                LegacyTransfer {
+                    to: 1 << 32,
+                    from: 1,
+                    time: 60,
+                },
+            ],
+        );
+        let err = convert_legacy_timetable(&old_path, &base_path.join("corrupt"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Record 1 of"), "{}", err);
+        assert!(err.contains("transfers"), "{}", err);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+};
+
+use redb::ReadableTable;
+use serde::Serialize;
+
+use super::{
+    mmap::{MmapTimetable, StopKey},
+    ServiceWindow, Timetable, TripMetadata, TRIP_METADATA_TABLE,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct SetDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl SetDiff {
+    fn between(old: Option<&BTreeSet<String>>, new: Option<&BTreeSet<String>>) -> SetDiff {
+        let empty = BTreeSet::new();
+        let old = old.unwrap_or(&empty);
+        let new = new.unwrap_or(&empty);
+        SetDiff {
+            added: new.difference(old).cloned().collect(),
+            removed: old.difference(new).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// What changed in one feed, by GTFS ID.
+#[derive(Debug, Serialize)]
+pub struct FeedDiff {
+    pub feed: String,
+    pub stops: SetDiff,
+    pub routes: SetDiff,
+    pub trips: SetDiff,
+    pub old_service_window: Option<ServiceWindow>,
+    pub new_service_window: Option<ServiceWindow>,
+}
+
+impl FeedDiff {
+    pub fn service_window_changed(&self) -> bool {
+        self.old_service_window != self.new_service_window
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.stops.is_empty()
+            && self.routes.is_empty()
+            && self.trips.is_empty()
+            && !self.service_window_changed()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferChange {
+    pub feed_from: String,
+    pub stop_from: String,
+    pub feed_to: String,
+    pub stop_to: String,
+    pub old_seconds: u32,
+    pub new_seconds: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimetableDiff {
+    pub feeds: Vec<FeedDiff>,
+    pub old_transfers: usize,
+    pub new_transfers: usize,
+    /// The number of stop pairs in both timetables whose transfer time changed by at least the
+    /// threshold the diff was made with.
+    pub changed_transfers: usize,
+    /// The largest of those changes.
+    pub transfer_changes: Vec<TransferChange>,
+}
+
+// The GTFS IDs of a timetable's stops, routes and trips, grouped by feed.
+#[derive(Default)]
+struct Entities {
+    stops: BTreeMap<String, BTreeSet<String>>,
+    routes: BTreeMap<String, BTreeSet<String>>,
+    trips: BTreeMap<String, BTreeSet<String>>,
+}
+
+// The feed and GTFS ID of every stop, in stop order.
+fn stop_keys<'a>(timetable: &'a MmapTimetable<'a>) -> Vec<StopKey> {
+    if let Ok(keys) = timetable.stop_keys() {
+        return keys;
+    }
+    // Only concatenated timetables record feed segments, anything else is a single feed.
+    let feed = match timetable.manifest().feeds.as_slice() {
+        [feed] => feed.id.clone(),
+        _ => timetable
+            .base_path()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    timetable
+        .stops()
+        .iter()
+        .map(|stop| (feed.clone(), timetable.stop_metadata(stop).id))
+        .collect()
+}
+
+fn entities<'a>(
+    timetable: &'a MmapTimetable<'a>,
+    stop_keys: &[StopKey],
+) -> Result<Entities, anyhow::Error> {
+    let mut entities = Entities::default();
+    for (feed, stop_id) in stop_keys {
+        entities
+            .stops
+            .entry(feed.clone())
+            .or_default()
+            .insert(stop_id.clone());
+    }
+    // Read trip metadata in one transaction, there's a record for every service day of a trip.
+    let read = timetable.metadata_db().begin_read()?;
+    let table = read.open_table(TRIP_METADATA_TABLE)?;
+    for entry in table.iter()? {
+        let (_, bytes) = entry?;
+        let metadata: TripMetadata = rmp_serde::from_slice(bytes.value())?;
+        let feed = metadata.feed_id.unwrap_or_default();
+        if let Some(route_id) = metadata.route_id {
+            entities
+                .routes
+                .entry(feed.clone())
+                .or_default()
+                .insert(route_id);
+        }
+        if let Some(trip_id) = metadata.trip_id {
+            entities.trips.entry(feed).or_default().insert(trip_id);
+        }
+    }
+    Ok(entities)
+}
+
+// The stop pairs in both timetables whose transfer time changed by at least
+// `min_change_seconds`, with a sample of the largest changes.
+fn transfer_changes<'a, 'b>(
+    old: &'a MmapTimetable<'a>,
+    old_keys: &[StopKey],
+    new: &'b MmapTimetable<'b>,
+    new_keys: &[StopKey],
+    min_change_seconds: u32,
+    sample_size: usize,
+) -> (usize, Vec<TransferChange>) {
+    let new_indices: HashMap<&StopKey, usize> = new_keys
+        .iter()
+        .enumerate()
+        .map(|(idx, key)| (key, idx))
+        .collect();
+    let mut changed = 0usize;
+    // Min-heap of the largest changes so far: (change, old from, old to, old time, new time).
+    let mut largest = BinaryHeap::new();
+    for (old_from, key) in old_keys.iter().enumerate() {
+        let Some(new_from) = new_indices.get(key) else {
+            continue;
+        };
+        let new_times: HashMap<usize, u32> = new
+            .transfers_from(*new_from)
+            .iter()
+            .map(|transfer| (transfer.to_id(), transfer.time_seconds()))
+            .collect();
+        for transfer in old.transfers_from(old_from) {
+            let old_to = transfer.to_id();
+            let Some(new_time) = new_indices
+                .get(&old_keys[old_to])
+                .and_then(|new_to| new_times.get(new_to))
+            else {
+                continue;
+            };
+            let old_time = transfer.time_seconds();
+            let change = old_time.abs_diff(*new_time);
+            if change < min_change_seconds {
+                continue;
+            }
+            changed += 1;
+            largest.push(Reverse((change, old_from, old_to, old_time, *new_time)));
+            if largest.len() > sample_size {
+                largest.pop();
+            }
+        }
+    }
+    let sample = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(
+            |Reverse((_, from, to, old_seconds, new_seconds))| TransferChange {
+                feed_from: old_keys[from].0.clone(),
+                stop_from: old_keys[from].1.clone(),
+                feed_to: old_keys[to].0.clone(),
+                stop_to: old_keys[to].1.clone(),
+                old_seconds,
+                new_seconds,
+            },
+        )
+        .collect();
+    (changed, sample)
+}
+
+/// Compare two builds by the feed names and GTFS IDs in their metadata, since indices aren't
+/// stable between builds.
+pub fn diff_timetables<'a, 'b>(
+    old: &'a MmapTimetable<'a>,
+    new: &'b MmapTimetable<'b>,
+    min_transfer_change_seconds: u32,
+    sample_size: usize,
+) -> Result<TimetableDiff, anyhow::Error> {
+    let old_keys = stop_keys(old);
+    let new_keys = stop_keys(new);
+    let old_entities = entities(old, &old_keys)?;
+    let new_entities = entities(new, &new_keys)?;
+    let old_windows = old.feed_service_windows();
+    let new_windows = new.feed_service_windows();
+
+    let feed_names: BTreeSet<&String> = old_entities
+        .stops
+        .keys()
+        .chain(old_entities.trips.keys())
+        .chain(old_windows.keys())
+        .chain(new_entities.stops.keys())
+        .chain(new_entities.trips.keys())
+        .chain(new_windows.keys())
+        .collect();
+    let feeds = feed_names
+        .into_iter()
+        .map(|feed| FeedDiff {
+            feed: feed.clone(),
+            stops: SetDiff::between(old_entities.stops.get(feed), new_entities.stops.get(feed)),
+            routes: SetDiff::between(old_entities.routes.get(feed), new_entities.routes.get(feed)),
+            trips: SetDiff::between(old_entities.trips.get(feed), new_entities.trips.get(feed)),
+            old_service_window: old_windows.get(feed).copied(),
+            new_service_window: new_windows.get(feed).copied(),
+        })
+        .collect();
+
+    let (changed_transfers, transfer_changes) = transfer_changes(
+        old,
+        &old_keys,
+        new,
+        &new_keys,
+        min_transfer_change_seconds,
+        sample_size,
+    );
+    Ok(TimetableDiff {
+        feeds,
+        old_transfers: old.transfers().len(),
+        new_transfers: new.transfers().len(),
+        changed_transfers,
+        transfer_changes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_diff_lists_added_and_removed_ids() {
+        let old: BTreeSet<String> = ["1", "2", "3"].map(String::from).into();
+        let new: BTreeSet<String> = ["2", "3", "4"].map(String::from).into();
+        let diff = SetDiff::between(Some(&old), Some(&new));
+        assert_eq!(diff.added, vec!["4".to_string()]);
+        assert_eq!(diff.removed, vec!["1".to_string()]);
+
+        let removed_feed = SetDiff::between(Some(&old), None);
+        assert!(removed_feed.added.is_empty());
+        assert_eq!(removed_feed.removed.len(), 3);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use super::Translations;
+
+// Files from the GTFS spec that gtfs-structures doesn't parse. They're read straight out of the
+// feed, and a missing file is treated as empty.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FareProductRecord {
+    pub fare_product_id: String,
+    pub fare_product_name: Option<String>,
+    pub rider_category_id: Option<String>,
+    pub fare_media_id: Option<String>,
+    pub amount: f64,
+    pub currency: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FareLegRuleRecord {
+    pub leg_group_id: Option<String>,
+    pub network_id: Option<String>,
+    pub from_area_id: Option<String>,
+    pub to_area_id: Option<String>,
+    pub from_timeframe_group_id: Option<String>,
+    pub to_timeframe_group_id: Option<String>,
+    pub fare_product_id: String,
+    pub rule_priority: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FareTransferRuleRecord {
+    pub from_leg_group_id: Option<String>,
+    pub to_leg_group_id: Option<String>,
+    pub transfer_count: Option<i32>,
+    pub duration_limit: Option<u32>,
+    pub duration_limit_type: Option<u8>,
+    pub fare_transfer_type: u8,
+    pub fare_product_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StopAreaRecord {
+    pub area_id: String,
+    pub stop_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteNetworkRecord {
+    pub network_id: Option<String>,
+    pub route_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathwayRecord {
+    pub pathway_id: String,
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub pathway_mode: u8,
+    pub is_bidirectional: u8,
+    pub length: Option<f64>,
+    pub traversal_time: Option<u32>,
+    pub stair_count: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranslationRecord {
+    pub table_name: String,
+    pub field_name: String,
+    pub language: String,
+    pub translation: String,
+    pub record_id: Option<String>,
+    pub record_sub_id: Option<String>,
+    pub field_value: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GtfsExtra {
+    pub fare_products: Vec<FareProductRecord>,
+    pub fare_leg_rules: Vec<FareLegRuleRecord>,
+    pub fare_transfer_rules: Vec<FareTransferRuleRecord>,
+    pub stop_areas: Vec<StopAreaRecord>,
+    pub route_networks: Vec<RouteNetworkRecord>,
+    pub translations: Vec<TranslationRecord>,
+    pub pathways: Vec<PathwayRecord>,
+}
+
+impl GtfsExtra {
+    /// Read from a feed that's either a zip file or an extracted directory, like
+    /// `GtfsReader::read_from_path`. None of these files are needed to route on a feed, so a file
+    /// that can't be read is treated as empty and malformed rows are skipped, with a warning,
+    /// rather than failing the feed.
+    pub fn read_from_path(path: &Path) -> GtfsExtra {
+        let mut route_networks: Vec<RouteNetworkRecord> =
+            read_optional_table(path, "route_networks.txt");
+        // Networks can also be assigned with a network_id column in routes.txt.
+        route_networks.extend(
+            read_optional_table::<RouteNetworkRecord>(path, "routes.txt")
+                .into_iter()
+                .filter(|route| route.network_id.is_some()),
+        );
+        GtfsExtra {
+            fare_products: read_optional_table(path, "fare_products.txt"),
+            fare_leg_rules: read_optional_table(path, "fare_leg_rules.txt"),
+            fare_transfer_rules: read_optional_table(path, "fare_transfer_rules.txt"),
+            stop_areas: read_optional_table(path, "stop_areas.txt"),
+            route_networks,
+            translations: read_optional_table(path, "translations.txt"),
+            pathways: read_optional_table(path, "pathways.txt"),
+        }
+    }
+}
+
+/// Lookup of translations.txt rows by the record they apply to, or by the original value of the
+/// field when the feed translates by `field_value` instead of `record_id`.
+pub(crate) struct TranslationIndex<'a> {
+    by_record: HashMap<(&'a str, &'a str), Vec<&'a TranslationRecord>>,
+    by_value: HashMap<(&'a str, &'a str, &'a str), Vec<&'a TranslationRecord>>,
+}
+
+impl<'a> TranslationIndex<'a> {
+    pub(crate) fn new(extra: &'a GtfsExtra) -> TranslationIndex<'a> {
+        let mut by_record: HashMap<(&str, &str), Vec<&TranslationRecord>> = HashMap::new();
+        let mut by_value: HashMap<(&str, &str, &str), Vec<&TranslationRecord>> = HashMap::new();
+        for record in &extra.translations {
+            if let Some(record_id) = &record.record_id {
+                by_record
+                    .entry((record.table_name.as_str(), record_id.as_str()))
+                    .or_default()
+                    .push(record);
+            } else if let Some(field_value) = &record.field_value {
+                by_value
+                    .entry((
+                        record.table_name.as_str(),
+                        record.field_name.as_str(),
+                        field_value.as_str(),
+                    ))
+                    .or_default()
+                    .push(record);
+            }
+        }
+        TranslationIndex {
+            by_record,
+            by_value,
+        }
+    }
+
+    /// Translations for the record with `record_id` in `table_name`, given the original values of
+    /// the fields we care about.
+    pub(crate) fn lookup(
+        &self,
+        table_name: &str,
+        record_id: &str,
+        fields: &[(&str, Option<&str>)],
+    ) -> Translations {
+        let mut translations = Translations::default();
+        for (field_name, value) in fields {
+            if let Some(records) =
+                value.and_then(|value| self.by_value.get(&(table_name, *field_name, value)))
+            {
+                for record in records {
+                    translations.insert(field_name, &record.language, &record.translation);
+                }
+            }
+        }
+        // Translations keyed by record ID are more specific, so they win.
+        if let Some(records) = self.by_record.get(&(table_name, record_id)) {
+            for record in records {
+                if fields
+                    .iter()
+                    .any(|(field_name, _)| *field_name == record.field_name)
+                {
+                    translations.insert(&record.field_name, &record.language, &record.translation);
+                }
+            }
+        }
+        translations
+    }
+}
+
+pub(crate) fn read_table<T: DeserializeOwned>(
+    path: &Path,
+    file_name: &str,
+) -> Result<Vec<T>, anyhow::Error> {
+    let mut contents = vec![];
+    if path.is_dir() {
+        let file_path = path.join(file_name);
+        if !file_path.exists() {
+            return Ok(vec![]);
+        }
+        BufReader::new(File::open(file_path)?).read_to_end(&mut contents)?;
+    } else {
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+        // Some feeds put their files in a subdirectory of the archive.
+        let entry_name = archive
+            .file_names()
+            .find(|name| Path::new(name).file_name() == Some(file_name.as_ref()))
+            .map(|name| name.to_string());
+        let entry_name = if let Some(entry_name) = entry_name {
+            entry_name
+        } else {
+            return Ok(vec![]);
+        };
+        archive.by_name(&entry_name)?.read_to_end(&mut contents)?;
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_slice());
+    let mut records = vec![];
+    let mut skipped = 0usize;
+    let mut first_error = None;
+    for record in reader.deserialize() {
+        match record {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                skipped += 1;
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    if let Some(err) = first_error {
+        warn!(
+            "Skipped {} malformed rows of {} in {:?}, the first because: {}",
+            skipped, file_name, path, err
+        );
+    }
+    Ok(records)
+}
+
+fn read_optional_table<T: DeserializeOwned>(path: &Path, file_name: &str) -> Vec<T> {
+    read_table(path, file_name).unwrap_or_else(|err| {
+        warn!("Ignoring {} in {:?}: {}", file_name, path, err);
+        vec![]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn skips_malformed_optional_files_and_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        // The pre-standard Google translations.txt, which has none of the current columns.
+        fs::write(
+            path.join("translations.txt"),
+            "trans_id,lang,translation\nMain St,fr,Rue Principale\n",
+        )
+        .unwrap();
+        fs::write(
+            path.join("fare_leg_rules.txt"),
+            "leg_group_id,fare_product_id,rule_priority\nlocal,single,1\nexpress,single,high\n",
+        )
+        .unwrap();
+
+        let extra = GtfsExtra::read_from_path(path);
+        assert!(extra.translations.is_empty());
+        assert_eq!(extra.fare_leg_rules.len(), 1);
+        assert_eq!(
+            extra.fare_leg_rules[0].leg_group_id.as_deref(),
+            Some("local")
+        );
+    }
+}
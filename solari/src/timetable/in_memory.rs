@@ -1,6 +1,8 @@
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap},
-    f32, u32,
+    f32,
+    sync::Arc,
+    u32,
 };
 
 use anyhow::bail;
@@ -8,17 +10,30 @@ use chrono::{
     offset::LocalResult, DateTime, Days, Local, NaiveDate, NaiveTime, TimeDelta, TimeZone,
 };
 use chrono_tz::Tz;
-use gtfs_structures::{Agency, Gtfs, StopTime};
+use gtfs_structures::{
+    Agency, ContinuousPickupDropOff, Gtfs, PickupDropOffType, RouteType, StopTime, RGB8,
+};
 use rstar::RTree;
 use s2::{cellid::CellID, latlng::LatLng};
+use solari_geomath::EARTH_RADIUS_APPROX;
 use tracing::{debug, warn};
 
 use crate::{
+    fare::{FareData, StopFareInfo},
     spatial::IndexedStop,
     timetable::{Route, RouteStop, Stop, StopRoute, Transfer, Trip, TripStopTime},
 };
 
-use super::{ShapeCoordinate, Timetable, TripMetadata};
+use super::{
+    calendar::{ServiceCalendar, StorageMode},
+    global_id,
+    gtfs_extra::{GtfsExtra, TranslationIndex},
+    le::U64Le,
+    manifest::{BuildParams, FeedManifest},
+    shape::{stop_shape_indices, straight_line_shape},
+    station::{station_info, StopStationInfo},
+    ServiceWindow, ShapeCoordinate, Timetable, Translations, TripMetadata,
+};
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -29,11 +44,21 @@ pub(crate) struct InMemoryTimetable {
     stops: Vec<Stop>,
     stop_routes: Vec<StopRoute>,
     trip_stop_times: Vec<TripStopTime>,
-    transfer_index: Vec<usize>,
+    transfer_index: Vec<U64Le>,
     transfers: Vec<Transfer>,
     trip_metadata_map: HashMap<Trip, TripMetadata>,
     stop_metadata_map: HashMap<Stop, gtfs_structures::Stop>,
-    route_shapes: HashMap<Route, Option<Vec<ShapeCoordinate>>>,
+    route_shapes: HashMap<Route, Option<Arc<Vec<ShapeCoordinate>>>>,
+    route_shape_indices: HashMap<Route, Arc<Vec<u32>>>,
+    stop_fare_map: HashMap<Stop, StopFareInfo>,
+    fare_data: HashMap<String, FareData>,
+    service_windows: HashMap<String, ServiceWindow>,
+    stop_station_map: HashMap<Stop, StopStationInfo>,
+    stop_translations: HashMap<Stop, Translations>,
+    trip_translations: HashMap<Trip, Translations>,
+    route_translations: HashMap<Route, Translations>,
+    service_calendar: Option<ServiceCalendar>,
+    feed_id: String,
 }
 
 impl<'a> Timetable<'a> for InMemoryTimetable {
@@ -93,7 +118,7 @@ impl<'a> Timetable<'a> for InMemoryTimetable {
     }
 
     #[inline]
-    fn transfer_index(&'a self) -> &'a [usize] {
+    fn transfer_index(&'a self) -> &'a [U64Le] {
         &self.transfer_index
     }
 
@@ -102,7 +127,79 @@ impl<'a> Timetable<'a> for InMemoryTimetable {
     }
 
     fn trip_metadata(&'a self, trip: &Trip) -> TripMetadata {
-        self.trip_metadata_map[trip].clone()
+        self.trip_metadata_map[&trip.on_service_day(0)].clone()
+    }
+
+    fn trips_by_gtfs_id(&'a self, gtfs_trip_id: &str) -> Vec<&'a Trip> {
+        self.route_trips
+            .iter()
+            .filter(|trip| self.trip_metadata_map[trip].trip_id.as_deref() == Some(gtfs_trip_id))
+            .collect()
+    }
+
+    fn stop_global_id(&'a self, stop: &Stop) -> Option<String> {
+        Some(global_id(&self.feed_id, &self.stop_metadata_map[stop].id))
+    }
+
+    fn stop_by_global_id(&'a self, global_stop_id: &str) -> Option<&'a Stop> {
+        self.stops
+            .iter()
+            .find(|stop| self.stop_global_id(stop).as_deref() == Some(global_stop_id))
+    }
+
+    fn routes_by_global_id(&'a self, global_route_id: &str) -> Vec<&'a Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.global_id(self).as_deref() == Some(global_route_id))
+            .collect()
+    }
+
+    fn trips_by_global_id(&'a self, global_trip_id: &str) -> Vec<&'a Trip> {
+        self.route_trips
+            .iter()
+            .filter(|trip| {
+                self.trip_metadata_map[trip].global_trip_id().as_deref() == Some(global_trip_id)
+            })
+            .collect()
+    }
+
+    fn stop_fare_info(&'a self, stop: &Stop) -> Option<StopFareInfo> {
+        self.stop_fare_map.get(stop).cloned()
+    }
+
+    fn fare_data(&'a self, feed_id: &str) -> Option<FareData> {
+        self.fare_data.get(feed_id).cloned()
+    }
+
+    fn stop_station_info(&'a self, stop: &Stop) -> Option<StopStationInfo> {
+        self.stop_station_map.get(stop).cloned()
+    }
+
+    fn stop_translations(&'a self, stop: &Stop) -> Option<Translations> {
+        self.stop_translations.get(stop).cloned()
+    }
+
+    fn trip_translations(&'a self, trip: &Trip) -> Option<Translations> {
+        self.trip_translations.get(&trip.on_service_day(0)).cloned()
+    }
+
+    fn route_translations(&'a self, route: &Route) -> Option<Translations> {
+        self.route_translations.get(route).cloned()
+    }
+
+    fn service_window(&'a self) -> Option<ServiceWindow> {
+        self.service_windows
+            .values()
+            .copied()
+            .reduce(|a, b| a.union(&b))
+    }
+
+    fn feed_service_windows(&'a self) -> HashMap<String, ServiceWindow> {
+        self.service_windows.clone()
+    }
+
+    fn service_calendar(&'a self) -> Option<&'a ServiceCalendar> {
+        self.service_calendar.as_ref()
     }
 
     fn stop_index_copy(&'a self) -> RTree<IndexedStop> {
@@ -113,9 +210,13 @@ impl<'a> Timetable<'a> for InMemoryTimetable {
         Vec::new()
     }
 
-    fn route_shape(&'a self, route: &Route) -> Option<Vec<ShapeCoordinate>> {
+    fn route_shape(&'a self, route: &Route) -> Option<Arc<Vec<ShapeCoordinate>>> {
         self.route_shapes[route].clone()
     }
+
+    fn route_shape_indices(&'a self, route: &Route) -> Option<Arc<Vec<u32>>> {
+        self.route_shape_indices.get(route).cloned()
+    }
 }
 
 impl<'a> InMemoryTimetable {
@@ -132,6 +233,48 @@ impl<'a> InMemoryTimetable {
             trip_metadata_map: HashMap::new(),
             stop_metadata_map: HashMap::new(),
             route_shapes: HashMap::new(),
+            route_shape_indices: HashMap::new(),
+            stop_fare_map: HashMap::new(),
+            fare_data: HashMap::new(),
+            service_windows: HashMap::new(),
+            stop_station_map: HashMap::new(),
+            stop_translations: HashMap::new(),
+            trip_translations: HashMap::new(),
+            route_translations: HashMap::new(),
+            service_calendar: None,
+            feed_id: String::new(),
+        }
+    }
+
+    pub(crate) fn fare_data_map(&self) -> &HashMap<String, FareData> {
+        &self.fare_data
+    }
+}
+
+#[cfg(test)]
+impl InMemoryTimetable {
+    /// A timetable of just the given entities, for testing code that only looks at its structure.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_entities(
+        routes: Vec<Route>,
+        route_stops: Vec<RouteStop>,
+        route_trips: Vec<Trip>,
+        stops: Vec<Stop>,
+        stop_routes: Vec<StopRoute>,
+        trip_stop_times: Vec<TripStopTime>,
+        transfer_index: Vec<U64Le>,
+        transfers: Vec<Transfer>,
+    ) -> InMemoryTimetable {
+        InMemoryTimetable {
+            routes,
+            route_stops,
+            route_trips,
+            stops,
+            stop_routes,
+            trip_stop_times,
+            transfer_index,
+            transfers,
+            ..InMemoryTimetable::new()
         }
     }
 }
@@ -150,11 +293,18 @@ pub struct InMemoryTimetableBuilder {
     next_route_trip_id: usize,
     next_route_stop_id: usize,
     next_trip_stop_time_id: usize,
+    feed_id: String,
     pub(crate) timetable: InMemoryTimetable,
+    pub(crate) build_params: BuildParams,
+    // BLAKE3 hash of the GTFS zip, if the feed was read from one.
+    pub(crate) source_hash: Option<String>,
 
     stop_table: BTreeMap<StopKey, StopData>,
     route_index: BTreeMap<RouteKey, RouteId>,
     route_table: BTreeMap<RouteId, RouteData>,
+    // Calendar indices of each (timezone, service_id) when building a pattern timetable.
+    calendar_zones: HashMap<String, u32>,
+    calendar_services: HashMap<(u32, String), u32>,
 }
 
 #[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Eq, Ord)]
@@ -168,6 +318,8 @@ struct TripInternal {
     service_day_start: DateTime<chrono_tz::Tz>,
     stop_times: Vec<StopTime>,
     gtfs_trip_id: String,
+    // The trip's service in the calendar when building a pattern timetable.
+    service: Option<u32>,
 }
 
 impl TripInternal {
@@ -201,6 +353,7 @@ struct RouteData {
     trip_list: Vec<TripInternal>,
     stops: Vec<StopId>,
     shape_distances: Vec<f32>,
+    shape_indices: Option<Vec<u32>>,
     agency_name: Option<String>,
 }
 
@@ -217,11 +370,34 @@ struct StopData {
 }
 
 impl<'a> InMemoryTimetableBuilder {
+    pub(crate) fn feed_manifest(&self) -> FeedManifest {
+        FeedManifest {
+            id: self.feed_id.clone(),
+            source_hash: self.source_hash.clone(),
+        }
+    }
+
     pub fn new(
         gtfs: &Gtfs,
+        extra: &GtfsExtra,
+        feed_id: &str,
         start_date: Option<NaiveDate>,
         num_days: Option<u16>,
+        storage_mode: StorageMode,
     ) -> Result<Self, anyhow::Error> {
+        // Record the dates actually used, so an incremental rebuild can cover the same ones.
+        let start_date = start_date.unwrap_or(
+            Local::now()
+                .date_naive()
+                .checked_sub_days(Days::new(7))
+                .unwrap(),
+        );
+        let num_days = num_days.unwrap_or(21);
+        let mut timetable = InMemoryTimetable::new();
+        timetable.feed_id = feed_id.to_string();
+        if storage_mode == StorageMode::Pattern {
+            timetable.service_calendar = Some(ServiceCalendar::default());
+        }
         let mut builder = InMemoryTimetableBuilder {
             next_stop_id: 0,
             next_stop_route_id: 0,
@@ -229,12 +405,21 @@ impl<'a> InMemoryTimetableBuilder {
             next_route_trip_id: 0,
             next_route_stop_id: 0,
             next_trip_stop_time_id: 0,
-            timetable: InMemoryTimetable::new(),
+            feed_id: feed_id.to_string(),
+            timetable,
+            build_params: BuildParams {
+                start_date: Some(start_date),
+                num_days: Some(num_days),
+                storage_mode,
+            },
+            source_hash: None,
             stop_table: BTreeMap::new(),
             route_index: BTreeMap::new(),
             route_table: BTreeMap::new(),
+            calendar_zones: HashMap::new(),
+            calendar_services: HashMap::new(),
         };
-        builder.preprocess_gtfs(gtfs, start_date, num_days)?;
+        builder.preprocess_gtfs(gtfs, extra, start_date, num_days)?;
         Ok(builder)
     }
 
@@ -281,7 +466,7 @@ impl<'a> InMemoryTimetableBuilder {
             self.next_route_id += 1;
 
             // Determine the path that the route travels.
-            let shape: Option<Vec<ShapeCoordinate>> = if let Some(shape_id) = &trip.shape_id {
+            let mut shape: Option<Vec<ShapeCoordinate>> = if let Some(shape_id) = &trip.shape_id {
                 if let Ok(coords) = gtfs.get_shape(&shape_id) {
                     Some(
                         coords
@@ -308,6 +493,22 @@ impl<'a> InMemoryTimetableBuilder {
                 .map(|time| time.shape_dist_traveled.unwrap_or(f32::NAN))
                 .collect();
 
+            // Precompute where along the shape each stop is, so a leg's geometry is a slice of it.
+            let stop_locations: Option<Vec<(f64, f64)>> = trip
+                .stop_times
+                .iter()
+                .map(|stop_time| stop_location(gtfs, &stop_time.stop))
+                .collect();
+            let shape_indices = stop_locations.and_then(|stop_locations| {
+                let shape = shape.get_or_insert_with(|| straight_line_shape(&stop_locations));
+                let stops: Vec<(f64, f64, f32)> = stop_locations
+                    .iter()
+                    .zip(&shape_distances)
+                    .map(|((lat, lon), distance)| (*lat, *lon, *distance))
+                    .collect();
+                stop_shape_indices(shape, &stops)
+            });
+
             let stops = trip
                 .stop_times
                 .iter()
@@ -337,6 +538,7 @@ impl<'a> InMemoryTimetableBuilder {
                     trip_list: vec![],
                     stops,
                     shape_distances,
+                    shape_indices,
                     agency_name,
                 },
             );
@@ -354,25 +556,19 @@ impl<'a> InMemoryTimetableBuilder {
     fn preprocess_gtfs(
         &mut self,
         gtfs: &Gtfs,
-        start_date: Option<NaiveDate>,
-        num_days: Option<u16>,
+        extra: &GtfsExtra,
+        start_date: NaiveDate,
+        num_days: u16,
     ) -> Result<(), anyhow::Error> {
         let agencies: HashMap<String, &Agency> = gtfs
             .agencies
             .iter()
             .map(|agency| (agency.id.clone().unwrap_or(String::new()), agency))
             .collect();
-        let start_date = start_date.unwrap_or(
-            Local::now()
-                .date_naive()
-                .checked_sub_days(Days::new(7))
-                .unwrap(),
-        );
-        let num_days = num_days.unwrap_or(21);
-
         // First things first, go through every trip in the feed.
         for (gtfs_trip_id, trip) in &gtfs.trips {
             {
+                let stop_times = interpolate_stop_times(gtfs, &trip.stop_times);
                 let agency_id = if let Some(agency_id) = gtfs
                     .routes
                     .get(&trip.route_id)
@@ -386,45 +582,40 @@ impl<'a> InMemoryTimetableBuilder {
                 let tz = self
                     .trip_agency_timezone(&agencies, &agency_id)
                     .expect("Failed to parse timezone");
-                let route_data = self.lookup_route_data(gtfs, trip);
-                let trip_days = gtfs.trip_days(&trip.service_id, start_date.clone());
-                for day in trip_days {
-                    if day <= num_days {
-                        let date_time_offset = start_date
-                            .checked_add_days(Days::new(day as u64))
-                            .expect(&format!(
-                                "Failed to add {day} days to date {:?}",
-                                start_date
-                            ));
-                        // The start of a service day is defined as noon minus 12 hours.
-                        let noon_service_day = match tz.from_local_datetime(
-                            &date_time_offset.and_time(
-                                NaiveTime::from_hms_opt(12, 0, 0)
-                                    .expect("Failed to add 12 hours to service day"),
-                            ),
-                        ) {
-                            LocalResult::Single(date_time) => date_time,
-                            LocalResult::Ambiguous(a, _b) => {
-                                // Pick one and call it good.
-                                a
-                            }
-                            LocalResult::None => {
-                                bail!("Gap in time (at noon? shouldn't be possible), can't determine service day start")
-                            }
-                        };
-                        let service_day_start = noon_service_day
-                            .checked_sub_signed(TimeDelta::hours(12))
-                            .expect(
-                                "Failed to subtract 12 hours from noon on the given service day.",
-                            );
-
-                        // Once we've assembled all the necessary data, push a trip to the route_data's trip_list for use later in `process_routes_trips`.
+                if self.timetable.service_calendar.is_some() {
+                    let service =
+                        self.calendar_service(gtfs, &tz, &trip.service_id, start_date, num_days)?;
+                    let route_data = self.lookup_route_data(gtfs, trip);
+                    if let Some(service) = service {
+                        // Pattern trips keep GTFS times, relative to whichever day they run on.
                         route_data.trip_list.push(TripInternal {
-                            service_day_start,
-                            stop_times: trip.stop_times.clone(),
+                            service_day_start: Tz::UTC.timestamp_opt(0, 0).unwrap(),
+                            stop_times,
                             gtfs_trip_id: gtfs_trip_id.clone(),
+                            service: Some(service),
                         });
                     }
+                } else {
+                    let route_data = self.lookup_route_data(gtfs, trip);
+                    let trip_days = gtfs.trip_days(&trip.service_id, start_date.clone());
+                    for day in trip_days {
+                        if day <= num_days {
+                            let date_time_offset =
+                                start_date.checked_add_days(Days::new(day as u64)).expect(
+                                    &format!("Failed to add {day} days to date {:?}", start_date),
+                                );
+                            let service_day_start = service_day_start(&tz, date_time_offset)?;
+
+                            // Once we've assembled all the necessary data, push a trip to the
+                            // route_data's trip_list for use later in `process_routes_trips`.
+                            route_data.trip_list.push(TripInternal {
+                                service_day_start,
+                                stop_times: stop_times.clone(),
+                                gtfs_trip_id: gtfs_trip_id.clone(),
+                                service: None,
+                            });
+                        }
+                    }
                 }
             }
             let route_id = self.lookup_route_data(gtfs, trip).id;
@@ -437,44 +628,152 @@ impl<'a> InMemoryTimetableBuilder {
 
         debug!("Done sorting");
 
-        self.process_routes_trips(gtfs)?;
+        let translations = TranslationIndex::new(extra);
 
-        self.process_stops(gtfs)?;
+        self.process_routes_trips(gtfs, &translations)?;
+
+        self.process_stops(gtfs, extra, &translations)?;
+
+        self.record_service_window(&agencies, start_date, num_days)?;
+
+        let fare_data = FareData::new(gtfs, extra);
+        if !fare_data.is_empty() {
+            self.timetable
+                .fare_data
+                .insert(self.feed_id.clone(), fare_data);
+        }
 
         Result::Ok(())
     }
 
-    fn process_routes_trips(&mut self, gtfs: &Gtfs) -> Result<(), anyhow::Error> {
+    fn process_routes_trips(
+        &mut self,
+        gtfs: &Gtfs,
+        translations: &TranslationIndex,
+    ) -> Result<(), anyhow::Error> {
         // TODO: How to deal with this route_table.clone()? It indicates an architectural problem IMO.
         for (_, route_data) in self.route_table.clone().iter() {
-            let route = Route {
-                route_index: route_data.id.0,
-                first_route_stop: self.next_route_stop_id,
-                first_route_trip: self.next_route_trip_id,
-            };
+            let route = Route::new(
+                route_data.id.0,
+                self.next_route_stop_id,
+                self.next_route_trip_id,
+            );
             self.timetable
                 .route_shapes
-                .insert(route, route_data.shape.clone());
+                .insert(route, route_data.shape.clone().map(Arc::new));
+            if let Some(shape_indices) = &route_data.shape_indices {
+                self.timetable
+                    .route_shape_indices
+                    .insert(route, Arc::new(shape_indices.clone()));
+            }
             self.timetable.routes.push(route);
+            let gtfs_route = &gtfs.routes[&route_data.gtfs_route_id];
+            let route_translations = translations.lookup(
+                "routes",
+                &route_data.gtfs_route_id,
+                &[
+                    ("route_short_name", gtfs_route.short_name.as_deref()),
+                    ("route_long_name", gtfs_route.long_name.as_deref()),
+                ],
+            );
+            if !route_translations.is_empty() {
+                self.timetable
+                    .route_translations
+                    .insert(route, route_translations);
+            }
 
             for (stop_seq, stop_id) in route_data.stops.iter().enumerate() {
-                self.timetable.route_stops.push(RouteStop {
-                    route_index: route_data.id.0,
-                    stop_index: stop_id.0,
-                    stop_seq: stop_seq as u32,
-                    distance_along_route: route_data.shape_distances[stop_seq],
-                });
+                self.timetable.route_stops.push(RouteStop::new(
+                    route_data.id.0,
+                    stop_id.0,
+                    stop_seq,
+                    route_data.shape_distances[stop_seq],
+                ));
                 self.next_route_stop_id += 1;
             }
             let mut trips = route_data.trip_list.clone();
             trips.sort_by_cached_key(|trip| trip.get_departure());
             for trip in &trips {
-                self.process_trip(gtfs, route_data, trip)?;
+                self.process_trip(gtfs, translations, route_data, trip)?;
             }
         }
         Ok(())
     }
 
+    fn record_service_window(
+        &mut self,
+        agencies: &HashMap<String, &Agency>,
+        start_date: NaiveDate,
+        num_days: u16,
+    ) -> Result<(), anyhow::Error> {
+        // Feeds are expected to use a single timezone for all agencies, so any agency will do.
+        let tz: Tz = if let Some(agency) = agencies.values().next() {
+            agency.timezone.parse().unwrap_or(Tz::UTC)
+        } else {
+            return Ok(());
+        };
+        let end_date = start_date
+            .checked_add_days(Days::new(num_days as u64 + 1))
+            .expect("Failed to compute end of service window");
+        let window = ServiceWindow {
+            start_date,
+            num_days,
+            valid_from: service_day_start(&tz, start_date)?.timestamp() as u32,
+            valid_until: service_day_start(&tz, end_date)?.timestamp() as u32,
+        };
+        self.timetable
+            .service_windows
+            .insert(self.feed_id.clone(), window);
+        Ok(())
+    }
+
+    // The calendar service for a GTFS service in the given timezone, or None if it doesn't run
+    // during the service window.
+    fn calendar_service(
+        &mut self,
+        gtfs: &Gtfs,
+        tz: &Tz,
+        service_id: &str,
+        start_date: NaiveDate,
+        num_days: u16,
+    ) -> Result<Option<u32>, anyhow::Error> {
+        let calendar = self
+            .timetable
+            .service_calendar
+            .as_mut()
+            .expect("Calendar services are only used for pattern timetables");
+        let zone = if let Some(zone) = self.calendar_zones.get(tz.name()) {
+            *zone
+        } else {
+            let day_starts = (0..=num_days)
+                .map(|day| {
+                    let date = start_date
+                        .checked_add_days(Days::new(day as u64))
+                        .expect("Failed to compute service day");
+                    Ok(service_day_start(tz, date)?.timestamp() as u32)
+                })
+                .collect::<Result<Vec<u32>, anyhow::Error>>()?;
+            let zone = calendar.add_zone(start_date, day_starts);
+            self.calendar_zones.insert(tz.name().to_string(), zone);
+            zone
+        };
+        let key = (zone, service_id.to_string());
+        if let Some(service) = self.calendar_services.get(&key) {
+            return Ok(Some(*service));
+        }
+        let days: Vec<u16> = gtfs
+            .trip_days(service_id, start_date)
+            .into_iter()
+            .filter(|day| *day <= num_days)
+            .collect();
+        if days.is_empty() {
+            return Ok(None);
+        }
+        let service = calendar.add_service(zone, days);
+        self.calendar_services.insert(key, service);
+        Ok(Some(service))
+    }
+
     fn trip_agency_timezone(
         &self,
         agencies: &HashMap<String, &Agency>,
@@ -501,6 +800,7 @@ impl<'a> InMemoryTimetableBuilder {
     fn process_trip(
         &mut self,
         gtfs: &Gtfs,
+        translations: &TranslationIndex,
         route_data: &RouteData,
         trip: &TripInternal,
     ) -> Result<(), anyhow::Error> {
@@ -526,61 +826,133 @@ impl<'a> InMemoryTimetableBuilder {
                     stop_time.departure_time.unwrap_or(u32::MAX) as i64,
                 ))
                 .unwrap();
+            let previous = stop_seq.checked_sub(1).map(|previous_seq| {
+                let previous = &trip.stop_times[previous_seq];
+                (
+                    continuous_code(&previous.continuous_pickup),
+                    continuous_code(&previous.continuous_drop_off),
+                )
+            });
+            let boarding = TripStopTime::boarding_flags(
+                pickup_drop_off_code(&stop_time.pickup_type),
+                pickup_drop_off_code(&stop_time.drop_off_type),
+                continuous_code(&stop_time.continuous_pickup),
+                continuous_code(&stop_time.continuous_drop_off),
+                previous,
+            );
             self.timetable.trip_stop_times.push(TripStopTime::new(
                 self.next_route_trip_id,
                 stop_seq,
                 arrival_time,
                 departure_time,
+                boarding,
             ));
             self.next_trip_stop_time_id += 1;
         }
         let gtfs_trip = gtfs
             .get_trip(&trip.gtfs_trip_id)
             .expect("Trip not found in trip table.");
-        let trip = Trip {
-            trip_index: self.next_route_trip_id,
-            route_index: route_data.id.0,
-            first_trip_stop_time,
-            last_trip_stop_time: self.next_trip_stop_time_id,
+        let gtfs_trip_id = trip.gtfs_trip_id.clone();
+        // Pattern trips run on many days, which the calendar resolves at query time.
+        let service_date = if trip.service.is_none() {
+            Some(trip.service_day_start.date_naive())
+        } else {
+            None
         };
+        if let Some(calendar) = &mut self.timetable.service_calendar {
+            calendar.push_trip(trip.service);
+        }
+        let trip = Trip::new(
+            self.next_route_trip_id,
+            route_data.id.0,
+            first_trip_stop_time,
+            self.next_trip_stop_time_id,
+        );
         self.timetable.route_trips.push(trip);
+        let gtfs_route = &gtfs.routes[&route_data.gtfs_route_id];
         let metadata = TripMetadata {
             agency_name: route_data.agency_name.clone(),
             headsign: gtfs_trip.trip_headsign.clone(),
-            route_name: gtfs.routes[&route_data.gtfs_route_id].short_name.clone(),
+            route_name: gtfs_route.short_name.clone(),
+            trip_id: Some(gtfs_trip_id),
+            service_date,
+            route_id: Some(route_data.gtfs_route_id.clone()),
+            feed_id: Some(self.feed_id.clone()),
+            route_long_name: gtfs_route.long_name.clone(),
+            route_color: Some(hex_color(&gtfs_route.color)),
+            route_text_color: Some(hex_color(&gtfs_route.text_color)),
+            route_type: Some(route_type_code(&gtfs_route.route_type)),
         };
         self.timetable.trip_metadata_map.insert(trip, metadata);
+        let trip_translations = translations.lookup(
+            "trips",
+            &gtfs_trip.id,
+            &[("trip_headsign", gtfs_trip.trip_headsign.as_deref())],
+        );
+        if !trip_translations.is_empty() {
+            self.timetable
+                .trip_translations
+                .insert(trip, trip_translations);
+        }
 
         self.next_route_trip_id += 1;
 
         Ok(())
     }
 
-    fn process_stops(&mut self, gtfs: &Gtfs) -> Result<(), anyhow::Error> {
+    fn process_stops(
+        &mut self,
+        gtfs: &Gtfs,
+        extra: &GtfsExtra,
+        translations: &TranslationIndex,
+    ) -> Result<(), anyhow::Error> {
+        let mut stop_areas: HashMap<&String, Vec<String>> = HashMap::new();
+        for stop_area in &extra.stop_areas {
+            stop_areas
+                .entry(&stop_area.stop_id)
+                .or_default()
+                .push(stop_area.area_id.clone());
+        }
         let mut sorted_stops: Vec<&StopData> = self.stop_table.values().collect();
         sorted_stops.sort_by_cached_key(|stop_data| stop_data.id);
         for stop_data in sorted_stops {
             let gtfs_stop = gtfs.get_stop(&stop_data.gtfs_id).unwrap();
-            let lat = if let Some(lat) = gtfs_stop.latitude {
-                lat
+            let (lat, lng) = if let Some(location) = stop_location(gtfs, gtfs_stop) {
+                location
             } else {
-                bail!("Can't process feeds containing stop IDs without lat/lng")
-            };
-            let lng = if let Some(lng) = gtfs_stop.longitude {
-                lng
-            } else {
-                bail!("Can't process feeds containing stop IDs without lat/lng")
+                bail!(
+                    "Stop {} has no lat/lng, and neither does its parent station",
+                    gtfs_stop.id
+                )
             };
             let s2cell: CellID = LatLng::from_degrees(lat, lng).into();
-            let stop = Stop {
-                stop_index: stop_data.id.0,
-                s2cell: s2cell.0,
-                first_stop_route_index: self.next_stop_route_id,
-            };
+            let stop = Stop::new(stop_data.id.0, s2cell.0, self.next_stop_route_id);
             self.timetable.stops.push(stop);
-            self.timetable
-                .stop_metadata_map
-                .insert(stop, gtfs_stop.clone());
+            let mut stop_metadata = gtfs_stop.clone();
+            stop_metadata.latitude = Some(lat);
+            stop_metadata.longitude = Some(lng);
+            self.timetable.stop_metadata_map.insert(stop, stop_metadata);
+            let stop_translations = translations.lookup(
+                "stops",
+                &gtfs_stop.id,
+                &[("stop_name", gtfs_stop.name.as_deref())],
+            );
+            if !stop_translations.is_empty() {
+                self.timetable
+                    .stop_translations
+                    .insert(stop, stop_translations);
+            }
+            self.timetable.stop_fare_map.insert(
+                stop,
+                StopFareInfo {
+                    feed_id: self.feed_id.clone(),
+                    zone_id: gtfs_stop.zone_id.clone(),
+                    area_ids: stop_areas
+                        .get(&stop_data.gtfs_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                },
+            );
             for route in &stop_data.stop_routes {
                 let mut seq = 0usize;
                 let mut found_seq = false;
@@ -592,13 +964,263 @@ impl<'a> InMemoryTimetableBuilder {
                     seq += 1;
                 }
                 assert!(found_seq);
-                self.timetable.stop_routes.push(StopRoute {
-                    route_index: route.0,
-                    stop_seq: seq,
-                });
+                self.timetable
+                    .stop_routes
+                    .push(StopRoute::new(route.0, seq));
                 self.next_stop_route_id += 1;
             }
         }
+
+        let stop_indices: HashMap<String, usize> = self
+            .stop_table
+            .values()
+            .map(|stop_data| (stop_data.gtfs_id.clone(), stop_data.id.0))
+            .collect();
+        for (gtfs_id, station_info) in station_info(gtfs, extra, &stop_indices) {
+            let stop = self.timetable.stops[stop_indices[&gtfs_id]];
+            self.timetable.stop_station_map.insert(stop, station_info);
+        }
         Ok(())
     }
 }
+
+fn hex_color(color: &RGB8) -> String {
+    format!("{:02X}{:02X}{:02X}", color.r, color.g, color.b)
+}
+
+fn pickup_drop_off_code(pickup_drop_off: &PickupDropOffType) -> u8 {
+    match pickup_drop_off {
+        PickupDropOffType::Regular => 0,
+        PickupDropOffType::NotAvailable => 1,
+        PickupDropOffType::ArrangeByPhone => 2,
+        PickupDropOffType::CoordinateWithDriver => 3,
+        PickupDropOffType::Unknown(_) => 0,
+    }
+}
+
+fn continuous_code(continuous: &ContinuousPickupDropOff) -> u8 {
+    match continuous {
+        ContinuousPickupDropOff::Continuous => 0,
+        ContinuousPickupDropOff::NotAvailable => 1,
+        ContinuousPickupDropOff::ArrangeByPhone => 2,
+        ContinuousPickupDropOff::CoordinateWithDriver => 3,
+        ContinuousPickupDropOff::Unknown(_) => 1,
+    }
+}
+
+// The numeric route_type from routes.txt, including extended route types.
+fn route_type_code(route_type: &RouteType) -> i16 {
+    match route_type {
+        RouteType::Tramway => 0,
+        RouteType::Subway => 1,
+        RouteType::Rail => 2,
+        RouteType::Bus => 3,
+        RouteType::Ferry => 4,
+        RouteType::CableCar => 5,
+        RouteType::Gondola => 6,
+        RouteType::Funicular => 7,
+        RouteType::Coach => 200,
+        RouteType::Air => 1100,
+        RouteType::Taxi => 1500,
+        RouteType::Other(code) => *code,
+    }
+}
+
+// The start of a service day is defined as noon minus 12 hours.
+fn service_day_start(tz: &Tz, date: NaiveDate) -> Result<DateTime<Tz>, anyhow::Error> {
+    let noon_service_day = match tz.from_local_datetime(&date.and_time(
+        NaiveTime::from_hms_opt(12, 0, 0).expect("Failed to add 12 hours to service day"),
+    )) {
+        LocalResult::Single(date_time) => date_time,
+        LocalResult::Ambiguous(a, _b) => {
+            // Pick one and call it good.
+            a
+        }
+        LocalResult::None => {
+            bail!("Gap in time (at noon? shouldn't be possible), can't determine service day start")
+        }
+    };
+    Ok(noon_service_day
+        .checked_sub_signed(TimeDelta::hours(12))
+        .expect("Failed to subtract 12 hours from noon on the given service day."))
+}
+
+/// A stop's coordinates, or those of the nearest ancestor station that has them. Entrances, generic
+/// nodes and boarding areas are allowed to leave them out.
+pub(crate) fn stop_location(gtfs: &Gtfs, stop: &gtfs_structures::Stop) -> Option<(f64, f64)> {
+    let mut current = stop;
+    // Station hierarchies are at most three levels deep, but guard against cycles anyway.
+    for _ in 0..4 {
+        if let (Some(lat), Some(lng)) = (current.latitude, current.longitude) {
+            return Some((lat, lng));
+        }
+        current = gtfs.stops.get(current.parent_station.as_ref()?)?;
+    }
+    None
+}
+
+// Only timepoints are required to have arrival and departure times. Fill in the rest by
+// interpolating between the surrounding timepoints, proportionally to shape_dist_traveled if the
+// feed has it, and to the straight-line distance between stops otherwise.
+fn interpolate_stop_times(gtfs: &Gtfs, stop_times: &[StopTime]) -> Vec<StopTime> {
+    let mut stop_times = stop_times.to_vec();
+    for stop_time in &mut stop_times {
+        if stop_time.arrival_time.is_none() {
+            stop_time.arrival_time = stop_time.departure_time;
+        }
+        if stop_time.departure_time.is_none() {
+            stop_time.departure_time = stop_time.arrival_time;
+        }
+    }
+    if stop_times
+        .iter()
+        .all(|stop_time| stop_time.arrival_time.is_some())
+    {
+        return stop_times;
+    }
+
+    let shape_distances: Option<Vec<f64>> = stop_times
+        .iter()
+        .map(|stop_time| stop_time.shape_dist_traveled.map(|dist| dist as f64))
+        .collect();
+    let distances = shape_distances.unwrap_or_else(|| {
+        let mut total = 0f64;
+        let mut previous: Option<LatLng> = None;
+        stop_times
+            .iter()
+            .map(|stop_time| {
+                let location = stop_location(gtfs, &stop_time.stop)
+                    .map(|(lat, lng)| LatLng::from_degrees(lat, lng));
+                if let (Some(previous), Some(location)) = (previous, location) {
+                    total += previous.distance(&location).rad() * EARTH_RADIUS_APPROX;
+                }
+                previous = location.or(previous);
+                total
+            })
+            .collect()
+    });
+
+    let timed: Vec<usize> = (0..stop_times.len())
+        .filter(|idx| stop_times[*idx].arrival_time.is_some())
+        .collect();
+    for window in timed.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        if end == start + 1 {
+            continue;
+        }
+        let start_time = stop_times[start].departure_time.unwrap();
+        let end_time = stop_times[end].arrival_time.unwrap();
+        let span = distances[end] - distances[start];
+        for idx in start + 1..end {
+            let fraction = if span > 0f64 {
+                ((distances[idx] - distances[start]) / span).clamp(0f64, 1f64)
+            } else {
+                (idx - start) as f64 / (end - start) as f64
+            };
+            let time = start_time + (end_time.saturating_sub(start_time) as f64 * fraction) as u32;
+            stop_times[idx].arrival_time = Some(time);
+            stop_times[idx].departure_time = Some(time);
+        }
+    }
+    stop_times
+}
+
+#[cfg(test)]
+mod tests {
+    use gtfs_structures::{Gtfs, StopTime};
+
+    use super::interpolate_stop_times;
+
+    fn stop_time(time: Option<u32>, shape_dist_traveled: Option<f32>) -> StopTime {
+        StopTime {
+            arrival_time: time,
+            departure_time: time,
+            shape_dist_traveled,
+            ..Default::default()
+        }
+    }
+
+    fn times(stop_times: &[StopTime]) -> Vec<Option<u32>> {
+        stop_times
+            .iter()
+            .map(|stop_time| stop_time.arrival_time)
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_interior_gaps_by_shape_distance() {
+        let stop_times = interpolate_stop_times(
+            &Gtfs::default(),
+            &[
+                stop_time(Some(100), Some(0.0)),
+                stop_time(None, Some(10.0)),
+                stop_time(None, Some(40.0)),
+                stop_time(Some(200), Some(50.0)),
+                stop_time(None, Some(75.0)),
+                stop_time(Some(300), Some(100.0)),
+            ],
+        );
+        assert_eq!(
+            times(&stop_times),
+            vec![
+                Some(100),
+                Some(120),
+                Some(180),
+                Some(200),
+                Some(250),
+                Some(300)
+            ]
+        );
+        assert!(stop_times
+            .iter()
+            .all(|stop_time| stop_time.departure_time == stop_time.arrival_time));
+    }
+
+    #[test]
+    fn leaves_leading_and_trailing_stops_untimed() {
+        let stop_times = interpolate_stop_times(
+            &Gtfs::default(),
+            &[
+                stop_time(None, Some(0.0)),
+                stop_time(Some(100), Some(10.0)),
+                stop_time(None, Some(20.0)),
+                stop_time(Some(200), Some(30.0)),
+                stop_time(None, Some(40.0)),
+            ],
+        );
+        assert_eq!(
+            times(&stop_times),
+            vec![None, Some(100), Some(150), Some(200), None]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_uniform_spacing_without_distances() {
+        // Some stops are missing shape_dist_traveled and none have locations, so there's no
+        // distance to interpolate by.
+        let stop_times = interpolate_stop_times(
+            &Gtfs::default(),
+            &[
+                stop_time(Some(100), Some(0.0)),
+                stop_time(None, None),
+                stop_time(None, Some(5.0)),
+                stop_time(Some(160), None),
+            ],
+        );
+        assert_eq!(
+            times(&stop_times),
+            vec![Some(100), Some(120), Some(140), Some(160)]
+        );
+
+        // Every stop is at the same shape distance.
+        let stop_times = interpolate_stop_times(
+            &Gtfs::default(),
+            &[
+                stop_time(Some(100), Some(5.0)),
+                stop_time(None, Some(5.0)),
+                stop_time(Some(200), Some(5.0)),
+            ],
+        );
+        assert_eq!(times(&stop_times), vec![Some(100), Some(150), Some(200)]);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{cmp::Ordering, fmt, ops::AddAssign};
+
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+
+// Little-endian integers for the memory-mapped timetable files, so they read the same on every
+// host. On little-endian hosts the conversions compile to nothing.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Pod, Zeroable, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+#[repr(transparent)]
+pub struct U32Le(u32);
+
+impl U32Le {
+    #[inline]
+    pub const fn new(value: u32) -> U32Le {
+        U32Le(value.to_le())
+    }
+
+    /// Panics if the index doesn't fit, rather than silently wrapping.
+    #[inline]
+    pub fn index(index: usize) -> U32Le {
+        U32Le::new(u32::try_from(index).expect("Index doesn't fit in 32 bits"))
+    }
+
+    #[inline]
+    pub const fn get(self) -> u32 {
+        u32::from_le(self.0)
+    }
+
+    #[inline]
+    pub const fn usize(self) -> usize {
+        self.get() as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Pod, Zeroable, Serialize, Deserialize)]
+#[serde(from = "u64", into = "u64")]
+#[repr(transparent)]
+pub struct U64Le(u64);
+
+impl U64Le {
+    #[inline]
+    pub const fn new(value: u64) -> U64Le {
+        U64Le(value.to_le())
+    }
+
+    #[inline]
+    pub fn index(index: usize) -> U64Le {
+        U64Le::new(index as u64)
+    }
+
+    #[inline]
+    pub const fn get(self) -> u64 {
+        u64::from_le(self.0)
+    }
+
+    #[inline]
+    pub const fn usize(self) -> usize {
+        self.get() as usize
+    }
+}
+
+// Stored as the little-endian bits of the float.
+#[derive(Clone, Copy, PartialEq, Default, Pod, Zeroable)]
+#[repr(transparent)]
+pub struct F32Le(u32);
+
+impl F32Le {
+    #[inline]
+    pub fn new(value: f32) -> F32Le {
+        F32Le(value.to_bits().to_le())
+    }
+
+    #[inline]
+    pub fn get(self) -> f32 {
+        f32::from_bits(u32::from_le(self.0))
+    }
+}
+
+macro_rules! le_traits {
+    ($le:ty, $native:ty) => {
+        impl From<$native> for $le {
+            fn from(value: $native) -> $le {
+                <$le>::new(value)
+            }
+        }
+
+        impl From<$le> for $native {
+            fn from(value: $le) -> $native {
+                value.get()
+            }
+        }
+
+        // Offsets an index, as when concatenating timetables.
+        impl AddAssign<usize> for $le {
+            fn add_assign(&mut self, offset: usize) {
+                *self = <$le>::index(self.usize() + offset);
+            }
+        }
+
+        impl PartialOrd for $le {
+            fn partial_cmp(&self, other: &$le) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $le {
+            fn cmp(&self, other: &$le) -> Ordering {
+                self.get().cmp(&other.get())
+            }
+        }
+
+        impl fmt::Debug for $le {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.get().fmt(f)
+            }
+        }
+    };
+}
+
+le_traits!(U32Le, u32);
+le_traits!(U64Le, u64);
+
+impl PartialOrd for F32Le {
+    fn partial_cmp(&self, other: &F32Le) -> Option<Ordering> {
+        self.get().partial_cmp(&other.get())
+    }
+}
+
+impl fmt::Debug for F32Le {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
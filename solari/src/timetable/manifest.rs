@@ -0,0 +1,276 @@
+use std::{
+    fs::{self, File},
+    io,
+    mem::{align_of, size_of},
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use solari_bundle::Source;
+
+use super::{
+    calendar::StorageMode, le::U64Le, Route, RouteStop, Stop, StopRoute, Transfer, Trip,
+    TripStopTime,
+};
+
+/// Bumped whenever the layout of the raw timetable files changes.
+pub const FORMAT_VERSION: u32 = 2;
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error("{0:?} has no {MANIFEST_FILE}, it was built by an older version of Solari or the build didn't finish")]
+    Missing(PathBuf),
+    #[error("{path:?} has timetable format version {found}, but this version of Solari reads version {expected}")]
+    FormatVersion {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
+    #[error(
+        "{path:?} stores {name} as {found:?}, but this version of Solari expects {expected:?}"
+    )]
+    Layout {
+        path: PathBuf,
+        name: String,
+        found: StructLayout,
+        expected: StructLayout,
+    },
+    #[error("{file:?} is {found} bytes long, but its manifest describes {expected} bytes")]
+    FileSize {
+        file: PathBuf,
+        found: u64,
+        expected: u64,
+    },
+    #[error("{file:?} doesn't match the content hash in its manifest")]
+    ContentHash { file: PathBuf },
+    #[error("Failed to read {0:?}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Malformed manifest {0:?}: {1}")]
+    Malformed(PathBuf, serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StructLayout {
+    pub size: usize,
+    pub align: usize,
+}
+
+/// The options a timetable was built with.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildParams {
+    pub start_date: Option<NaiveDate>,
+    pub num_days: Option<u16>,
+    pub storage_mode: StorageMode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedManifest {
+    pub id: String,
+    // BLAKE3 hash of the GTFS zip the feed was built from.
+    pub source_hash: Option<String>,
+}
+
+/// One of the raw files of a timetable, which are arrays of `element`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DataFile {
+    pub name: String,
+    pub element: String,
+    pub count: u64,
+    // BLAKE3 hash of the file's contents.
+    pub hash: String,
+}
+
+/// Describes the raw files of a timetable, so they aren't read as the wrong structs by a different
+/// version of Solari. The files are little-endian whichever host built them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub solari_version: String,
+    pub layouts: Vec<(String, StructLayout)>,
+    pub files: Vec<DataFile>,
+    pub build: BuildParams,
+    pub feeds: Vec<FeedManifest>,
+}
+
+fn layout<T>() -> StructLayout {
+    StructLayout {
+        size: size_of::<T>(),
+        align: align_of::<T>(),
+    }
+}
+
+// The element type of each raw file.
+fn file_elements() -> [(&'static str, &'static str, StructLayout); 8] {
+    [
+        ("routes", "Route", layout::<Route>()),
+        ("route_stops", "RouteStop", layout::<RouteStop>()),
+        ("route_trips", "Trip", layout::<Trip>()),
+        ("stops", "Stop", layout::<Stop>()),
+        ("stop_routes", "StopRoute", layout::<StopRoute>()),
+        ("trip_stop_times", "TripStopTime", layout::<TripStopTime>()),
+        ("transfer_index", "u64", layout::<U64Le>()),
+        ("transfers", "Transfer", layout::<Transfer>()),
+    ]
+}
+
+pub fn hash_file(path: &Path) -> Result<String, ManifestError> {
+    let mut file = File::open(path).map_err(|err| ManifestError::Io(path.to_path_buf(), err))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher).map_err(|err| ManifestError::Io(path.to_path_buf(), err))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// Timetables are either a directory of files or a bundle with a section for each of them.
+fn open_source(base_path: &Path) -> Result<Source, ManifestError> {
+    Source::open(base_path).map_err(|err| ManifestError::Io(base_path.to_path_buf(), err))
+}
+
+impl Manifest {
+    /// Describe the raw files currently in `base_path`.
+    pub fn describe(
+        base_path: &Path,
+        build: BuildParams,
+        feeds: Vec<FeedManifest>,
+    ) -> Result<Manifest, ManifestError> {
+        let mut layouts = vec![];
+        let mut files = vec![];
+        for (name, element, layout) in file_elements() {
+            let path = base_path.join(name);
+            let len = fs::metadata(&path)
+                .map_err(|err| ManifestError::Io(path.clone(), err))?
+                .len();
+            files.push(DataFile {
+                name: name.to_string(),
+                element: element.to_string(),
+                count: len / layout.size as u64,
+                hash: hash_file(&path)?,
+            });
+            if !layouts.iter().any(|(existing, _)| existing == element) {
+                layouts.push((element.to_string(), layout));
+            }
+        }
+        Ok(Manifest {
+            format_version: FORMAT_VERSION,
+            solari_version: env!("CARGO_PKG_VERSION").to_string(),
+            layouts,
+            files,
+            build,
+            feeds,
+        })
+    }
+
+    pub fn read(base_path: &Path) -> Result<Manifest, ManifestError> {
+        let path = base_path.join(MANIFEST_FILE);
+        let bytes = match open_source(base_path)?.read(MANIFEST_FILE) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(ManifestError::Missing(base_path.to_path_buf()))
+            }
+            Err(err) => return Err(ManifestError::Io(path, err)),
+        };
+        serde_json::from_slice(&bytes).map_err(|err| ManifestError::Malformed(path, err))
+    }
+
+    pub fn write(&self, base_path: &Path) -> Result<(), anyhow::Error> {
+        fs::write(
+            base_path.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Check that the raw files in `base_path` can be read as this version of Solari's structs.
+    /// This only looks at file sizes; `verify_hashes` also checks their contents.
+    pub fn validate(&self, base_path: &Path) -> Result<(), ManifestError> {
+        let source = open_source(base_path)?;
+        if self.format_version != FORMAT_VERSION {
+            return Err(ManifestError::FormatVersion {
+                path: base_path.to_path_buf(),
+                found: self.format_version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        for (name, element, expected) in file_elements() {
+            let found = self
+                .layouts
+                .iter()
+                .find(|(layout_name, _)| layout_name == element)
+                .map(|(_, layout)| layout.clone());
+            if found.as_ref() != Some(&expected) {
+                return Err(ManifestError::Layout {
+                    path: base_path.to_path_buf(),
+                    name: element.to_string(),
+                    found: found.unwrap_or(StructLayout { size: 0, align: 0 }),
+                    expected,
+                });
+            }
+            let path = base_path.join(name);
+            let found = source
+                .len(name)
+                .map_err(|err| ManifestError::Io(path.clone(), err))?;
+            let expected = self
+                .files
+                .iter()
+                .find(|file| file.name == name)
+                .map(|file| file.count * expected.size as u64)
+                .unwrap_or_default();
+            if found != expected {
+                return Err(ManifestError::FileSize {
+                    file: path,
+                    found,
+                    expected,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the raw files in `base_path` against their content hashes. This reads every file in
+    /// full, so unlike `validate` it isn't done on every open.
+    pub fn verify_hashes(&self, base_path: &Path) -> Result<(), ManifestError> {
+        let source = open_source(base_path)?;
+        for file in &self.files {
+            let path = base_path.join(&file.name);
+            let mut hasher = blake3::Hasher::new();
+            source
+                .reader(&file.name)
+                .and_then(|mut reader| io::copy(&mut reader, &mut hasher))
+                .map_err(|err| ManifestError::Io(path.clone(), err))?;
+            if hasher.finalize().to_hex().to_string() != file.hash {
+                return Err(ManifestError::ContentHash { file: path });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_timetables_from_other_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_path = dir.path();
+        for (name, _, _) in file_elements() {
+            fs::write(base_path.join(name), []).unwrap();
+        }
+        let mut manifest = Manifest::describe(&base_path, BuildParams::default(), vec![]).unwrap();
+        manifest.validate(&base_path).unwrap();
+        manifest.verify_hashes(&base_path).unwrap();
+
+        fs::write(base_path.join("stops"), [0u8; 3]).unwrap();
+        assert!(matches!(
+            manifest.validate(&base_path),
+            Err(ManifestError::FileSize { .. })
+        ));
+
+        manifest.format_version += 1;
+        assert!(matches!(
+            manifest.validate(&base_path),
+            Err(ManifestError::FormatVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,148 @@
+use rstar::RTree;
+use solari_geomath::lat_lng_to_cartesian;
+use strsim::jaro_winkler;
+
+use crate::spatial::IndexedStop;
+
+// Stops further apart than this are never treated as the same place.
+const MAX_MERGE_DISTANCE_METERS: f64 = 150.0;
+// Jaro-Winkler similarity of normalized names, to tolerate small spelling differences.
+const MIN_NAME_SIMILARITY: f64 = 0.9;
+const ABBREVIATIONS: [(&str, &str); 8] = [
+    ("st", "street"),
+    ("stn", "station"),
+    ("sta", "station"),
+    ("ave", "avenue"),
+    ("av", "avenue"),
+    ("rd", "road"),
+    ("blvd", "boulevard"),
+    ("ctr", "center"),
+];
+
+/// The parts of a stop used to decide whether two feeds describe the same place.
+pub(crate) struct MergeCandidate {
+    pub(crate) feed: usize,
+    pub(crate) gtfs_id: String,
+    pub(crate) code: Option<String>,
+    pub(crate) name: Option<String>,
+    pub(crate) lat: f64,
+    pub(crate) lng: f64,
+}
+
+/// Pairs of stops from different feeds that describe the same physical stop or station. Stops
+/// are paired when they're close together and share a stop ID or code, or have similar names.
+/// Pairing isn't transitive, so two stops of one feed are never paired through a third.
+pub(crate) fn merge_pairs(stops: &[MergeCandidate]) -> Vec<(usize, usize)> {
+    let coords: Vec<[f64; 3]> = stops
+        .iter()
+        .map(|stop| lat_lng_to_cartesian(stop.lat, stop.lng))
+        .collect();
+    let rtree = RTree::bulk_load(
+        coords
+            .iter()
+            .enumerate()
+            .map(|(id, coords)| IndexedStop {
+                coords: *coords,
+                id,
+            })
+            .collect(),
+    );
+    let names: Vec<Option<String>> = stops
+        .iter()
+        .map(|stop| stop.name.as_deref().map(normalize_name))
+        .collect();
+
+    let mut pairs = vec![];
+    for (id, stop) in stops.iter().enumerate() {
+        for (other, dist_sq) in rtree.nearest_neighbor_iter_with_distance_2(&coords[id]) {
+            if dist_sq.sqrt() > MAX_MERGE_DISTANCE_METERS {
+                break;
+            }
+            let other_stop = &stops[other.id];
+            if other.id <= id || other_stop.feed == stop.feed {
+                continue;
+            }
+            let shared_id = stop.gtfs_id == other_stop.gtfs_id
+                || (stop.code.is_some() && stop.code == other_stop.code);
+            let similar_name = match (&names[id], &names[other.id]) {
+                (Some(name), Some(other_name)) => similar_names(name, other_name),
+                _ => false,
+            };
+            if shared_id || similar_name {
+                pairs.push((id, other.id));
+            }
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let word = word.to_lowercase();
+            ABBREVIATIONS
+                .iter()
+                .find(|(abbreviation, _)| *abbreviation == word)
+                .map(|(_, expanded)| expanded.to_string())
+                .unwrap_or(word)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// One feed often names a stop after the station it's in, plus a bay or platform.
+fn similar_names(name: &str, other_name: &str) -> bool {
+    let (shorter, longer) = if name.len() <= other_name.len() {
+        (name, other_name)
+    } else {
+        (other_name, name)
+    };
+    let prefixed = !shorter.is_empty()
+        && longer.starts_with(shorter)
+        && longer[shorter.len()..].starts_with(' ');
+    prefixed || jaro_winkler(name, other_name) >= MIN_NAME_SIMILARITY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(feed: usize, gtfs_id: &str, name: &str, lat: f64, lng: f64) -> MergeCandidate {
+        MergeCandidate {
+            feed,
+            gtfs_id: gtfs_id.to_string(),
+            code: None,
+            name: Some(name.to_string()),
+            lat,
+            lng,
+        }
+    }
+
+    #[test]
+    fn merges_nearby_stops_with_similar_names_across_feeds() {
+        let stops = vec![
+            stop(0, "rail-1", "King Street Station", 47.5983, -122.3300),
+            stop(1, "bus-7", "King St. Station Bay 2", 47.5985, -122.3302),
+            // Different name across the street.
+            stop(1, "bus-8", "Jackson & 4th", 47.5986, -122.3298),
+            // Duplicates within a feed are left alone.
+            stop(1, "bus-10", "Jackson & 4th", 47.5986, -122.3298),
+            // Same name, but across town.
+            stop(1, "bus-9", "King Street Station", 47.6500, -122.3500),
+        ];
+        assert_eq!(merge_pairs(&stops), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn doesnt_pair_stops_of_one_feed_through_another() {
+        let stops = vec![
+            stop(0, "rail-1", "King Street Station", 47.5983, -122.3300),
+            // Both sides of the street, each next to the station.
+            stop(1, "bus-7", "King Street Station Bay 1", 47.5984, -122.3303),
+            stop(1, "bus-8", "King Street Station Bay 2", 47.5982, -122.3297),
+        ];
+        assert_eq!(merge_pairs(&stops), vec![(0, 1), (0, 2)]);
+    }
+}
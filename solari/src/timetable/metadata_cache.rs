@@ -0,0 +1,154 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+const SHARDS: usize = 16;
+
+struct Slot<V> {
+    key: u64,
+    value: V,
+    referenced: bool,
+}
+
+// Evicts with the CLOCK algorithm, which approximates LRU without reordering anything on a hit.
+struct Shard<V> {
+    slots: Vec<Slot<V>>,
+    index: HashMap<u64, usize>,
+    hand: usize,
+    capacity: usize,
+}
+
+impl<V: Clone> Shard<V> {
+    fn get(&mut self, key: u64) -> Option<V> {
+        let slot = &mut self.slots[*self.index.get(&key)?];
+        slot.referenced = true;
+        Some(slot.value.clone())
+    }
+
+    fn insert(&mut self, key: u64, value: V) {
+        if let Some(&idx) = self.index.get(&key) {
+            self.slots[idx].value = value;
+            return;
+        }
+        if self.slots.len() < self.capacity {
+            self.index.insert(key, self.slots.len());
+            self.slots.push(Slot {
+                key,
+                value,
+                referenced: false,
+            });
+            return;
+        }
+        loop {
+            let slot = &mut self.slots[self.hand];
+            if slot.referenced {
+                slot.referenced = false;
+                self.hand = (self.hand + 1) % self.capacity;
+                continue;
+            }
+            self.index.remove(&slot.key);
+            self.index.insert(key, self.hand);
+            *slot = Slot {
+                key,
+                value,
+                referenced: false,
+            };
+            self.hand = (self.hand + 1) % self.capacity;
+            return;
+        }
+    }
+}
+
+/// Decoded metadata records keyed by stop, trip or route index, so lookups that hit don't need a
+/// database read transaction. Sharded by key to keep concurrent requests from contending.
+pub struct MetadataCache<V> {
+    shards: Vec<Mutex<Shard<V>>>,
+}
+
+impl<V: Clone> MetadataCache<V> {
+    pub fn new(capacity: usize) -> MetadataCache<V> {
+        let shard_capacity = capacity.div_ceil(SHARDS).max(1);
+        MetadataCache {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        slots: Vec::new(),
+                        index: HashMap::new(),
+                        hand: 0,
+                        capacity: shard_capacity,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn shard(&self, key: u64) -> &Mutex<Shard<V>> {
+        &self.shards[key as usize % SHARDS]
+    }
+
+    pub fn get(&self, key: u64) -> Option<V> {
+        self.shard(key).lock().unwrap().get(key)
+    }
+
+    pub fn insert(&self, key: u64, value: V) {
+        self.shard(key).lock().unwrap().insert(key, value);
+    }
+
+    /// The cached value for `key`, reading and caching it with `read` if there isn't one.
+    pub fn get_or_insert_with(&self, key: u64, read: impl FnOnce() -> V) -> V {
+        if let Some(value) = self.get(key) {
+            return value;
+        }
+        let value = read();
+        self.insert(key, value.clone());
+        value
+    }
+}
+
+impl<V: Clone> MetadataCache<(Arc<str>, V)> {
+    /// Like `get_or_insert_with`, for records keyed by a string such as a stable ID. Entries are
+    /// keyed by the string's hash and hold the string itself, so a colliding key is just a miss.
+    pub fn get_or_insert_with_str(&self, key: &str, read: impl FnOnce() -> V) -> V {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        match self.get(hash) {
+            Some((cached_key, value)) if *cached_key == *key => value,
+            _ => {
+                let value = read();
+                self.insert(hash, (key.into(), value.clone()));
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_unreferenced_entries_first() {
+        // Two slots per shard, and keys that all land in shard 0.
+        let cache = MetadataCache::new(2 * SHARDS);
+        cache.insert(0, "zero");
+        cache.insert(SHARDS as u64, "one");
+        assert_eq!(cache.get(0), Some("zero"));
+
+        cache.insert(2 * SHARDS as u64, "two");
+        assert_eq!(cache.get(SHARDS as u64), None);
+        assert_eq!(cache.get(2 * SHARDS as u64), Some("two"));
+        assert_eq!(cache.get_or_insert_with(0, || "reread"), "zero");
+    }
+
+    #[test]
+    fn string_keys_are_read_once() {
+        let cache = MetadataCache::new(SHARDS);
+        assert_eq!(cache.get_or_insert_with_str("feed:a", || Some(1)), Some(1));
+        assert_eq!(cache.get_or_insert_with_str("feed:b", || None), None);
+        assert_eq!(cache.get_or_insert_with_str("feed:a", || Some(2)), Some(1));
+        assert_eq!(cache.get_or_insert_with_str("feed:b", || Some(3)), None);
+    }
+}
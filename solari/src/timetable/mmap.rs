@@ -1,11 +1,15 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
     marker::PhantomData,
     mem::size_of,
-    path::PathBuf,
+    path::{Path, PathBuf},
     pin::Pin,
     slice,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use anyhow::{Error, Ok};
@@ -13,25 +17,126 @@ use bytemuck::{cast_slice_mut, checked::cast_slice};
 use geo::Coord;
 use memmap2::{Mmap, MmapMut, MmapOptions};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use redb::Database;
+use redb::{Database, ReadableTable, TableDefinition};
 use rstar::RTree;
 use s2::latlng::LatLng;
-use solari_geomath::lat_lng_to_cartesian;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use solari_bundle::Source;
+use solari_geomath::{lat_lng_to_cartesian, EARTH_RADIUS_APPROX};
 use solari_spatial::{SphereIndex, SphereIndexMmap};
 use solari_transfers::{
     fast_paths::{FastGraph, FastGraphStatic},
-    {TransferGraph, TransferGraphSearcher},
+    {TransferGraph, TransferGraphSearcher, TRANSFER_METADATA_FILE},
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::spatial::{IndexedStop, WALK_SPEED_MM_PER_SECOND};
+use crate::{
+    fare::{FareData, StopFareInfo},
+    spatial::{IndexedStop, FAKE_WALK_SPEED_SECONDS_PER_METER, WALK_SPEED_MM_PER_SECOND},
+};
 
+use super::le::U64Le;
+use super::manifest::Manifest;
+use super::merge::{merge_pairs, MergeCandidate};
+use super::metadata_cache::MetadataCache;
+use super::station::{PlatformTransfer, StopStationInfo};
 use super::{
-    in_memory::InMemoryTimetableBuilder, Route, RouteStop, ShapeCoordinate, Stop, StopRoute,
-    Timetable, Transfer, Trip, TripMetadata, TripStopTime, ROUTE_SHAPE_TABLE, STOP_METADATA_TABLE,
-    TRIP_METADATA_TABLE,
+    calendar::ServiceCalendar, in_memory::InMemoryTimetableBuilder, Route, RouteStop,
+    ServiceWindow, ShapeCoordinate, Stop, StopRoute, Timetable, Transfer, Translations, Trip,
+    TripMetadata, TripStopTime, COMBINED_SERVICE_WINDOW_TABLE, FARE_DATA_TABLE, FEED_SEGMENT_TABLE,
+    GLOBAL_ROUTE_INDEX_TABLE, GLOBAL_STOP_INDEX_TABLE, GLOBAL_TRIP_INDEX_TABLE,
+    GTFS_TRIP_INDEX_TABLE, ROUTE_SHAPE_INDEX_TABLE, ROUTE_SHAPE_TABLE, ROUTE_TRANSLATION_TABLE,
+    SERVICE_CALENDAR_TABLE, SERVICE_WINDOW_TABLE, STOP_FARE_TABLE, STOP_GLOBAL_ID_TABLE,
+    STOP_METADATA_TABLE, STOP_STATION_TABLE, STOP_TRANSLATION_TABLE, TRIP_METADATA_TABLE,
+    TRIP_TRANSLATION_TABLE,
 };
 
+// A stop's feed directory and GTFS stop ID, which identify it across rebuilds.
+pub(crate) type StopKey = (String, String);
+
+// The range of stops a feed's timetable occupies in a concatenated timetable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FeedSegment {
+    name: String,
+    first_stop: usize,
+    stop_count: usize,
+}
+
+/// The transfers of a previously built timetable, which an incremental rebuild reuses for every
+/// stop whose surroundings didn't change.
+pub struct PreviousTransfers {
+    stops: HashMap<StopKey, PreviousStop>,
+}
+
+impl PreviousTransfers {
+    // The previous transfers of the stop `key`, now at index `stop_id`, as long as neither it nor
+    // any of its transfer `candidates` has been added, moved or removed since.
+    fn reuse(
+        &self,
+        stop_id: usize,
+        key: &StopKey,
+        s2cell: u64,
+        platform_transfers: &[(StopKey, u32)],
+        candidates: &[(StopKey, u64)],
+        indices: &HashMap<StopKey, usize>,
+    ) -> Option<Vec<Transfer>> {
+        let previous_stop = self.stops.get(key)?;
+        if previous_stop.s2cell != s2cell || previous_stop.platform_transfers != platform_transfers
+        {
+            return None;
+        }
+        let mut candidate_keys = vec![];
+        for (candidate, s2cell) in candidates {
+            if self.stops.get(candidate)?.s2cell != *s2cell {
+                return None;
+            }
+            candidate_keys.push(candidate.clone());
+        }
+        candidate_keys.sort();
+        if candidate_keys != previous_stop.candidates {
+            return None;
+        }
+        previous_stop
+            .transfers
+            .iter()
+            .map(|(to, time)| Some(Transfer::new(stop_id, *indices.get(to)?, *time)))
+            .collect()
+    }
+}
+
+// The stops within transfer range of `coords`, nearest first.
+fn transfer_candidate_ids(rtree: &RTree<IndexedStop>, coords: &[f64; 3]) -> Vec<usize> {
+    let mut transfer_candidates = vec![];
+    for (count, (to_stop, dist_sq)) in rtree
+        .nearest_neighbor_iter_with_distance_2(coords)
+        .enumerate()
+    {
+        let dist = dist_sq.sqrt();
+        if dist > 1000f64 || count > 20 {
+            break;
+        }
+        transfer_candidates.push(to_stop.id);
+    }
+    transfer_candidates
+}
+
+// Stable IDs of a timetable's stops, plus the indices of the routes and trips each stable ID
+// refers to. A GTFS route or trip can map to several of either.
+#[derive(Default)]
+struct GlobalIds {
+    stops: Vec<(u64, String)>,
+    routes: HashMap<String, Vec<u64>>,
+    trips: HashMap<String, Vec<u64>>,
+}
+
+struct PreviousStop {
+    s2cell: u64,
+    // Sorted.
+    candidates: Vec<StopKey>,
+    platform_transfers: Vec<(StopKey, u32)>,
+    transfers: Vec<(StopKey, u32)>,
+}
+
 #[allow(unused)]
 pub struct MmapTimetable<'a> {
     base_path: PathBuf,
@@ -51,15 +156,38 @@ pub struct MmapTimetable<'a> {
     stops_slice: &'a [Stop],
     stop_routes_slice: &'a [StopRoute],
     trip_stop_times_slice: &'a [TripStopTime],
-    transfer_index_slice: &'a [usize],
+    transfer_index_slice: &'a [U64Le],
     transfers_slice: &'a [Transfer],
     rtree: RTree<IndexedStop>,
+    service_calendar: Option<ServiceCalendar>,
+    manifest: Manifest,
 
     metadata_db: redb::Database,
+    stop_metadata_cache: MetadataCache<gtfs_structures::Stop>,
+    trip_metadata_cache: MetadataCache<TripMetadata>,
+    route_shape_cache: MetadataCache<Option<Arc<Vec<ShapeCoordinate>>>>,
+    route_shape_index_cache: MetadataCache<Option<Arc<Vec<u32>>>>,
+    // Optional per-stop records, cached whether or not the stop has one.
+    stop_global_id_cache: MetadataCache<Option<String>>,
+    stop_translation_cache: MetadataCache<Option<Translations>>,
+    stop_station_cache: MetadataCache<Option<StopStationInfo>>,
+    stop_fare_cache: MetadataCache<Option<StopFareInfo>>,
+    trip_translation_cache: MetadataCache<Option<Translations>>,
+    route_translation_cache: MetadataCache<Option<Translations>>,
+    global_stop_cache: MetadataCache<(Arc<str>, Option<usize>)>,
+    fare_data_cache: MetadataCache<(Arc<str>, Option<FareData>)>,
 
     phantom: &'a PhantomData<()>,
 }
 
+// How many decoded records of each kind to keep in memory. Shapes are the largest by far.
+const STOP_METADATA_CACHE_SIZE: usize = 1 << 16;
+const TRIP_METADATA_CACHE_SIZE: usize = 1 << 16;
+const ROUTE_SHAPE_CACHE_SIZE: usize = 1 << 10;
+const ROUTE_METADATA_CACHE_SIZE: usize = 1 << 14;
+// Fare data is per feed.
+const FARE_DATA_CACHE_SIZE: usize = 1 << 6;
+
 impl<'a> Timetable<'a> for MmapTimetable<'a> {
     #[inline]
     fn route(&'a self, route_id: usize) -> &'a Route {
@@ -117,7 +245,7 @@ impl<'a> Timetable<'a> for MmapTimetable<'a> {
     }
 
     #[inline]
-    fn transfer_index(&'a self) -> &'a [usize] {
+    fn transfer_index(&'a self) -> &'a [U64Le] {
         self.transfer_index_slice
     }
 
@@ -135,48 +263,216 @@ impl<'a> Timetable<'a> for MmapTimetable<'a> {
     }
 
     fn stop_metadata(&'a self, stop: &Stop) -> gtfs_structures::Stop {
-        let table = self
-            .metadata_db
-            .begin_read()
-            .expect("Read failed")
-            .open_table(STOP_METADATA_TABLE)
-            .expect("Failed to open table");
+        self.stop_metadata_batch(&[stop]).pop().unwrap()
+    }
 
-        let bytes = table
-            .get(stop.id() as u64)
-            .expect("DB error")
-            .expect("Missing metadata for stop");
-        rmp_serde::from_slice(bytes.value()).expect("Deserialization failed")
+    fn stop_metadata_batch(&'a self, stops: &[&Stop]) -> Vec<gtfs_structures::Stop> {
+        let keys: Vec<u64> = stops.iter().map(|stop| stop.id() as u64).collect();
+        self.read_metadata(
+            &self.stop_metadata_cache,
+            STOP_METADATA_TABLE,
+            &keys,
+            "stop",
+        )
     }
 
     fn trip_metadata(&'a self, trip: &Trip) -> TripMetadata {
-        let table = self
+        self.trip_metadata_batch(&[*trip]).pop().unwrap()
+    }
+
+    fn trip_metadata_batch(&'a self, trips: &[Trip]) -> Vec<TripMetadata> {
+        let keys: Vec<u64> = trips.iter().map(|trip| trip.id() as u64).collect();
+        self.read_metadata(
+            &self.trip_metadata_cache,
+            TRIP_METADATA_TABLE,
+            &keys,
+            "trip",
+        )
+    }
+
+    fn trips_by_gtfs_id(&'a self, gtfs_trip_id: &str) -> Vec<&'a Trip> {
+        let table = if let Some(table) = self
             .metadata_db
             .begin_read()
             .expect("Read failed")
-            .open_table(TRIP_METADATA_TABLE)
-            .expect("Failed to open table");
+            .open_table(GTFS_TRIP_INDEX_TABLE)
+            .ok()
+        {
+            table
+        } else {
+            // Timetables built before realtime support don't have this table.
+            return vec![];
+        };
+
+        if let Some(bytes) = table.get(gtfs_trip_id).expect("DB error") {
+            let trip_indices: Vec<u64> =
+                rmp_serde::from_slice(bytes.value()).expect("Deserialization failed");
+            trip_indices
+                .iter()
+                .map(|trip_index| &self.route_trips_slice[*trip_index as usize])
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+
+    fn stop_global_id(&'a self, stop: &Stop) -> Option<String> {
+        self.stop_global_id_cache
+            .get_or_insert_with(stop.id() as u64, || {
+                // Timetables built before stable IDs don't have these tables.
+                let table = self
+                    .metadata_db
+                    .begin_read()
+                    .expect("Read failed")
+                    .open_table(STOP_GLOBAL_ID_TABLE)
+                    .ok()?;
+
+                let global_id = table.get(stop.id() as u64).expect("DB error")?;
+                Some(global_id.value().to_string())
+            })
+    }
+
+    fn stop_by_global_id(&'a self, global_stop_id: &str) -> Option<&'a Stop> {
+        let stop_index = self
+            .global_stop_cache
+            .get_or_insert_with_str(global_stop_id, || {
+                let table = self
+                    .metadata_db
+                    .begin_read()
+                    .expect("Read failed")
+                    .open_table(GLOBAL_STOP_INDEX_TABLE)
+                    .ok()?;
+
+                let stop_index = table.get(global_stop_id).expect("DB error")?;
+                Some(stop_index.value() as usize)
+            })?;
+        self.stops_slice.get(stop_index)
+    }
+
+    fn routes_by_global_id(&'a self, global_route_id: &str) -> Vec<&'a Route> {
+        self.global_indices(GLOBAL_ROUTE_INDEX_TABLE, global_route_id)
+            .iter()
+            .map(|route_index| &self.routes_slice[*route_index as usize])
+            .collect()
+    }
+
+    fn trips_by_global_id(&'a self, global_trip_id: &str) -> Vec<&'a Trip> {
+        self.global_indices(GLOBAL_TRIP_INDEX_TABLE, global_trip_id)
+            .iter()
+            .map(|trip_index| &self.route_trips_slice[*trip_index as usize])
+            .collect()
+    }
+
+    fn stop_fare_info(&'a self, stop: &Stop) -> Option<StopFareInfo> {
+        // Timetables built before fare support don't have this table.
+        self.optional_record(&self.stop_fare_cache, STOP_FARE_TABLE, stop.id() as u64)
+    }
+
+    fn fare_data(&'a self, feed_id: &str) -> Option<FareData> {
+        self.fare_data_cache.get_or_insert_with_str(feed_id, || {
+            let table = self
+                .metadata_db
+                .begin_read()
+                .expect("Read failed")
+                .open_table(FARE_DATA_TABLE)
+                .ok()?;
+
+            let bytes = table.get(feed_id).expect("DB error")?;
+            Some(rmp_serde::from_slice(bytes.value()).expect("Deserialization failed"))
+        })
+    }
+
+    fn stop_station_info(&'a self, stop: &Stop) -> Option<StopStationInfo> {
+        // Timetables built before station support don't have this table.
+        self.optional_record(
+            &self.stop_station_cache,
+            STOP_STATION_TABLE,
+            stop.id() as u64,
+        )
+    }
+
+    // Timetables built before translation support don't have these tables.
+    fn stop_translations(&'a self, stop: &Stop) -> Option<Translations> {
+        self.optional_record(
+            &self.stop_translation_cache,
+            STOP_TRANSLATION_TABLE,
+            stop.id() as u64,
+        )
+    }
+
+    fn trip_translations(&'a self, trip: &Trip) -> Option<Translations> {
+        self.optional_record(
+            &self.trip_translation_cache,
+            TRIP_TRANSLATION_TABLE,
+            trip.id() as u64,
+        )
+    }
 
-        let bytes = table
-            .get(trip.trip_index as u64)
-            .expect("DB error")
-            .expect("Missing metadata for trip");
-        rmp_serde::from_slice(bytes.value()).expect("Deserialization failed")
+    fn route_translations(&'a self, route: &Route) -> Option<Translations> {
+        self.optional_record(
+            &self.route_translation_cache,
+            ROUTE_TRANSLATION_TABLE,
+            route.id() as u64,
+        )
     }
 
-    fn route_shape(&'a self, route: &Route) -> Option<Vec<ShapeCoordinate>> {
+    fn service_window(&'a self) -> Option<ServiceWindow> {
+        // Timetables built before service windows were recorded don't have this table.
         let table = self
             .metadata_db
             .begin_read()
             .expect("Read failed")
-            .open_table(ROUTE_SHAPE_TABLE)
-            .expect("Failed to open table");
+            .open_table(COMBINED_SERVICE_WINDOW_TABLE)
+            .ok()?;
 
-        if let Some(bytes) = table.get(route.route_index as u64).expect("DB error") {
-            rmp_serde::from_slice(bytes.value()).expect("Deserialization failed")
-        } else {
-            None
-        }
+        let bytes = table.get(()).expect("DB error")?;
+        Some(rmp_serde::from_slice(bytes.value()).expect("Deserialization failed"))
+    }
+
+    fn service_calendar(&'a self) -> Option<&'a ServiceCalendar> {
+        self.service_calendar.as_ref()
+    }
+
+    fn feed_service_windows(&'a self) -> HashMap<String, ServiceWindow> {
+        self.all_service_windows()
+            .expect("Failed to read service windows")
+    }
+
+    fn route_shape(&'a self, route: &Route) -> Option<Arc<Vec<ShapeCoordinate>>> {
+        self.route_shape_cache
+            .get_or_insert_with(route.id() as u64, || {
+                let table = self
+                    .metadata_db
+                    .begin_read()
+                    .expect("Read failed")
+                    .open_table(ROUTE_SHAPE_TABLE)
+                    .expect("Failed to open table");
+
+                if let Some(bytes) = table.get(route.id() as u64).expect("DB error") {
+                    rmp_serde::from_slice::<Option<Vec<ShapeCoordinate>>>(bytes.value())
+                        .expect("Deserialization failed")
+                        .map(Arc::new)
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn route_shape_indices(&'a self, route: &Route) -> Option<Arc<Vec<u32>>> {
+        self.route_shape_index_cache
+            .get_or_insert_with(route.id() as u64, || {
+                // Timetables built before shape indices were precomputed don't have this table.
+                let table = self
+                    .metadata_db
+                    .begin_read()
+                    .expect("Read failed")
+                    .open_table(ROUTE_SHAPE_INDEX_TABLE)
+                    .ok()?;
+                let bytes = table.get(route.id() as u64).expect("DB error")?;
+                Some(Arc::new(
+                    rmp_serde::from_slice(bytes.value()).expect("Deserialization failed"),
+                ))
+            })
     }
 }
 
@@ -192,6 +488,7 @@ impl<'a> MmapTimetable<'a> {
         backing_transfer_index: Pin<Mmap>,
         backing_transfers: Pin<Mmap>,
         metadata_db: Database,
+        manifest: Manifest,
     ) -> Result<MmapTimetable<'a>, anyhow::Error> {
         let routes = unsafe {
             let s = cast_slice::<u8, Route>(&backing_routes);
@@ -218,7 +515,7 @@ impl<'a> MmapTimetable<'a> {
             slice::from_raw_parts(s.as_ptr(), s.len())
         };
         let transfer_index = unsafe {
-            let s = cast_slice::<u8, usize>(&backing_transfer_index);
+            let s = cast_slice::<u8, U64Le>(&backing_transfer_index);
             slice::from_raw_parts(s.as_ptr(), s.len())
         };
         let transfers = unsafe {
@@ -230,7 +527,7 @@ impl<'a> MmapTimetable<'a> {
                 stops
                     .iter()
                     .map(|stop| {
-                        let latlng: LatLng = s2::cellid::CellID(stop.s2cell).into();
+                        let latlng: LatLng = s2::cellid::CellID(stop.s2cell()).into();
                         let location_cartesian =
                             lat_lng_to_cartesian(latlng.lat.deg(), latlng.lng.deg());
                         IndexedStop {
@@ -242,6 +539,8 @@ impl<'a> MmapTimetable<'a> {
             )
         };
 
+        let service_calendar = Self::read_service_calendar(&metadata_db)?;
+
         let table = MmapTimetable {
             base_path,
             backing_routes,
@@ -255,6 +554,8 @@ impl<'a> MmapTimetable<'a> {
             phantom: &PhantomData,
 
             rtree,
+            service_calendar,
+            manifest,
             routes_slice: routes,
             route_stops_slice: route_stops,
             route_trips_slice: route_trips,
@@ -265,46 +566,41 @@ impl<'a> MmapTimetable<'a> {
             transfers_slice: transfers,
 
             metadata_db,
+            stop_metadata_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            trip_metadata_cache: MetadataCache::new(TRIP_METADATA_CACHE_SIZE),
+            route_shape_cache: MetadataCache::new(ROUTE_SHAPE_CACHE_SIZE),
+            route_shape_index_cache: MetadataCache::new(ROUTE_SHAPE_CACHE_SIZE),
+            stop_global_id_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            stop_translation_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            stop_station_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            stop_fare_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            trip_translation_cache: MetadataCache::new(TRIP_METADATA_CACHE_SIZE),
+            route_translation_cache: MetadataCache::new(ROUTE_METADATA_CACHE_SIZE),
+            global_stop_cache: MetadataCache::new(STOP_METADATA_CACHE_SIZE),
+            fare_data_cache: MetadataCache::new(FARE_DATA_CACHE_SIZE),
         };
         Ok(table)
     }
 
     pub fn open(base_path: &PathBuf) -> Result<MmapTimetable<'a>, anyhow::Error> {
         info!("Opening a memory-mapped timetable.");
-        debug!("Opening files");
-        debug!("Opening routes.");
-        let routes = File::open(base_path.join("routes"))?;
-        debug!("Opening route stops.");
-        let route_stops = File::open(base_path.join("route_stops"))?;
-        debug!("Opening route trips.");
-        let route_trips = File::open(base_path.join("route_trips"))?;
-        debug!("Opening stops.");
-        let stops = File::open(base_path.join("stops"))?;
-        debug!("Opening stop routes.");
-        let stop_routes = File::open(base_path.join("stop_routes"))?;
-        debug!("Opening stop times.");
-        let trip_stop_times = File::open(base_path.join("trip_stop_times"))?;
-        debug!("Opening transfer index.");
-        let transfer_index = File::open(base_path.join("transfer_index"))?;
-        debug!("Opening transfers.");
-        let transfers = File::open(base_path.join("transfers"))?;
+        let manifest = Manifest::read(base_path)?;
+        manifest.validate(base_path)?;
+        // `base_path` is either a timetable directory or a bundle with a section for each file.
+        let source = Source::open(base_path)?;
 
         debug!("Opening metadata database");
-        let metadata_db = Database::open(base_path.join("metadata.db"))?;
-
-        let page_bits = Some(21);
+        let metadata_db = source.database("metadata.db")?;
 
         debug!("mmapping");
-        let backing_routes = unsafe { MmapOptions::new().huge(page_bits).map(&routes)? };
-        let backing_route_stops = unsafe { MmapOptions::new().huge(page_bits).map(&route_stops)? };
-        let backing_route_trips = unsafe { MmapOptions::new().huge(page_bits).map(&route_trips)? };
-        let backing_stops = unsafe { MmapOptions::new().huge(page_bits).map(&stops)? };
-        let backing_stop_routes = unsafe { MmapOptions::new().huge(page_bits).map(&stop_routes)? };
-        let backing_trip_stop_times =
-            unsafe { MmapOptions::new().huge(page_bits).map(&trip_stop_times)? };
-        let backing_transfer_index =
-            unsafe { MmapOptions::new().huge(page_bits).map(&transfer_index)? };
-        let backing_transfers = unsafe { MmapOptions::new().huge(page_bits).map(&transfers)? };
+        let backing_routes = source.map("routes")?;
+        let backing_route_stops = source.map("route_stops")?;
+        let backing_route_trips = source.map("route_trips")?;
+        let backing_stops = source.map("stops")?;
+        let backing_stop_routes = source.map("stop_routes")?;
+        let backing_trip_stop_times = source.map("trip_stop_times")?;
+        let backing_transfer_index = source.map("transfer_index")?;
+        let backing_transfers = source.map("transfers")?;
 
         MmapTimetable::assemble(
             base_path.clone(),
@@ -317,15 +613,83 @@ impl<'a> MmapTimetable<'a> {
             Pin::new(backing_transfer_index),
             Pin::new(backing_transfers),
             metadata_db,
+            manifest,
         )
     }
 
+    // Metadata records for `keys`, in order. Records that aren't cached are all read in a single
+    // transaction.
+    fn read_metadata<V: Clone + DeserializeOwned>(
+        &self,
+        cache: &MetadataCache<V>,
+        table: TableDefinition<u64, &[u8]>,
+        keys: &[u64],
+        kind: &str,
+    ) -> Vec<V> {
+        let mut values: Vec<Option<V>> = keys.iter().map(|key| cache.get(*key)).collect();
+        if values.iter().all(Option::is_some) {
+            return values.into_iter().flatten().collect();
+        }
+        let table = self
+            .metadata_db
+            .begin_read()
+            .expect("Read failed")
+            .open_table(table)
+            .expect("Failed to open table");
+        for (key, value) in keys.iter().zip(values.iter_mut()) {
+            if value.is_some() {
+                continue;
+            }
+            let bytes = table
+                .get(*key)
+                .expect("DB error")
+                .unwrap_or_else(|| panic!("Missing metadata for {kind} {key}"));
+            let decoded: V = rmp_serde::from_slice(bytes.value()).expect("Deserialization failed");
+            cache.insert(*key, decoded.clone());
+            *value = Some(decoded);
+        }
+        values.into_iter().flatten().collect()
+    }
+
+    // A record that not every key has, from a table that not every timetable has. Absent records
+    // are cached too.
+    fn optional_record<V: Clone + DeserializeOwned>(
+        &self,
+        cache: &MetadataCache<Option<V>>,
+        table: TableDefinition<u64, &[u8]>,
+        key: u64,
+    ) -> Option<V> {
+        cache.get_or_insert_with(key, || {
+            let table = self
+                .metadata_db
+                .begin_read()
+                .expect("Read failed")
+                .open_table(table)
+                .ok()?;
+            let bytes = table.get(key).expect("DB error")?;
+            Some(rmp_serde::from_slice(bytes.value()).expect("Deserialization failed"))
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    pub(crate) fn metadata_db(&self) -> &Database {
+        &self.metadata_db
+    }
+
     pub fn from_in_memory(
         in_memory_timetable: &InMemoryTimetableBuilder,
         base_path: &PathBuf,
     ) -> Result<MmapTimetable<'a>, anyhow::Error> {
         fs::create_dir_all(base_path)?;
 
+        let builder = in_memory_timetable;
         let in_memory_timetable = &in_memory_timetable.timetable;
 
         {
@@ -416,22 +780,114 @@ impl<'a> MmapTimetable<'a> {
                     let mut table = write.open_table(TRIP_METADATA_TABLE)?;
                     for trip in in_memory_timetable.route_trips() {
                         let bytes = rmp_serde::to_vec(&in_memory_timetable.trip_metadata(trip))?;
-                        table.insert(trip.trip_index as u64, bytes.as_slice())?;
+                        table.insert(trip.id() as u64, bytes.as_slice())?;
+                    }
+                }
+                write.commit()?;
+            }
+            {
+                let mut gtfs_trip_index: HashMap<String, Vec<u64>> = HashMap::new();
+                for trip in in_memory_timetable.route_trips() {
+                    if let Some(trip_id) = in_memory_timetable.trip_metadata(trip).trip_id {
+                        gtfs_trip_index
+                            .entry(trip_id)
+                            .or_default()
+                            .push(trip.id() as u64);
+                    }
+                }
+                Self::write_gtfs_trip_index(&metadata_db, &gtfs_trip_index)?;
+            }
+            {
+                let mut global_ids = GlobalIds::default();
+                Self::collect_global_ids(&mut global_ids, in_memory_timetable, 0, 0, 0);
+                Self::write_global_ids(&metadata_db, &global_ids)?;
+            }
+            {
+                let write = metadata_db.begin_write()?;
+                {
+                    let mut table = write.open_table(STOP_FARE_TABLE)?;
+                    for stop in in_memory_timetable.stops() {
+                        if let Some(fare_info) = in_memory_timetable.stop_fare_info(stop) {
+                            let bytes = rmp_serde::to_vec(&fare_info)?;
+                            table.insert(stop.id() as u64, bytes.as_slice())?;
+                        }
+                    }
+                }
+                write.commit()?;
+            }
+            Self::write_fare_data(&metadata_db, in_memory_timetable.fare_data_map())?;
+            {
+                let write = metadata_db.begin_write()?;
+                {
+                    let mut table = write.open_table(STOP_STATION_TABLE)?;
+                    for stop in in_memory_timetable.stops() {
+                        if let Some(station_info) = in_memory_timetable.stop_station_info(stop) {
+                            let bytes = rmp_serde::to_vec(&station_info)?;
+                            table.insert(stop.id() as u64, bytes.as_slice())?;
+                        }
                     }
                 }
                 write.commit()?;
             }
+            Self::write_service_windows(&metadata_db, &in_memory_timetable.feed_service_windows())?;
+            if let Some(calendar) = in_memory_timetable.service_calendar() {
+                Self::write_service_calendar(&metadata_db, calendar)?;
+            }
+            Self::write_translations(
+                &metadata_db,
+                STOP_TRANSLATION_TABLE,
+                in_memory_timetable.stops().iter().filter_map(|stop| {
+                    Some((
+                        stop.id() as u64,
+                        in_memory_timetable.stop_translations(stop)?,
+                    ))
+                }),
+            )?;
+            Self::write_translations(
+                &metadata_db,
+                TRIP_TRANSLATION_TABLE,
+                in_memory_timetable.route_trips().iter().filter_map(|trip| {
+                    Some((
+                        trip.id() as u64,
+                        in_memory_timetable.trip_translations(trip)?,
+                    ))
+                }),
+            )?;
+            Self::write_translations(
+                &metadata_db,
+                ROUTE_TRANSLATION_TABLE,
+                in_memory_timetable.routes().iter().filter_map(|route| {
+                    Some((
+                        route.id() as u64,
+                        in_memory_timetable.route_translations(route)?,
+                    ))
+                }),
+            )?;
             {
                 let write = metadata_db.begin_write()?;
                 {
                     let mut table = write.open_table(ROUTE_SHAPE_TABLE)?;
                     for route in in_memory_timetable.routes() {
-                        let bytes = rmp_serde::to_vec(&in_memory_timetable.route_shape(route))?;
-                        table.insert(route.route_index as u64, bytes.as_slice())?;
+                        let bytes =
+                            rmp_serde::to_vec(&in_memory_timetable.route_shape(route).as_deref())?;
+                        table.insert(route.id() as u64, bytes.as_slice())?;
+                    }
+                    let mut table = write.open_table(ROUTE_SHAPE_INDEX_TABLE)?;
+                    for route in in_memory_timetable.routes() {
+                        if let Some(indices) = in_memory_timetable.route_shape_indices(route) {
+                            let bytes = rmp_serde::to_vec(indices.as_slice())?;
+                            table.insert(route.id() as u64, bytes.as_slice())?;
+                        }
                     }
                 }
                 write.commit()?;
             }
+            Manifest::describe(
+                base_path,
+                builder.build_params.clone(),
+                vec![builder.feed_manifest()],
+            )?
+            .write(base_path)?;
             info!("Done writing timetable");
         }
         MmapTimetable::open(base_path)
@@ -441,6 +897,17 @@ impl<'a> MmapTimetable<'a> {
         timetables: &[MmapTimetable<'b>],
         base_path: &PathBuf,
         valhalla_tile_path: &PathBuf,
+    ) -> MmapTimetable<'b> {
+        Self::concatenate_reusing_transfers(timetables, base_path, valhalla_tile_path, None).await
+    }
+
+    /// Concatenate timetables, copying transfers from `previous` for stops that are unaffected
+    /// by whatever changed since it was built.
+    pub async fn concatenate_reusing_transfers<'b>(
+        timetables: &[MmapTimetable<'b>],
+        base_path: &PathBuf,
+        valhalla_tile_path: &PathBuf,
+        previous: Option<&PreviousTransfers>,
     ) -> MmapTimetable<'b> {
         {
             let total_routes: usize = timetables.iter().map(|tt| tt.routes().len()).sum();
@@ -594,6 +1061,28 @@ impl<'a> MmapTimetable<'a> {
                 }
             }
             let metadata_db = Database::create(base_path.join("metadata.db")).unwrap();
+            {
+                let write = metadata_db.begin_write().unwrap();
+                {
+                    let mut table = write.open_table(FEED_SEGMENT_TABLE).unwrap();
+                    let mut cursor = 0usize;
+                    for (index, tt) in timetables.iter().enumerate() {
+                        let segment = FeedSegment {
+                            name: tt
+                                .base_path
+                                .file_name()
+                                .map(|name| name.to_string_lossy().to_string())
+                                .unwrap_or_default(),
+                            first_stop: cursor,
+                            stop_count: tt.stops().len(),
+                        };
+                        let bytes = rmp_serde::to_vec(&segment).unwrap();
+                        table.insert(index as u64, bytes.as_slice()).unwrap();
+                        cursor += tt.stops().len();
+                    }
+                }
+                write.commit().unwrap();
+            }
             {
                 let write = metadata_db.begin_write().unwrap();
                 {
@@ -620,7 +1109,7 @@ impl<'a> MmapTimetable<'a> {
                         for trip in tt.route_trips() {
                             let bytes = rmp_serde::to_vec(&tt.trip_metadata(trip)).unwrap();
                             table
-                                .insert((cursor + trip.trip_index) as u64, bytes.as_slice())
+                                .insert((cursor + trip.id()) as u64, bytes.as_slice())
                                 .unwrap();
                         }
                         cursor += tt.route_trips().len();
@@ -628,17 +1117,167 @@ impl<'a> MmapTimetable<'a> {
                 }
                 write.commit().unwrap();
             }
+            {
+                // GTFS trip IDs are only unique within a feed, so the same ID can map to trips from
+                // several feeds.
+                let mut gtfs_trip_index: HashMap<String, Vec<u64>> = HashMap::new();
+                let mut cursor = 0usize;
+                for tt in timetables {
+                    for trip in tt.route_trips() {
+                        if let Some(trip_id) = tt.trip_metadata(trip).trip_id {
+                            gtfs_trip_index
+                                .entry(trip_id)
+                                .or_default()
+                                .push((cursor + trip.id()) as u64);
+                        }
+                    }
+                    cursor += tt.route_trips().len();
+                }
+                Self::write_gtfs_trip_index(&metadata_db, &gtfs_trip_index).unwrap();
+            }
+            {
+                let mut global_ids = GlobalIds::default();
+                let (mut stop_cursor, mut route_cursor, mut trip_cursor) = (0usize, 0usize, 0usize);
+                for tt in timetables {
+                    Self::collect_global_ids(
+                        &mut global_ids,
+                        tt,
+                        stop_cursor,
+                        route_cursor,
+                        trip_cursor,
+                    );
+                    stop_cursor += tt.stops().len();
+                    route_cursor += tt.routes().len();
+                    trip_cursor += tt.route_trips().len();
+                }
+                Self::write_global_ids(&metadata_db, &global_ids).unwrap();
+            }
+            {
+                let write = metadata_db.begin_write().unwrap();
+                {
+                    let mut table = write.open_table(STOP_FARE_TABLE).unwrap();
+                    let mut cursor = 0usize;
+                    for tt in timetables {
+                        for stop in tt.stops() {
+                            if let Some(fare_info) = tt.stop_fare_info(stop) {
+                                let bytes = rmp_serde::to_vec(&fare_info).unwrap();
+                                table
+                                    .insert((cursor + stop.id()) as u64, bytes.as_slice())
+                                    .unwrap();
+                            }
+                        }
+                        cursor += tt.stops().len();
+                    }
+                }
+                write.commit().unwrap();
+            }
+            {
+                let mut fare_data = HashMap::new();
+                for tt in timetables {
+                    fare_data.extend(tt.all_fare_data().unwrap());
+                }
+                Self::write_fare_data(&metadata_db, &fare_data).unwrap();
+            }
+            {
+                let merged_stops = Self::merge_stops(timetables);
+                let write = metadata_db.begin_write().unwrap();
+                {
+                    let mut table = write.open_table(STOP_STATION_TABLE).unwrap();
+                    let mut cursor = 0usize;
+                    for tt in timetables {
+                        for stop in tt.stops() {
+                            let id = cursor + stop.id();
+                            let station_info = tt
+                                .stop_station_info(stop)
+                                .map(|station_info| station_info.offset_stops(cursor));
+                            let station_info = match merged_stops.get(&id) {
+                                Some(merged) => Some(Self::link_merged_stops(
+                                    station_info.unwrap_or_default(),
+                                    stop,
+                                    merged,
+                                )),
+                                None => station_info,
+                            };
+                            if let Some(station_info) = station_info {
+                                let bytes = rmp_serde::to_vec(&station_info).unwrap();
+                                table.insert(id as u64, bytes.as_slice()).unwrap();
+                            }
+                        }
+                        cursor += tt.stops().len();
+                    }
+                }
+                write.commit().unwrap();
+            }
+            {
+                let mut service_windows = HashMap::new();
+                for tt in timetables {
+                    service_windows.extend(tt.all_service_windows().unwrap());
+                }
+                Self::write_service_windows(&metadata_db, &service_windows).unwrap();
+            }
+            {
+                let mut calendar = ServiceCalendar::default();
+                for tt in timetables {
+                    calendar.append(tt.service_calendar(), tt.route_trips().len());
+                }
+                if calendar.has_patterns() {
+                    Self::write_service_calendar(&metadata_db, &calendar).unwrap();
+                }
+            }
+            {
+                let mut stop_translations = vec![];
+                let mut trip_translations = vec![];
+                let mut route_translations = vec![];
+                let (mut stop_cursor, mut trip_cursor, mut route_cursor) = (0usize, 0usize, 0usize);
+                for tt in timetables {
+                    for stop in tt.stops() {
+                        if let Some(translations) = tt.stop_translations(stop) {
+                            stop_translations
+                                .push(((stop_cursor + stop.id()) as u64, translations));
+                        }
+                    }
+                    for trip in tt.route_trips() {
+                        if let Some(translations) = tt.trip_translations(trip) {
+                            trip_translations
+                                .push(((trip_cursor + trip.id()) as u64, translations));
+                        }
+                    }
+                    for route in tt.routes() {
+                        if let Some(translations) = tt.route_translations(route) {
+                            route_translations
+                                .push(((route_cursor + route.id()) as u64, translations));
+                        }
+                    }
+                    stop_cursor += tt.stops().len();
+                    trip_cursor += tt.route_trips().len();
+                    route_cursor += tt.routes().len();
+                }
+                Self::write_translations(&metadata_db, STOP_TRANSLATION_TABLE, stop_translations)
+                    .unwrap();
+                Self::write_translations(&metadata_db, TRIP_TRANSLATION_TABLE, trip_translations)
+                    .unwrap();
+                Self::write_translations(&metadata_db, ROUTE_TRANSLATION_TABLE, route_translations)
+                    .unwrap();
+            }
             {
                 let write = metadata_db.begin_write().unwrap();
                 {
                     let mut table = write.open_table(ROUTE_SHAPE_TABLE).unwrap();
+                    let mut index_table = write.open_table(ROUTE_SHAPE_INDEX_TABLE).unwrap();
                     let mut cursor = 0usize;
                     for tt in timetables {
                         for route in tt.routes() {
-                            let bytes = rmp_serde::to_vec(&tt.route_shape(route)).unwrap();
+                            let bytes =
+                                rmp_serde::to_vec(&tt.route_shape(route).as_deref()).unwrap();
                             table
-                                .insert((cursor + route.route_index) as u64, bytes.as_slice())
+                                .insert((cursor + route.id()) as u64, bytes.as_slice())
                                 .unwrap();
+                            if let Some(indices) = tt.route_shape_indices(route) {
+                                let bytes = rmp_serde::to_vec(indices.as_slice()).unwrap();
+                                index_table
+                                    .insert((cursor + route.id()) as u64, bytes.as_slice())
+                                    .unwrap();
+                            }
                         }
                         cursor += tt.routes().len();
                     }
@@ -646,20 +1285,323 @@ impl<'a> MmapTimetable<'a> {
                 write.commit().unwrap();
             }
         }
+        {
+            // Every feed in a build shares its build parameters.
+            let build = timetables
+                .first()
+                .map(|tt| tt.manifest.build.clone())
+                .unwrap_or_default();
+            let feeds = timetables
+                .iter()
+                .flat_map(|tt| tt.manifest.feeds.iter().cloned())
+                .collect();
+            Manifest::describe(base_path, build, feeds)
+                .unwrap()
+                .write(base_path)
+                .unwrap();
+        }
         let mut tt = MmapTimetable::open(base_path).unwrap();
-        tt.calculate_transfers(valhalla_tile_path).await.unwrap();
+        tt.calculate_transfers(valhalla_tile_path, previous)
+            .await
+            .unwrap();
         tt
     }
 
+    // For every stop that another feed also describes, the concatenated indices of those stops.
+    fn merge_stops(timetables: &[MmapTimetable<'_>]) -> HashMap<usize, Vec<(usize, LatLng)>> {
+        let mut candidates = vec![];
+        let mut locations = vec![];
+        for (feed, tt) in timetables.iter().enumerate() {
+            for stop in tt.stops() {
+                let metadata = tt.stop_metadata(stop);
+                let location = stop.location();
+                candidates.push(MergeCandidate {
+                    feed,
+                    gtfs_id: metadata.id,
+                    code: metadata.code,
+                    name: metadata.name,
+                    lat: location.lat.deg(),
+                    lng: location.lng.deg(),
+                });
+                locations.push(location);
+            }
+        }
+        let pairs = merge_pairs(&candidates);
+        info!("Merged {} pairs of stops shared between feeds", pairs.len());
+        let mut merged_stops: HashMap<usize, Vec<(usize, LatLng)>> = HashMap::new();
+        for (id, other) in pairs {
+            merged_stops
+                .entry(id)
+                .or_default()
+                .push((other, locations[other]));
+            merged_stops
+                .entry(other)
+                .or_default()
+                .push((id, locations[id]));
+        }
+        merged_stops
+    }
+
+    // Link a stop to the other feeds' copies of it, with a transfer that only covers the distance
+    // between where each feed puts the stop.
+    fn link_merged_stops(
+        mut station_info: StopStationInfo,
+        stop: &Stop,
+        merged: &[(usize, LatLng)],
+    ) -> StopStationInfo {
+        for (other, location) in merged {
+            if station_info
+                .platform_transfers
+                .iter()
+                .any(|transfer| transfer.to == *other)
+            {
+                continue;
+            }
+            let meters = stop.location().distance(location).rad() * EARTH_RADIUS_APPROX;
+            station_info.platform_transfers.push(PlatformTransfer {
+                to: *other,
+                seconds: (meters * FAKE_WALK_SPEED_SECONDS_PER_METER) as u32,
+            });
+        }
+        station_info
+    }
+
+    fn global_indices(&self, table: TableDefinition<&str, &[u8]>, global_id: &str) -> Vec<u64> {
+        // Timetables built before stable IDs don't have this table.
+        let table = match self
+            .metadata_db
+            .begin_read()
+            .expect("Read failed")
+            .open_table(table)
+        {
+            Result::Ok(table) => table,
+            Err(_) => return vec![],
+        };
+
+        if let Some(bytes) = table.get(global_id).expect("DB error") {
+            rmp_serde::from_slice(bytes.value()).expect("Deserialization failed")
+        } else {
+            vec![]
+        }
+    }
+
+    // Collect the stable IDs of a timetable that starts at the given offsets in the one being
+    // written.
+    fn collect_global_ids<'b, T: Timetable<'b>>(
+        global_ids: &mut GlobalIds,
+        tt: &'b T,
+        stop_offset: usize,
+        route_offset: usize,
+        trip_offset: usize,
+    ) {
+        for stop in tt.stops() {
+            if let Some(global_id) = tt.stop_global_id(stop) {
+                global_ids
+                    .stops
+                    .push(((stop_offset + stop.id()) as u64, global_id));
+            }
+        }
+        for route in tt.routes() {
+            if let Some(global_id) = route.global_id(tt) {
+                global_ids
+                    .routes
+                    .entry(global_id)
+                    .or_default()
+                    .push((route_offset + route.id()) as u64);
+            }
+        }
+        for trip in tt.route_trips() {
+            if let Some(global_id) = tt.trip_metadata(trip).global_trip_id() {
+                global_ids
+                    .trips
+                    .entry(global_id)
+                    .or_default()
+                    .push((trip_offset + trip.id()) as u64);
+            }
+        }
+    }
+
+    fn write_global_ids(
+        metadata_db: &Database,
+        global_ids: &GlobalIds,
+    ) -> Result<(), anyhow::Error> {
+        let write = metadata_db.begin_write()?;
+        {
+            let mut stop_table = write.open_table(STOP_GLOBAL_ID_TABLE)?;
+            let mut index_table = write.open_table(GLOBAL_STOP_INDEX_TABLE)?;
+            for (stop_index, global_id) in &global_ids.stops {
+                stop_table.insert(*stop_index, global_id.as_str())?;
+                if index_table
+                    .insert(global_id.as_str(), *stop_index)?
+                    .is_some()
+                {
+                    warn!("Duplicate stable stop ID {}", global_id);
+                }
+            }
+        }
+        for (table, index) in [
+            (GLOBAL_ROUTE_INDEX_TABLE, &global_ids.routes),
+            (GLOBAL_TRIP_INDEX_TABLE, &global_ids.trips),
+        ] {
+            let mut table = write.open_table(table)?;
+            for (global_id, indices) in index {
+                let bytes = rmp_serde::to_vec(indices)?;
+                table.insert(global_id.as_str(), bytes.as_slice())?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn write_gtfs_trip_index(
+        metadata_db: &Database,
+        gtfs_trip_index: &HashMap<String, Vec<u64>>,
+    ) -> Result<(), anyhow::Error> {
+        let write = metadata_db.begin_write()?;
+        {
+            let mut table = write.open_table(GTFS_TRIP_INDEX_TABLE)?;
+            for (trip_id, trip_indices) in gtfs_trip_index {
+                let bytes = rmp_serde::to_vec(trip_indices)?;
+                table.insert(trip_id.as_str(), bytes.as_slice())?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn write_fare_data(
+        metadata_db: &Database,
+        fare_data: &HashMap<String, FareData>,
+    ) -> Result<(), anyhow::Error> {
+        let write = metadata_db.begin_write()?;
+        {
+            let mut table = write.open_table(FARE_DATA_TABLE)?;
+            for (feed_id, data) in fare_data {
+                let bytes = rmp_serde::to_vec(data)?;
+                table.insert(feed_id.as_str(), bytes.as_slice())?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn all_fare_data(&self) -> Result<HashMap<String, FareData>, anyhow::Error> {
+        let read = self.metadata_db.begin_read()?;
+        let table = if let Result::Ok(table) = read.open_table(FARE_DATA_TABLE) {
+            table
+        } else {
+            return Ok(HashMap::new());
+        };
+        let mut fare_data = HashMap::new();
+        for entry in table.iter()? {
+            let (feed_id, bytes) = entry?;
+            fare_data.insert(
+                feed_id.value().to_string(),
+                rmp_serde::from_slice(bytes.value())?,
+            );
+        }
+        Ok(fare_data)
+    }
+
+    fn write_translations(
+        metadata_db: &Database,
+        table: TableDefinition<u64, &[u8]>,
+        translations: impl IntoIterator<Item = (u64, Translations)>,
+    ) -> Result<(), anyhow::Error> {
+        let write = metadata_db.begin_write()?;
+        {
+            let mut table = write.open_table(table)?;
+            for (index, translations) in translations {
+                let bytes = rmp_serde::to_vec(&translations)?;
+                table.insert(index, bytes.as_slice())?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn write_service_windows(
+        metadata_db: &Database,
+        service_windows: &HashMap<String, ServiceWindow>,
+    ) -> Result<(), anyhow::Error> {
+        let combined = service_windows.values().copied().reduce(|a, b| a.union(&b));
+        let write = metadata_db.begin_write()?;
+        {
+            let mut table = write.open_table(SERVICE_WINDOW_TABLE)?;
+            for (feed_id, window) in service_windows {
+                let bytes = rmp_serde::to_vec(window)?;
+                table.insert(feed_id.as_str(), bytes.as_slice())?;
+            }
+            let mut table = write.open_table(COMBINED_SERVICE_WINDOW_TABLE)?;
+            if let Some(combined) = combined {
+                let bytes = rmp_serde::to_vec(&combined)?;
+                table.insert((), bytes.as_slice())?;
+            }
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn write_service_calendar(
+        metadata_db: &Database,
+        calendar: &ServiceCalendar,
+    ) -> Result<(), anyhow::Error> {
+        let write = metadata_db.begin_write()?;
+        {
+            let mut table = write.open_table(SERVICE_CALENDAR_TABLE)?;
+            let bytes = rmp_serde::to_vec(calendar)?;
+            table.insert((), bytes.as_slice())?;
+        }
+        write.commit()?;
+        Ok(())
+    }
+
+    fn read_service_calendar(
+        metadata_db: &Database,
+    ) -> Result<Option<ServiceCalendar>, anyhow::Error> {
+        let read = metadata_db.begin_read()?;
+        // Only pattern timetables have a calendar.
+        let table = if let Result::Ok(table) = read.open_table(SERVICE_CALENDAR_TABLE) {
+            table
+        } else {
+            return Ok(None);
+        };
+        let calendar = if let Some(bytes) = table.get(())? {
+            Some(rmp_serde::from_slice(bytes.value())?)
+        } else {
+            None
+        };
+        Ok(calendar)
+    }
+
+    fn all_service_windows(&self) -> Result<HashMap<String, ServiceWindow>, anyhow::Error> {
+        let read = self.metadata_db.begin_read()?;
+        let table = if let Result::Ok(table) = read.open_table(SERVICE_WINDOW_TABLE) {
+            table
+        } else {
+            return Ok(HashMap::new());
+        };
+        let mut service_windows = HashMap::new();
+        for entry in table.iter()? {
+            let (feed_id, bytes) = entry?;
+            service_windows.insert(
+                feed_id.value().to_string(),
+                rmp_serde::from_slice(bytes.value())?,
+            );
+        }
+        Ok(service_windows)
+    }
+
     pub(crate) async fn calculate_transfers(
         &mut self,
         valhalla_tile_path: &PathBuf,
+        previous: Option<&PreviousTransfers>,
     ) -> Result<(), Error> {
         {
             let mut rtree = RTree::<IndexedStop>::new();
 
             for (stop_id, stop) in self.stops().iter().enumerate() {
-                let latlng: LatLng = s2::cellid::CellID(stop.s2cell).into();
+                let latlng: LatLng = s2::cellid::CellID(stop.s2cell()).into();
                 let location_cartesian = lat_lng_to_cartesian(latlng.lat.deg(), latlng.lng.deg());
                 rtree.insert(IndexedStop {
                     coords: location_cartesian,
@@ -675,22 +1617,53 @@ impl<'a> MmapTimetable<'a> {
             TransferGraph::<FastGraphStatic, SphereIndexMmap<usize>>::read_from_dir(
                 valhalla_tile_path.clone(),
                 Arc::new(redb::Database::open(
-                    valhalla_tile_path.join("graph_metadata.db"),
+                    valhalla_tile_path.join(TRANSFER_METADATA_FILE),
                 )?),
             )?,
         );
 
+        let reuse = previous.and_then(|previous| match self.stop_keys() {
+            Result::Ok(keys) => {
+                let indices: HashMap<StopKey, usize> = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(index, key)| (key.clone(), index))
+                    .collect();
+                Some((previous, keys, indices))
+            }
+            Err(err) => {
+                warn!("Can't match stops to the previous timetable, recalculating all transfers: {err}");
+                None
+            }
+        });
+
         info!("Calculating transfer times");
+        let reused = AtomicUsize::new(0);
         let transfers: Vec<Vec<Transfer>> = self
             .stops()
             .par_iter()
             .map_with(
                 TransferGraphSearcher::new(transfer_graph.clone()),
                 |searcher, from_stop| {
+                    if let Some((previous, keys, indices)) = &reuse {
+                        if let Some(transfers) =
+                            self.reuse_transfers(previous, keys, indices, from_stop)
+                        {
+                            reused.fetch_add(1, Ordering::Relaxed);
+                            return transfers;
+                        }
+                    }
                     self.calculate_transfer_matrix(&transfer_graph, searcher, from_stop)
                 },
             )
             .collect();
+        if reuse.is_some() {
+            info!(
+                "Reused transfers for {} of {} stops",
+                reused.load(Ordering::Relaxed),
+                transfers.len()
+            );
+        }
 
         let transfer_index_file = File::options()
             .write(true)
@@ -698,7 +1671,7 @@ impl<'a> MmapTimetable<'a> {
             .create(true)
             .open(&self.base_path.join("transfer_index"))?;
         transfer_index_file
-            .set_len((size_of::<usize>() * transfers.len()) as u64)
+            .set_len((size_of::<U64Le>() * transfers.len()) as u64)
             .unwrap();
         let transfer_file = File::options()
             .write(true)
@@ -719,7 +1692,7 @@ impl<'a> MmapTimetable<'a> {
         let mut backing_transfers_mut = unsafe { MmapMut::map_mut(&transfer_file).unwrap() };
 
         let out_transfer_index = unsafe {
-            let s = cast_slice_mut::<u8, usize>(&mut backing_transfer_index_mut);
+            let s = cast_slice_mut::<u8, U64Le>(&mut backing_transfer_index_mut);
             slice::from_raw_parts_mut(s.as_mut_ptr(), s.len())
         };
         let out_transfers = unsafe {
@@ -729,33 +1702,124 @@ impl<'a> MmapTimetable<'a> {
 
         let mut total_transfers_processed = 0;
         for (transfer_chunk_idx, transfers) in transfers.iter().enumerate() {
-            out_transfer_index[transfer_chunk_idx] = total_transfers_processed;
+            out_transfer_index[transfer_chunk_idx] = U64Le::index(total_transfers_processed);
             for transfer in transfers {
                 out_transfers[total_transfers_processed] = *transfer;
                 total_transfers_processed += 1;
             }
         }
+        backing_transfer_index_mut.flush()?;
+        backing_transfers_mut.flush()?;
+        self.manifest = Manifest::describe(
+            &self.base_path,
+            self.manifest.build.clone(),
+            self.manifest.feeds.clone(),
+        )?;
+        self.manifest.write(&self.base_path)?;
         Ok(())
     }
 
-    fn generate_transfer_candidates(&self, stop: &Stop) -> Vec<&Stop> {
-        let latlng = stop.location();
-        let mut transfer_candidates = vec![];
-        for (count, (to_stop, dist_sq)) in self
-            .rtree
-            .nearest_neighbor_iter_with_distance_2(&lat_lng_to_cartesian(
-                latlng.lat.deg(),
-                latlng.lng.deg(),
-            ))
-            .enumerate()
-        {
-            let dist = dist_sq.sqrt();
-            if dist > 1000f64 || count > 20 {
-                break;
+    // The feed directory and GTFS ID of every stop, in stop order. Only concatenated timetables
+    // record which feed each stop came from.
+    pub(crate) fn stop_keys(&self) -> Result<Vec<StopKey>, Error> {
+        let read = self.metadata_db.begin_read()?;
+        let segments = read.open_table(FEED_SEGMENT_TABLE)?;
+        let mut keys = Vec::with_capacity(self.stops().len());
+        for entry in segments.iter()? {
+            let (_, bytes) = entry?;
+            let segment: FeedSegment = rmp_serde::from_slice(bytes.value())?;
+            for stop in self
+                .stops()
+                .get(segment.first_stop..segment.first_stop + segment.stop_count)
+                .ok_or_else(|| anyhow::anyhow!("Feed segment {} is out of range", segment.name))?
+            {
+                keys.push((segment.name.clone(), self.stop_metadata(stop).id));
             }
-            transfer_candidates.push(self.stop(to_stop.id));
         }
-        transfer_candidates
+        if keys.len() != self.stops().len() {
+            anyhow::bail!("Feed segments don't cover every stop");
+        }
+        Ok(keys)
+    }
+
+    /// Capture this timetable's transfers so they can be reused after it's rebuilt.
+    pub fn previous_transfers(&self) -> Result<PreviousTransfers, Error> {
+        let keys = self.stop_keys()?;
+        let stops = self
+            .stops()
+            .par_iter()
+            .map(|stop| {
+                let mut candidates: Vec<StopKey> = self
+                    .generate_transfer_candidates(stop)
+                    .iter()
+                    .map(|candidate| keys[candidate.id()].clone())
+                    .collect();
+                candidates.sort();
+                let transfers = self
+                    .transfers_from(stop.id())
+                    .iter()
+                    .map(|transfer| (keys[transfer.to_id()].clone(), transfer.time_seconds()))
+                    .collect();
+                (
+                    keys[stop.id()].clone(),
+                    PreviousStop {
+                        s2cell: stop.s2cell(),
+                        candidates,
+                        platform_transfers: self.platform_transfer_keys(stop, &keys),
+                        transfers,
+                    },
+                )
+            })
+            .collect();
+        Ok(PreviousTransfers { stops })
+    }
+
+    fn platform_transfer_keys(&self, stop: &Stop, keys: &[StopKey]) -> Vec<(StopKey, u32)> {
+        let mut transfers: Vec<(StopKey, u32)> = self
+            .stop_station_info(stop)
+            .map(|station_info| {
+                station_info
+                    .platform_transfers
+                    .iter()
+                    .map(|transfer| (keys[transfer.to].clone(), transfer.seconds))
+                    .collect()
+            })
+            .unwrap_or_default();
+        transfers.sort();
+        transfers
+    }
+
+    fn reuse_transfers(
+        &self,
+        previous: &PreviousTransfers,
+        keys: &[StopKey],
+        indices: &HashMap<StopKey, usize>,
+        stop: &Stop,
+    ) -> Option<Vec<Transfer>> {
+        let candidates: Vec<(StopKey, u64)> = self
+            .generate_transfer_candidates(stop)
+            .iter()
+            .map(|candidate| (keys[candidate.id()].clone(), candidate.s2cell()))
+            .collect();
+        previous.reuse(
+            stop.id(),
+            &keys[stop.id()],
+            stop.s2cell(),
+            &self.platform_transfer_keys(stop, keys),
+            &candidates,
+            indices,
+        )
+    }
+
+    fn generate_transfer_candidates(&self, stop: &Stop) -> Vec<&Stop> {
+        let latlng = stop.location();
+        transfer_candidate_ids(
+            &self.rtree,
+            &lat_lng_to_cartesian(latlng.lat.deg(), latlng.lng.deg()),
+        )
+        .into_iter()
+        .map(|id| self.stop(id))
+        .collect()
     }
 
     fn calculate_transfer_matrix<G: FastGraph, I: SphereIndex<usize>>(
@@ -765,9 +1829,23 @@ impl<'a> MmapTimetable<'a> {
         stop: &Stop,
     ) -> Vec<Transfer> {
         let transfer_candidates = self.generate_transfer_candidates(stop);
-        transfer_candidates
+        // Transfers within a station follow its pathways instead of the street network.
+        let platform_transfers: HashMap<usize, u32> = self
+            .stop_station_info(stop)
+            .map(|station_info| {
+                station_info
+                    .platform_transfers
+                    .iter()
+                    .map(|transfer| (transfer.to, transfer.seconds))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut transfers: Vec<Transfer> = transfer_candidates
             .iter()
             .filter_map(|to_stop| {
+                if let Some(seconds) = platform_transfers.get(&to_stop.id()) {
+                    return Some(Transfer::new(stop.id(), to_stop.id(), *seconds));
+                }
                 let transfer_time = graph
                     .transfer_distance_mm(
                         search_context,
@@ -776,13 +1854,16 @@ impl<'a> MmapTimetable<'a> {
                     )
                     .ok()?
                     / WALK_SPEED_MM_PER_SECOND;
-                Some(Transfer {
-                    to: to_stop.id(),
-                    from: stop.id(),
-                    time: transfer_time,
-                })
+                Some(Transfer::new(stop.id(), to_stop.id(), transfer_time as u32))
             })
-            .collect()
+            .collect();
+        // Platforms in big stations can be further apart than the candidate search reaches.
+        for (to, seconds) in platform_transfers {
+            if !transfers.iter().any(|transfer| transfer.to_id() == to) {
+                transfers.push(Transfer::new(stop.id(), to, seconds));
+            }
+        }
+        transfers
     }
 
     fn location_to_coords(location: &LatLng) -> Coord {
@@ -792,3 +1873,118 @@ impl<'a> MmapTimetable<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str) -> StopKey {
+        ("feed".to_string(), id.to_string())
+    }
+
+    fn rtree(locations: &[(f64, f64)]) -> RTree<IndexedStop> {
+        RTree::bulk_load(
+            locations
+                .iter()
+                .enumerate()
+                .map(|(id, (lat, lon))| IndexedStop {
+                    coords: lat_lng_to_cartesian(*lat, *lon),
+                    id,
+                })
+                .collect(),
+        )
+    }
+
+    fn candidates(
+        rtree: &RTree<IndexedStop>,
+        locations: &[(f64, f64)],
+        keys: &[StopKey],
+        cells: &[u64],
+        stop: usize,
+    ) -> Vec<(StopKey, u64)> {
+        let (lat, lon) = locations[stop];
+        transfer_candidate_ids(rtree, &lat_lng_to_cartesian(lat, lon))
+            .into_iter()
+            .map(|id| (keys[id].clone(), cells[id]))
+            .collect()
+    }
+
+    #[test]
+    fn moving_a_stop_only_recalculates_its_neighbours() {
+        // Three stops about 110m apart, and two more a couple of kilometres away.
+        let mut locations = vec![
+            (47.6, -122.300),
+            (47.6, -122.301),
+            (47.6, -122.302),
+            (47.6, -122.330),
+            (47.6, -122.331),
+        ];
+        let keys: Vec<StopKey> = ["a", "b", "c", "d", "e"].map(key).to_vec();
+        let mut cells: Vec<u64> = vec![1, 2, 3, 4, 5];
+        let indices: HashMap<StopKey, usize> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| (key.clone(), index))
+            .collect();
+
+        let before = rtree(&locations);
+        let previous = PreviousTransfers {
+            stops: (0..keys.len())
+                .map(|stop| {
+                    let stop_candidates = candidates(&before, &locations, &keys, &cells, stop);
+                    let mut candidate_keys: Vec<StopKey> =
+                        stop_candidates.iter().map(|(key, _)| key.clone()).collect();
+                    candidate_keys.sort();
+                    let transfers = stop_candidates
+                        .into_iter()
+                        .map(|(key, _)| (key, 60))
+                        .collect();
+                    (
+                        keys[stop].clone(),
+                        PreviousStop {
+                            s2cell: cells[stop],
+                            candidates: candidate_keys,
+                            platform_transfers: vec![],
+                            transfers,
+                        },
+                    )
+                })
+                .collect(),
+        };
+
+        // Move b a little further along the street.
+        locations[1] = (47.6, -122.3015);
+        cells[1] = 6;
+        let after = rtree(&locations);
+        let reused: Vec<bool> = (0..keys.len())
+            .map(|stop| {
+                previous
+                    .reuse(
+                        stop,
+                        &keys[stop],
+                        cells[stop],
+                        &[],
+                        &candidates(&after, &locations, &keys, &cells, stop),
+                        &indices,
+                    )
+                    .is_some()
+            })
+            .collect();
+        assert_eq!(reused, vec![false, false, false, true, true]);
+
+        let transfers = previous
+            .reuse(
+                3,
+                &keys[3],
+                cells[3],
+                &[],
+                &candidates(&after, &locations, &keys, &cells, 3),
+                &indices,
+            )
+            .unwrap();
+        assert_eq!(
+            transfers,
+            vec![Transfer::new(3, 3, 60), Transfer::new(3, 4, 60)]
+        );
+    }
+}
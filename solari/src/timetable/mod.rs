@@ -1,8 +1,26 @@
+pub mod boarding;
 pub mod build;
+pub mod bundle;
+pub mod calendar;
+pub mod check;
+pub mod convert;
+pub mod diff;
+pub mod gtfs_extra;
 pub mod in_memory;
+pub mod le;
+pub mod manifest;
+mod merge;
+pub mod metadata_cache;
 pub mod mmap;
+mod shape;
+pub mod station;
 
-use std::{time::UNIX_EPOCH, u32};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::UNIX_EPOCH,
+    u32,
+};
 
 use bytemuck::{Pod, Zeroable};
 use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime};
@@ -13,12 +31,48 @@ use rstar::RTree;
 use s2::latlng::LatLng;
 use serde::{Deserialize, Serialize};
 
-use crate::spatial::IndexedStop;
+use crate::{
+    fare::{FareData, StopFareInfo},
+    spatial::IndexedStop,
+};
+use calendar::ServiceCalendar;
+use le::{F32Le, U32Le, U64Le};
+use station::StopStationInfo;
 
 static DAY_SECONDS: u32 = 86_400;
 const STOP_METADATA_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("stop_metadata");
 const TRIP_METADATA_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("trip_metadata");
 const ROUTE_SHAPE_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("route_shapes");
+const ROUTE_SHAPE_INDEX_TABLE: TableDefinition<u64, &[u8]> =
+    TableDefinition::new("route_shape_indices");
+const GTFS_TRIP_INDEX_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("gtfs_trip_index");
+const STOP_FARE_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("stop_fares");
+const FARE_DATA_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("fare_data");
+const SERVICE_WINDOW_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("service_windows");
+const STOP_TRANSLATION_TABLE: TableDefinition<u64, &[u8]> =
+    TableDefinition::new("stop_translations");
+const TRIP_TRANSLATION_TABLE: TableDefinition<u64, &[u8]> =
+    TableDefinition::new("trip_translations");
+const ROUTE_TRANSLATION_TABLE: TableDefinition<u64, &[u8]> =
+    TableDefinition::new("route_translations");
+const STOP_STATION_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("stop_stations");
+const COMBINED_SERVICE_WINDOW_TABLE: TableDefinition<(), &[u8]> =
+    TableDefinition::new("combined_service_window");
+const SERVICE_CALENDAR_TABLE: TableDefinition<(), &[u8]> = TableDefinition::new("service_calendar");
+const FEED_SEGMENT_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("feed_segments");
+const STOP_GLOBAL_ID_TABLE: TableDefinition<u64, &str> = TableDefinition::new("stop_global_ids");
+const GLOBAL_STOP_INDEX_TABLE: TableDefinition<&str, u64> =
+    TableDefinition::new("global_stop_index");
+const GLOBAL_ROUTE_INDEX_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("global_route_index");
+const GLOBAL_TRIP_INDEX_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("global_trip_index");
+
+/// The stable identifier of a GTFS stop, route or trip, which unlike its index in the timetable
+/// doesn't change between builds.
+pub fn global_id(feed_id: &str, gtfs_id: &str) -> String {
+    format!("{feed_id}:{gtfs_id}")
+}
 
 pub trait Timetable<'a> {
     fn route(&'a self, route_id: usize) -> &'a Route;
@@ -32,85 +86,154 @@ pub trait Timetable<'a> {
     fn route_trips(&'a self) -> &'a [Trip];
     fn trip_stop_times(&'a self) -> &'a [TripStopTime];
     fn transfers(&'a self) -> &'a [Transfer];
-    fn transfer_index(&'a self) -> &'a [usize];
+    fn transfer_index(&'a self) -> &'a [U64Le];
     fn transfers_from(&'a self, stop_id: usize) -> &'a [Transfer];
     fn stop_index_copy(&'a self) -> RTree<IndexedStop>;
     fn nearest_stops(&'a self, lat: f64, lng: f64, n: usize) -> Vec<(&'a Stop, f64)>;
 
     fn stop_metadata(&'a self, stop: &Stop) -> gtfs_structures::Stop;
     fn trip_metadata(&'a self, trip: &Trip) -> TripMetadata;
-
-    fn route_shape(&'a self, route: &Route) -> Option<Vec<ShapeCoordinate>>;
+    /// Metadata for several stops at once, in order, which may be cheaper than looking each up.
+    fn stop_metadata_batch(&'a self, stops: &[&Stop]) -> Vec<gtfs_structures::Stop> {
+        stops.iter().map(|stop| self.stop_metadata(stop)).collect()
+    }
+    fn trip_metadata_batch(&'a self, trips: &[Trip]) -> Vec<TripMetadata> {
+        trips.iter().map(|trip| self.trip_metadata(trip)).collect()
+    }
+    fn trips_by_gtfs_id(&'a self, gtfs_trip_id: &str) -> Vec<&'a Trip>;
+    fn stop_global_id(&'a self, stop: &Stop) -> Option<String>;
+    fn stop_by_global_id(&'a self, global_stop_id: &str) -> Option<&'a Stop>;
+    fn routes_by_global_id(&'a self, global_route_id: &str) -> Vec<&'a Route>;
+    fn trips_by_global_id(&'a self, global_trip_id: &str) -> Vec<&'a Trip>;
+    fn stop_fare_info(&'a self, stop: &Stop) -> Option<StopFareInfo>;
+    fn fare_data(&'a self, feed_id: &str) -> Option<FareData>;
+    fn stop_station_info(&'a self, stop: &Stop) -> Option<StopStationInfo>;
+    fn stop_translations(&'a self, stop: &Stop) -> Option<Translations>;
+    fn trip_translations(&'a self, trip: &Trip) -> Option<Translations>;
+    fn route_translations(&'a self, route: &Route) -> Option<Translations>;
+    fn service_window(&'a self) -> Option<ServiceWindow>;
+    fn feed_service_windows(&'a self) -> HashMap<String, ServiceWindow>;
+    fn service_calendar(&'a self) -> Option<&'a ServiceCalendar>;
+
+    fn route_shape(&'a self, route: &Route) -> Option<Arc<Vec<ShapeCoordinate>>>;
+    /// For each stop of the route, the index of the segment of its shape the stop lies on. None
+    /// for timetables built before these were precomputed.
+    fn route_shape_indices(&'a self, route: &Route) -> Option<Arc<Vec<u32>>>;
 }
 
+// The structs below are the elements of the memory-mapped timetable files. Every field is a
+// little-endian integer and the structs have no padding, so the files are the same on every host.
+// Layouts are given as byte offsets.
+
+/// 16 bytes: `stop_index` u32 @0, `first_stop_route_index` u32 @4, `s2cell` u64 @8.
 #[derive(
     Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Pod, Zeroable, Serialize, Deserialize,
 )]
 #[repr(C)]
 pub struct Stop {
-    stop_index: usize,
-    s2cell: u64,
-    first_stop_route_index: usize,
+    stop_index: U32Le,
+    first_stop_route_index: U32Le,
+    s2cell: U64Le,
 }
 
 impl<'a> Stop {
     pub fn stop_routes(&self, timetable: &'a dyn Timetable<'a>) -> &'a [StopRoute] {
-        let range_end = if self.stop_index == timetable.stops().len() - 1 {
+        let range_end = if self.id() == timetable.stops().len() - 1 {
             timetable.stop_routes().len()
         } else {
-            timetable.stops()[self.stop_index + 1].first_stop_route_index
+            timetable.stops()[self.id() + 1]
+                .first_stop_route_index
+                .usize()
         };
-        &timetable.stop_routes()[self.first_stop_route_index..range_end]
+        &timetable.stop_routes()[self.first_stop_route_index.usize()..range_end]
+    }
+
+    pub(crate) fn new(stop_index: usize, s2cell: u64, first_stop_route_index: usize) -> Stop {
+        Stop {
+            stop_index: U32Le::index(stop_index),
+            first_stop_route_index: U32Le::index(first_stop_route_index),
+            s2cell: U64Le::new(s2cell),
+        }
     }
 
     #[inline]
     pub fn id(&self) -> usize {
-        self.stop_index
+        self.stop_index.usize()
+    }
+
+    #[inline]
+    pub fn s2cell(&self) -> u64 {
+        self.s2cell.get()
     }
 
     #[inline]
     pub fn location(&self) -> LatLng {
-        s2::cellid::CellID(self.s2cell).into()
+        s2::cellid::CellID(self.s2cell()).into()
     }
 
     pub fn metadata(&self, timetable: &'a dyn Timetable<'a>) -> gtfs_structures::Stop {
         timetable.stop_metadata(self).clone()
     }
+
+    pub fn global_id(&self, timetable: &'a dyn Timetable<'a>) -> Option<String> {
+        timetable.stop_global_id(self)
+    }
 }
 
+/// 16 bytes: `route_index` u32 @0, `stop_index` u32 @4, `stop_seq` u32 @8,
+/// `distance_along_route` f32 @12.
 #[derive(Debug, Clone, PartialEq, PartialOrd, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct RouteStop {
-    route_index: usize,
-    stop_index: usize,
-    stop_seq: u32,
-    distance_along_route: f32,
+    route_index: U32Le,
+    stop_index: U32Le,
+    stop_seq: U32Le,
+    distance_along_route: F32Le,
 }
 
 impl<'a> RouteStop {
+    pub(crate) fn new(
+        route_index: usize,
+        stop_index: usize,
+        stop_seq: usize,
+        distance_along_route: f32,
+    ) -> RouteStop {
+        RouteStop {
+            route_index: U32Le::index(route_index),
+            stop_index: U32Le::index(stop_index),
+            stop_seq: U32Le::index(stop_seq),
+            distance_along_route: F32Le::new(distance_along_route),
+        }
+    }
+
     #[inline]
     pub fn route(&self, timetable: &'a dyn Timetable<'a>) -> &'a Route {
-        &timetable.routes()[self.route_index]
+        &timetable.routes()[self.route_index.usize()]
+    }
+
+    #[inline]
+    pub fn route_id(&self) -> usize {
+        self.route_index.usize()
     }
 
     #[inline]
     pub fn stop_seq(&self) -> usize {
-        self.stop_seq as usize
+        self.stop_seq.usize()
     }
 
     #[inline]
     pub fn id(&self) -> usize {
-        self.stop_index
+        self.stop_index.usize()
     }
 
     #[inline]
     pub fn stop(&self, timetable: &'a dyn Timetable<'a>) -> &'a Stop {
-        &timetable.stops()[self.stop_index]
+        &timetable.stops()[self.id()]
     }
 
     #[inline]
     pub fn distance_along_route(&self) -> f32 {
-        self.distance_along_route
+        self.distance_along_route.get()
     }
 }
 
@@ -118,29 +241,88 @@ impl<'a> RouteStop {
 #[repr(C)]
 pub struct StopId(pub usize);
 
+/// 32 bytes: `trip_index` u32 @0, `route_index` u32 @4, `first_trip_stop_time` u64 @8,
+/// `last_trip_stop_time` u64 @16, `service_day_start` u32 @24, 4 bytes of zero padding @28.
 #[derive(
     Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Pod, Zeroable, Serialize, Deserialize,
 )]
 #[repr(C)]
 pub struct Trip {
-    trip_index: usize,
-    route_index: usize,
-    first_trip_stop_time: usize,
-    last_trip_stop_time: usize,
+    trip_index: U32Le,
+    route_index: U32Le,
+    first_trip_stop_time: U64Le,
+    last_trip_stop_time: U64Le,
+    // Zero for stored trips. Pattern trips that have been resolved to a service day carry the
+    // epoch time that day starts at, which their stop times are relative to.
+    service_day_start: U32Le,
+    _padding: U32Le,
 }
 
 impl<'a> Trip {
+    pub(crate) fn new(
+        trip_index: usize,
+        route_index: usize,
+        first_trip_stop_time: usize,
+        last_trip_stop_time: usize,
+    ) -> Trip {
+        Trip {
+            trip_index: U32Le::index(trip_index),
+            route_index: U32Le::index(route_index),
+            first_trip_stop_time: U64Le::index(first_trip_stop_time),
+            last_trip_stop_time: U64Le::index(last_trip_stop_time),
+            service_day_start: U32Le::new(0),
+            _padding: U32Le::new(0),
+        }
+    }
+
+    /// The trip's stop times as stored. For pattern trips these are relative to the service day.
     pub fn stop_times(&self, timetable: &'a dyn Timetable<'a>) -> &'a [TripStopTime] {
-        &timetable.trip_stop_times()[self.first_trip_stop_time..self.last_trip_stop_time]
+        &timetable.trip_stop_times()
+            [self.first_trip_stop_time.usize()..self.last_trip_stop_time.usize()]
+    }
+
+    /// The stop time at `stop_seq`, on the service day the trip was resolved to.
+    #[inline]
+    pub fn stop_time(&self, timetable: &'a dyn Timetable<'a>, stop_seq: usize) -> TripStopTime {
+        timetable.trip_stop_times()[self.first_trip_stop_time.usize() + stop_seq]
+            .on_service_day(self.service_day_start())
+    }
+
+    #[inline]
+    pub fn service_day_start(&self) -> u32 {
+        self.service_day_start.get()
+    }
+
+    pub(crate) fn on_service_day(&self, service_day_start: u32) -> Trip {
+        Trip {
+            service_day_start: U32Le::new(service_day_start),
+            ..*self
+        }
     }
 
     #[inline]
     pub fn route(&self, timetable: &'a dyn Timetable<'a>) -> Route {
-        timetable.routes()[self.route_index].clone()
+        timetable.routes()[self.route_id()].clone()
+    }
+
+    #[inline]
+    pub fn route_id(&self) -> usize {
+        self.route_index.usize()
     }
 
     pub fn metadata(&self, timetable: &'a dyn Timetable<'a>) -> TripMetadata {
-        timetable.trip_metadata(self).clone()
+        let mut metadata = timetable.trip_metadata(self).clone();
+        if metadata.service_date.is_none() {
+            metadata.service_date = timetable
+                .service_calendar()
+                .and_then(|calendar| calendar.service_date(self));
+        }
+        metadata
+    }
+
+    #[inline]
+    pub fn id(&self) -> usize {
+        self.trip_index.usize()
     }
 }
 
@@ -149,146 +331,412 @@ pub struct TripMetadata {
     pub headsign: Option<String>,
     pub route_name: Option<String>,
     pub agency_name: Option<String>,
+    #[serde(default)]
+    pub trip_id: Option<String>,
+    #[serde(default)]
+    pub service_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub route_id: Option<String>,
+    #[serde(default)]
+    pub feed_id: Option<String>,
+    #[serde(default)]
+    pub route_long_name: Option<String>,
+    // Hex RGB without a leading '#', as in routes.txt.
+    #[serde(default)]
+    pub route_color: Option<String>,
+    #[serde(default)]
+    pub route_text_color: Option<String>,
+    #[serde(default)]
+    pub route_type: Option<i16>,
 }
 
+impl TripMetadata {
+    pub fn global_trip_id(&self) -> Option<String> {
+        Some(global_id(self.feed_id.as_ref()?, self.trip_id.as_ref()?))
+    }
+
+    pub fn global_route_id(&self) -> Option<String> {
+        Some(global_id(self.feed_id.as_ref()?, self.route_id.as_ref()?))
+    }
+}
+
+/// Translated values for a record's fields from translations.txt, keyed by field name and then
+/// by IETF language tag.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Translations(pub BTreeMap<String, BTreeMap<String, String>>);
+
+impl Translations {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn insert(&mut self, field_name: &str, language: &str, translation: &str) {
+        self.0
+            .entry(field_name.to_string())
+            .or_default()
+            .insert(language.to_lowercase(), translation.to_string());
+    }
+
+    /// Find the translation for the first of `languages` (in order of preference) that has one,
+    /// falling back from a regional tag like "fr-CA" to its primary language.
+    pub fn get(&self, field_name: &str, languages: &[String]) -> Option<&str> {
+        let field = self.0.get(field_name)?;
+        languages.iter().find_map(|language| {
+            let language = language.to_lowercase();
+            field
+                .get(&language)
+                .or_else(|| {
+                    language
+                        .split_once('-')
+                        .and_then(|(primary, _)| field.get(primary))
+                })
+                .map(|translation| translation.as_str())
+        })
+    }
+}
+
+/// The range of service days a timetable was built for, along with the epoch times at which the
+/// first service day starts and the last one ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ServiceWindow {
+    pub start_date: NaiveDate,
+    pub num_days: u16,
+    pub valid_from: u32,
+    pub valid_until: u32,
+}
+
+impl ServiceWindow {
+    pub fn union(&self, other: &ServiceWindow) -> ServiceWindow {
+        let start_date = self.start_date.min(other.start_date);
+        let end_date = self.end_date().max(other.end_date());
+        ServiceWindow {
+            start_date,
+            num_days: (end_date - start_date).num_days() as u16,
+            valid_from: self.valid_from.min(other.valid_from),
+            valid_until: self.valid_until.max(other.valid_until),
+        }
+    }
+
+    // Service days run from `start_date` through `start_date + num_days`, inclusive.
+    fn end_date(&self) -> NaiveDate {
+        self.start_date
+            .checked_add_days(Days::new(self.num_days as u64))
+            .unwrap_or(self.start_date)
+    }
+}
+
+/// 12 bytes: `route_index` u32 @0, `first_route_stop` u32 @4, `first_route_trip` u32 @8.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct Route {
-    route_index: usize,
-    first_route_stop: usize,
-    pub(crate) first_route_trip: usize,
+    route_index: U32Le,
+    first_route_stop: U32Le,
+    first_route_trip: U32Le,
 }
 
 impl<'a> Route {
+    pub(crate) fn new(
+        route_index: usize,
+        first_route_stop: usize,
+        first_route_trip: usize,
+    ) -> Route {
+        Route {
+            route_index: U32Le::index(route_index),
+            first_route_stop: U32Le::index(first_route_stop),
+            first_route_trip: U32Le::index(first_route_trip),
+        }
+    }
+
     pub fn route_stops(&self, timetable: &'a dyn Timetable<'a>) -> &'a [RouteStop] {
-        let range_end = if self.route_index == timetable.routes().len() - 1 {
+        let range_end = if self.id() == timetable.routes().len() - 1 {
             timetable.route_stops().len()
         } else {
-            timetable.routes()[self.route_index + 1].first_route_stop
+            timetable.routes()[self.id() + 1].first_route_stop()
         };
-        &timetable.route_stops()[self.first_route_stop..range_end]
+        &timetable.route_stops()[self.first_route_stop()..range_end]
     }
 
     pub fn route_trips(&self, timetable: &'a dyn Timetable<'a>) -> &'a [Trip] {
-        let range_end = if self.route_index == timetable.routes().len() - 1 {
+        let range_end = if self.id() == timetable.routes().len() - 1 {
             timetable.route_trips().len()
         } else {
-            timetable.routes()[self.route_index + 1].first_route_trip
+            timetable.routes()[self.id() + 1].first_route_trip()
         };
-        &timetable.route_trips()[self.first_route_trip..range_end]
+        &timetable.route_trips()[self.first_route_trip()..range_end]
     }
 
     #[inline]
     pub fn id(&self) -> usize {
-        self.route_index
+        self.route_index.usize()
+    }
+
+    #[inline]
+    pub(crate) fn first_route_stop(&self) -> usize {
+        self.first_route_stop.usize()
+    }
+
+    #[inline]
+    pub(crate) fn first_route_trip(&self) -> usize {
+        self.first_route_trip.usize()
+    }
+
+    // Every trip of a route shares its GTFS route.
+    pub fn global_id(&self, timetable: &'a dyn Timetable<'a>) -> Option<String> {
+        self.route_trips(timetable)
+            .first()?
+            .metadata(timetable)
+            .global_route_id()
     }
 }
 
+/// 8 bytes: `route_index` u32 @0, `stop_seq` u32 @4.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct StopRoute {
-    route_index: usize,
-    stop_seq: usize,
+    route_index: U32Le,
+    stop_seq: U32Le,
 }
 
 impl<'a> StopRoute {
+    pub(crate) fn new(route_index: usize, stop_seq: usize) -> StopRoute {
+        StopRoute {
+            route_index: U32Le::index(route_index),
+            stop_seq: U32Le::index(stop_seq),
+        }
+    }
+
     #[inline]
     pub fn route(&'a self, timetable: &'a dyn Timetable<'a>) -> &'a Route {
-        &timetable.routes()[self.route_index]
+        &timetable.routes()[self.route_id()]
     }
 
     #[inline]
     pub fn route_id(&self) -> usize {
-        self.route_index
+        self.route_index.usize()
     }
 
     #[inline]
     pub fn stop_seq(&self) -> usize {
-        self.stop_seq
+        self.stop_seq.usize()
     }
 }
 
+/// 20 bytes: `trip_index` u32 @0, `route_stop_seq` u32 @4, `arrival_time` u32 @8,
+/// `departure_time` u32 @12, `boarding` u32 @16.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Pod, Zeroable)]
 #[repr(C)]
 pub struct TripStopTime {
-    pub(crate) trip_index: usize,
-    pub(crate) route_stop_seq: usize,
-    arrival_time: u32,
-    departure_time: u32,
+    trip_index: U32Le,
+    route_stop_seq: U32Le,
+    arrival_time: U32Le,
+    departure_time: U32Le,
+    // Packed pickup_type, drop_off_type, continuous_pickup and continuous_drop_off codes from
+    // stop_times.txt, two bits each, followed by the CONTINUOUS_*_BEFORE bits.
+    boarding: U32Le,
 }
 
+const PICKUP_TYPE_SHIFT: u32 = 0;
+const DROP_OFF_TYPE_SHIFT: u32 = 2;
+const CONTINUOUS_PICKUP_SHIFT: u32 = 4;
+const CONTINUOUS_DROP_OFF_SHIFT: u32 = 6;
+// Set when the previous stop time allows continuous pickup or drop-off, meaning the segment
+// leading up to this stop can be boarded or alighted from anywhere.
+const CONTINUOUS_PICKUP_BEFORE: u32 = 1 << 8;
+const CONTINUOUS_DROP_OFF_BEFORE: u32 = 1 << 9;
+// GTFS codes for "no pickup/drop-off available" and "continuous stopping".
+const NOT_AVAILABLE: u8 = 1;
+const CONTINUOUS: u8 = 0;
+
 impl<'a> TripStopTime {
     #[inline]
     pub fn arrival(&self) -> Time {
         Time {
-            epoch_seconds: self.arrival_time,
+            epoch_seconds: self.arrival_time.get(),
         }
     }
 
     #[inline]
     pub fn departure(&self) -> Time {
         Time {
-            epoch_seconds: self.departure_time,
+            epoch_seconds: self.departure_time.get(),
         }
     }
 
+    /// The pickup_type code from stop_times.txt.
+    #[inline]
+    pub fn pickup_type(&self) -> u8 {
+        ((self.boarding.get() >> PICKUP_TYPE_SHIFT) & 0b11) as u8
+    }
+
+    /// The drop_off_type code from stop_times.txt.
+    #[inline]
+    pub fn drop_off_type(&self) -> u8 {
+        ((self.boarding.get() >> DROP_OFF_TYPE_SHIFT) & 0b11) as u8
+    }
+
+    #[inline]
+    pub fn continuous_pickup(&self) -> u8 {
+        ((self.boarding.get() >> CONTINUOUS_PICKUP_SHIFT) & 0b11) as u8
+    }
+
+    #[inline]
+    pub fn continuous_drop_off(&self) -> u8 {
+        ((self.boarding.get() >> CONTINUOUS_DROP_OFF_SHIFT) & 0b11) as u8
+    }
+
+    /// Whether the segment leading up to this stop allows continuous pickup, per the previous stop
+    /// time's continuous_pickup.
+    #[inline]
+    pub fn continuous_pickup_before(&self) -> bool {
+        self.boarding.get() & CONTINUOUS_PICKUP_BEFORE != 0
+    }
+
+    /// Whether the segment leading up to this stop allows continuous drop-off, per the previous
+    /// stop time's continuous_drop_off.
+    #[inline]
+    pub fn continuous_drop_off_before(&self) -> bool {
+        self.boarding.get() & CONTINUOUS_DROP_OFF_BEFORE != 0
+    }
+
+    // Stops that require phoning ahead or flagging down the driver still count as boardable.
+    // Continuous pickup only applies between stops, so it doesn't override pickup_type here.
+    #[inline]
+    pub fn can_board(&self) -> bool {
+        self.pickup_type() != NOT_AVAILABLE
+    }
+
+    #[inline]
+    pub fn can_alight(&self) -> bool {
+        self.drop_off_type() != NOT_AVAILABLE
+    }
+
     pub(crate) fn new(
         trip_index: usize,
         route_stop_seq: usize,
         arrival_time: DateTime<Tz>,
         departure_time: DateTime<Tz>,
+        boarding: u32,
     ) -> TripStopTime {
         TripStopTime {
-            trip_index,
-            route_stop_seq,
-            arrival_time: arrival_time.timestamp() as u32,
-            departure_time: departure_time.timestamp() as u32,
+            trip_index: U32Le::index(trip_index),
+            route_stop_seq: U32Le::index(route_stop_seq),
+            arrival_time: U32Le::new(arrival_time.timestamp() as u32),
+            departure_time: U32Le::new(departure_time.timestamp() as u32),
+            boarding: U32Le::new(boarding),
+        }
+    }
+
+    /// Pack the pickup and drop-off codes for a stop time. `previous` holds the continuous_pickup
+    /// and continuous_drop_off codes of the stop time before it on the trip, if any.
+    pub(crate) fn boarding_flags(
+        pickup_type: u8,
+        drop_off_type: u8,
+        continuous_pickup: u8,
+        continuous_drop_off: u8,
+        previous: Option<(u8, u8)>,
+    ) -> u32 {
+        let mut flags = (pickup_type as u32 & 0b11) << PICKUP_TYPE_SHIFT
+            | (drop_off_type as u32 & 0b11) << DROP_OFF_TYPE_SHIFT
+            | (continuous_pickup as u32 & 0b11) << CONTINUOUS_PICKUP_SHIFT
+            | (continuous_drop_off as u32 & 0b11) << CONTINUOUS_DROP_OFF_SHIFT;
+        if let Some((previous_pickup, previous_drop_off)) = previous {
+            if previous_pickup == CONTINUOUS {
+                flags |= CONTINUOUS_PICKUP_BEFORE;
+            }
+            if previous_drop_off == CONTINUOUS {
+                flags |= CONTINUOUS_DROP_OFF_BEFORE;
+            }
+        }
+        flags
+    }
+
+    pub(crate) fn on_service_day(&self, service_day_start: u32) -> TripStopTime {
+        TripStopTime {
+            arrival_time: U32Le::new(self.arrival_time.get().saturating_add(service_day_start)),
+            departure_time: U32Le::new(self.departure_time.get().saturating_add(service_day_start)),
+            ..*self
         }
     }
 
     pub(crate) fn marked() -> TripStopTime {
         TripStopTime {
-            trip_index: usize::MAX,
-            route_stop_seq: usize::MAX,
-            arrival_time: u32::MAX,
-            departure_time: u32::MAX,
+            trip_index: U32Le::new(u32::MAX),
+            route_stop_seq: U32Le::new(u32::MAX),
+            arrival_time: U32Le::new(u32::MAX),
+            departure_time: U32Le::new(u32::MAX),
+            boarding: U32Le::new(0),
         }
     }
 
+    #[inline]
+    pub fn trip_id(&self) -> usize {
+        self.trip_index.usize()
+    }
+
+    #[inline]
+    pub(crate) fn is_marked(&self) -> bool {
+        self.trip_index.get() == u32::MAX
+    }
+
+    #[inline]
+    pub fn route_stop_seq(&self) -> usize {
+        self.route_stop_seq.usize()
+    }
+
     #[inline]
     pub fn route_stop(&self, timetable: &'a dyn Timetable<'a>) -> &'a RouteStop {
-        let route = &timetable.route_trips()[self.trip_index].route(timetable);
-        &timetable.route_stops()[route.first_route_stop + self.route_stop_seq]
+        let route = &timetable.route_trips()[self.trip_id()].route(timetable);
+        &timetable.route_stops()[route.first_route_stop() + self.route_stop_seq()]
     }
 }
 
+/// 12 bytes: `to` u32 @0, `from` u32 @4, `time` u32 @8. The `transfer_index` file alongside the
+/// transfers holds the u64 index of each stop's first transfer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Pod, Zeroable)]
 #[repr(C)]
 pub struct Transfer {
-    to: usize,
-    from: usize,
-    time: u64,
+    to: U32Le,
+    from: U32Le,
+    time: U32Le,
 }
 
 impl<'a> Transfer {
+    pub(crate) fn new(from: usize, to: usize, time_seconds: u32) -> Transfer {
+        Transfer {
+            to: U32Le::index(to),
+            from: U32Le::index(from),
+            time: U32Le::new(time_seconds),
+        }
+    }
+
     pub fn all_transfers(from: &Stop, timetable: &'a dyn Timetable<'a>) -> &'a [Transfer] {
-        let from = from.stop_index;
+        let from = from.id();
         let range_end = if from == timetable.transfer_index().len() - 1 {
             timetable.transfers().len()
         } else {
-            timetable.transfer_index()[from + 1]
+            timetable.transfer_index()[from + 1].usize()
         };
-        &timetable.transfers()[timetable.transfer_index()[from]..range_end]
+        &timetable.transfers()[timetable.transfer_index()[from].usize()..range_end]
     }
 
     #[inline]
     pub fn to(&self, timetable: &'a dyn Timetable<'a>) -> &'a Stop {
-        timetable.stop(self.to)
+        timetable.stop(self.to_id())
+    }
+
+    #[inline]
+    pub fn to_id(&self) -> usize {
+        self.to.usize()
+    }
+
+    #[inline]
+    pub fn from_id(&self) -> usize {
+        self.from.usize()
     }
 
     #[inline]
     pub fn time_seconds(&self) -> u32 {
-        self.time as u32
+        self.time.get()
     }
 }
 
@@ -362,9 +810,56 @@ impl ShapeCoordinate {
 
 #[cfg(test)]
 mod test {
-    use chrono::{NaiveDate, NaiveTime};
+    use chrono::{NaiveDate, NaiveTime, TimeZone};
+
+    use super::{Time, TripStopTime};
+
+    fn stop_time(boarding: u32) -> TripStopTime {
+        let time = chrono_tz::UTC
+            .with_ymd_and_hms(2024, 3, 1, 12, 0, 0)
+            .unwrap();
+        TripStopTime::new(0, 1, time, time, boarding)
+    }
 
-    use super::Time;
+    #[test]
+    fn pickup_and_drop_off_types() {
+        let regular = stop_time(TripStopTime::boarding_flags(0, 0, 1, 1, None));
+        assert!(regular.can_board() && regular.can_alight());
+
+        // Phone ahead and coordinate with driver.
+        let on_request = stop_time(TripStopTime::boarding_flags(2, 3, 1, 1, None));
+        assert!(on_request.can_board() && on_request.can_alight());
+
+        let drop_off_only = stop_time(TripStopTime::boarding_flags(1, 0, 1, 1, None));
+        assert!(!drop_off_only.can_board() && drop_off_only.can_alight());
+
+        let pickup_only = stop_time(TripStopTime::boarding_flags(0, 1, 1, 1, None));
+        assert!(pickup_only.can_board() && !pickup_only.can_alight());
+    }
+
+    #[test]
+    fn continuous_stopping_before_a_stop() {
+        // Continuous stopping on the way here is recorded on this stop time, which keeps its own
+        // pickup and drop-off types.
+        let after_continuous = stop_time(TripStopTime::boarding_flags(0, 0, 1, 1, Some((0, 0))));
+        assert!(after_continuous.continuous_pickup_before());
+        assert!(after_continuous.continuous_drop_off_before());
+        assert!(after_continuous.can_board() && after_continuous.can_alight());
+        assert_eq!(after_continuous.continuous_pickup(), 1);
+        assert_eq!(after_continuous.continuous_drop_off(), 1);
+
+        let after_continuous_pickup =
+            stop_time(TripStopTime::boarding_flags(0, 0, 1, 1, Some((0, 1))));
+        assert!(after_continuous_pickup.continuous_pickup_before());
+        assert!(!after_continuous_pickup.continuous_drop_off_before());
+
+        // A stop time's own continuous codes only apply to the segment after it.
+        let before_continuous = stop_time(TripStopTime::boarding_flags(0, 0, 0, 0, Some((1, 1))));
+        assert!(!before_continuous.continuous_pickup_before());
+        assert!(!before_continuous.continuous_drop_off_before());
+        let first = stop_time(TripStopTime::boarding_flags(0, 0, 0, 0, None));
+        assert!(!first.continuous_pickup_before() && !first.continuous_drop_off_before());
+    }
 
     #[test]
     fn time_with_24hr_service_day() {
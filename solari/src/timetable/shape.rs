@@ -0,0 +1,121 @@
+use super::ShapeCoordinate;
+
+// Planar coordinates for comparing distances over short spans, with longitude scaled by the
+// cosine of the latitude so that both axes are roughly in the same units.
+fn planar(lat: f64, lon: f64) -> (f64, f64) {
+    (lon * lat.to_radians().cos(), lat)
+}
+
+fn squared_distance_to_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0f64 {
+        0f64
+    } else {
+        (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0f64, 1f64)
+    };
+    let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+    (point.0 - x).powi(2) + (point.1 - y).powi(2)
+}
+
+/// A shape that runs straight from each stop to the next, for routes whose feed has no shapes.
+pub(crate) fn straight_line_shape(stops: &[(f64, f64)]) -> Vec<ShapeCoordinate> {
+    stops
+        .iter()
+        .map(|(lat, lon)| ShapeCoordinate {
+            lat: *lat,
+            lon: *lon,
+            distance_along_shape: None,
+        })
+        .collect()
+}
+
+/// For each stop of a route, given as its location and shape_dist_traveled (NaN if the feed
+/// doesn't have one), the index of the shape segment the stop lies on, i.e. the segment from
+/// `shape[index]` to `shape[index + 1]`. The indices never decrease along the route, so the
+/// geometry between two stops is the slice of the shape between their segments. None if the shape
+/// has fewer than two points.
+pub(crate) fn stop_shape_indices(
+    shape: &[ShapeCoordinate],
+    stops: &[(f64, f64, f32)],
+) -> Option<Vec<u32>> {
+    let last_segment = shape.len().checked_sub(2)?;
+    let shape_has_distances = shape.iter().all(|coord| {
+        coord
+            .distance_along_shape
+            .is_some_and(|dist| !dist.is_nan())
+    });
+    let points: Vec<(f64, f64)> = shape
+        .iter()
+        .map(|coord| planar(coord.lat, coord.lon))
+        .collect();
+
+    let mut start = 0usize;
+    let mut indices = Vec::with_capacity(stops.len());
+    for (lat, lon, distance) in stops {
+        let index = if shape_has_distances && !distance.is_nan() {
+            // The last point at or before the stop's distance along the shape.
+            let after = shape[start..].partition_point(|coord| {
+                coord.distance_along_shape.unwrap_or_default() <= *distance
+            });
+            (start + after).saturating_sub(1)
+        } else {
+            // Project onto the closest segment that doesn't come before the previous stop's.
+            let point = planar(*lat, *lon);
+            (start..=last_segment)
+                .map(|segment| {
+                    (
+                        segment,
+                        squared_distance_to_segment(point, points[segment], points[segment + 1]),
+                    )
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(segment, _)| segment)
+                .unwrap_or(start)
+        };
+        let index = index.clamp(start, last_segment);
+        indices.push(index as u32);
+        start = index;
+    }
+    Some(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coord(lat: f64, lon: f64, distance_along_shape: Option<f32>) -> ShapeCoordinate {
+        ShapeCoordinate {
+            lat,
+            lon,
+            distance_along_shape,
+        }
+    }
+
+    #[test]
+    fn indexes_stops_by_distance_and_by_projection() {
+        let shape = vec![
+            coord(0.0, 0.0, Some(0.0)),
+            coord(0.0, 0.001, Some(100.0)),
+            coord(0.0, 0.002, Some(200.0)),
+            coord(0.0, 0.003, Some(300.0)),
+        ];
+        let stops = [(0.0, 0.0, 0.0), (0.0, 0.0015, 150.0), (0.0, 0.003, 300.0)];
+        assert_eq!(stop_shape_indices(&shape, &stops), Some(vec![0, 1, 2]));
+
+        let stops = [
+            (0.0001, 0.0, f32::NAN),
+            (0.0001, 0.0025, f32::NAN),
+            (0.0, 0.003, f32::NAN),
+        ];
+        assert_eq!(stop_shape_indices(&shape, &stops), Some(vec![0, 2, 2]));
+
+        let line = straight_line_shape(&[(0.0, 0.0), (0.0, 0.001), (0.0, 0.002)]);
+        let stops = [
+            (0.0, 0.0, f32::NAN),
+            (0.0, 0.001, f32::NAN),
+            (0.0, 0.002, f32::NAN),
+        ];
+        assert_eq!(stop_shape_indices(&line, &stops), Some(vec![0, 0, 1]));
+    }
+}
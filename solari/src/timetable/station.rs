@@ -0,0 +1,253 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use gtfs_structures::{Gtfs, LocationType};
+use s2::latlng::LatLng;
+use serde::{Deserialize, Serialize};
+use solari_geomath::EARTH_RADIUS_APPROX;
+
+use crate::spatial::FAKE_WALK_SPEED_SECONDS_PER_METER;
+
+use super::{
+    gtfs_extra::{GtfsExtra, PathwayRecord},
+    in_memory::stop_location,
+};
+
+// pathway_mode values from pathways.txt.
+const PATHWAY_MODE_STAIRS: u8 = 2;
+const PATHWAY_MODE_ESCALATOR: u8 = 4;
+const PATHWAY_MODE_ELEVATOR: u8 = 5;
+const PATHWAY_MODE_FARE_GATE: u8 = 6;
+const PATHWAY_MODE_EXIT_GATE: u8 = 7;
+
+const PATHWAY_WALK_SPEED_METERS_PER_SECOND: f64 = 1.4;
+const SECONDS_PER_STAIR: u32 = 1;
+// Includes waiting for the car, which usually dominates the ride itself.
+const ELEVATOR_SECONDS: u32 = 60;
+const GATE_SECONDS: u32 = 10;
+const DEFAULT_PATHWAY_SECONDS: u32 = 30;
+
+/// How a timetable stop fits into its parent station.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct StopStationInfo {
+    pub station_id: String,
+    pub entrances: Vec<StationEntrance>,
+    pub platform_transfers: Vec<PlatformTransfer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StationEntrance {
+    pub gtfs_id: String,
+    pub lat: f64,
+    pub lon: f64,
+    // Time to get between the entrance and the stop.
+    pub seconds: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlatformTransfer {
+    pub to: usize,
+    pub seconds: u32,
+}
+
+impl StopStationInfo {
+    pub(crate) fn offset_stops(mut self, offset: usize) -> StopStationInfo {
+        for transfer in &mut self.platform_transfers {
+            transfer.to += offset;
+        }
+        self
+    }
+}
+
+pub(crate) fn pathway_seconds(pathway: &PathwayRecord) -> u32 {
+    if let Some(traversal_time) = pathway.traversal_time {
+        return traversal_time;
+    }
+    match pathway.pathway_mode {
+        PATHWAY_MODE_ELEVATOR => ELEVATOR_SECONDS,
+        PATHWAY_MODE_FARE_GATE | PATHWAY_MODE_EXIT_GATE => GATE_SECONDS,
+        PATHWAY_MODE_STAIRS | PATHWAY_MODE_ESCALATOR if pathway.stair_count.is_some() => {
+            pathway.stair_count.unwrap().unsigned_abs() * SECONDS_PER_STAIR
+        }
+        _ => pathway
+            .length
+            .map(|length| (length / PATHWAY_WALK_SPEED_METERS_PER_SECOND) as u32)
+            .unwrap_or(DEFAULT_PATHWAY_SECONDS),
+    }
+}
+
+/// The pathways in a feed as a directed graph between GTFS location IDs.
+pub(crate) struct PathwayGraph<'a> {
+    edges: HashMap<&'a str, Vec<(&'a str, u32)>>,
+}
+
+impl<'a> PathwayGraph<'a> {
+    pub(crate) fn new(pathways: &'a [PathwayRecord]) -> PathwayGraph<'a> {
+        let mut edges: HashMap<&str, Vec<(&str, u32)>> = HashMap::new();
+        for pathway in pathways {
+            let seconds = pathway_seconds(pathway);
+            edges
+                .entry(&pathway.from_stop_id)
+                .or_default()
+                .push((&pathway.to_stop_id, seconds));
+            if pathway.is_bidirectional == 1 {
+                edges
+                    .entry(&pathway.to_stop_id)
+                    .or_default()
+                    .push((&pathway.from_stop_id, seconds));
+            }
+        }
+        PathwayGraph { edges }
+    }
+
+    pub(crate) fn contains(&self, location_id: &str) -> bool {
+        self.edges.contains_key(location_id)
+    }
+
+    /// Shortest traversal time from `from` to every location reachable through pathways.
+    pub(crate) fn travel_times(&self, from: &'a str) -> HashMap<&'a str, u32> {
+        let mut best: HashMap<&str, u32> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        best.insert(from, 0);
+        queue.push(Reverse((0u32, from)));
+        while let Some(Reverse((seconds, location))) = queue.pop() {
+            if best.get(location).is_some_and(|best| *best < seconds) {
+                continue;
+            }
+            for (next, edge_seconds) in self.edges.get(location).into_iter().flatten() {
+                let next_seconds = seconds + edge_seconds;
+                if best.get(next).is_none_or(|best| next_seconds < *best) {
+                    best.insert(next, next_seconds);
+                    queue.push(Reverse((next_seconds, next)));
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Station info for every timetable stop with a parent station, keyed by GTFS stop ID.
+/// `stop_indices` maps the GTFS IDs of timetable stops to their index in the timetable.
+pub(crate) fn station_info(
+    gtfs: &Gtfs,
+    extra: &GtfsExtra,
+    stop_indices: &HashMap<String, usize>,
+) -> HashMap<String, StopStationInfo> {
+    let mut platforms: HashMap<&str, Vec<&str>> = HashMap::new();
+    for gtfs_id in stop_indices.keys() {
+        if let Some(station_id) = gtfs
+            .stops
+            .get(gtfs_id)
+            .and_then(|stop| stop.parent_station.as_deref())
+        {
+            platforms.entry(station_id).or_default().push(gtfs_id);
+        }
+    }
+    let mut entrances: HashMap<&str, Vec<&gtfs_structures::Stop>> = HashMap::new();
+    for stop in gtfs.stops.values() {
+        if stop.location_type != LocationType::StationEntrance {
+            continue;
+        }
+        if let Some(station_id) = stop.parent_station.as_deref() {
+            entrances.entry(station_id).or_default().push(stop.as_ref());
+        }
+    }
+
+    let graph = PathwayGraph::new(&extra.pathways);
+    let mut station_info = HashMap::new();
+    for (station_id, station_platforms) in &platforms {
+        let station_entrances = entrances.get(station_id).cloned().unwrap_or_default();
+        for platform in station_platforms {
+            let times = if graph.contains(platform) {
+                graph.travel_times(platform)
+            } else {
+                HashMap::new()
+            };
+            let platform_transfers = station_platforms
+                .iter()
+                .filter(|other| *other != platform)
+                .filter_map(|other| {
+                    Some(PlatformTransfer {
+                        to: stop_indices[*other],
+                        seconds: *times.get(other)?,
+                    })
+                })
+                .collect();
+            let entrances = station_entrances
+                .iter()
+                .filter_map(|entrance| {
+                    let (lat, lon) = (entrance.latitude?, entrance.longitude?);
+                    let seconds = if let Some(seconds) = times.get(entrance.id.as_str()) {
+                        *seconds
+                    } else if times.is_empty() {
+                        // Without pathways, walk straight from the entrance to the platform.
+                        let (platform_lat, platform_lng) =
+                            stop_location(gtfs, &gtfs.stops[*platform])?;
+                        let platform_location = LatLng::from_degrees(platform_lat, platform_lng);
+                        (LatLng::from_degrees(lat, lon)
+                            .distance(&platform_location)
+                            .rad()
+                            * EARTH_RADIUS_APPROX
+                            * FAKE_WALK_SPEED_SECONDS_PER_METER) as u32
+                    } else {
+                        // The pathways don't connect this entrance to the platform.
+                        return None;
+                    };
+                    Some(StationEntrance {
+                        gtfs_id: entrance.id.clone(),
+                        lat,
+                        lon,
+                        seconds,
+                    })
+                })
+                .collect();
+            station_info.insert(
+                platform.to_string(),
+                StopStationInfo {
+                    station_id: station_id.to_string(),
+                    entrances,
+                    platform_transfers,
+                },
+            );
+        }
+    }
+    station_info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pathway(from: &str, to: &str, mode: u8, bidirectional: bool) -> PathwayRecord {
+        PathwayRecord {
+            pathway_id: format!("{from}-{to}"),
+            from_stop_id: from.to_string(),
+            to_stop_id: to.to_string(),
+            pathway_mode: mode,
+            is_bidirectional: bidirectional as u8,
+            length: Some(70.0),
+            traversal_time: None,
+            stair_count: None,
+        }
+    }
+
+    #[test]
+    fn travel_times_follow_pathway_directions() {
+        let pathways = vec![
+            pathway("entrance", "mezzanine", 1, true),
+            pathway("mezzanine", "platform_a", PATHWAY_MODE_ELEVATOR, true),
+            pathway("mezzanine", "platform_b", 1, false),
+        ];
+        let graph = PathwayGraph::new(&pathways);
+
+        let from_entrance = graph.travel_times("entrance");
+        assert_eq!(from_entrance["platform_a"], 50 + ELEVATOR_SECONDS);
+        assert_eq!(from_entrance["platform_b"], 100);
+
+        // platform_b is only reachable one way.
+        let from_b = graph.travel_times("platform_b");
+        assert!(!from_b.contains_key("entrance"));
+    }
+}
@@ -0,0 +1,14 @@
+
+
+2.0<
+stale"3
+
+
+stale-trip20250604
+>B(B
+staleJ
+	unmatched"=
+
+unscheduled-trip20250604
+>BѢ(B
+	unmatched
\ No newline at end of file